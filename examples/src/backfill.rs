@@ -0,0 +1,223 @@
+//! Fill gaps of trajectories by past-frame meta of the tracker
+//!
+//! Trackers with `enable-past-frame=1` report positions of shadow-tracked objects
+//! in later batches. Frames are held back by `window` frames per source, so an
+//! object reported for a held frame is inserted into it before the frame is written.
+//! Objects reported for frames already written are dropped.
+use std::collections::{HashMap, VecDeque};
+use std::io;
+
+use crate::exporter::Exporter;
+use crate::{FrameObjects, Record, SourceState};
+
+/// Exporter inserting past-frame objects into frames before the inner exporter
+pub struct BackfillExporter {
+    inner: Box<dyn Exporter>,
+    window: usize,
+    frames: HashMap<u32, VecDeque<FrameObjects>>,
+}
+
+impl BackfillExporter {
+    pub fn new(inner: Box<dyn Exporter>, window: u32) -> Self {
+        Self {
+            inner,
+            window: window as usize,
+            frames: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, f: &FrameObjects) -> io::Result<()> {
+        let source_id = f.frame().source_id();
+        let held = self.frames.entry(source_id).or_default();
+        for p in f.past_objects() {
+            let frame = match held
+                .iter_mut()
+                .rev()
+                .find(|h| h.frame().frame_num() == p.frame_num)
+            {
+                Some(frame) => frame,
+                None => continue,
+            };
+            if frame.objects.iter().any(|o| o.object_id == p.object_id) {
+                continue;
+            }
+            let index = frame.objects.len() as u32;
+            frame.objects.push(p.to_object(index));
+        }
+        let mut f = f.clone();
+        f.past_objects.clear();
+        held.push_back(f);
+
+        let overflow = held.len().saturating_sub(self.window);
+        let ready = held.drain(..overflow).collect::<Vec<_>>();
+        self.write_frames(ready)
+    }
+
+    fn write_frames(&mut self, frames: impl IntoIterator<Item = FrameObjects>) -> io::Result<()> {
+        for f in frames {
+            self.inner.write_record(&Record::Frame(f))?;
+        }
+        Ok(())
+    }
+
+    /// Write held frames of the source
+    fn flush_source(&mut self, source_id: u32) -> io::Result<()> {
+        match self.frames.remove(&source_id) {
+            Some(held) => self.write_frames(held),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Exporter for BackfillExporter {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        match record {
+            Record::Frame(f) => self.push(f),
+            Record::Source(e) => {
+                if e.state == SourceState::Removed {
+                    self.flush_source(e.source_id)?;
+                }
+                self.inner.write_record(record)
+            }
+            Record::Track(_) | Record::Latency(_) | Record::Audio(_) => {
+                self.inner.write_record(record)
+            }
+        }
+    }
+
+    fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut sources = self.frames.keys().copied().collect::<Vec<_>>();
+        sources.sort_unstable();
+        for source_id in sources {
+            self.flush_source(source_id)?;
+        }
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{frame, object, Collect};
+    use crate::{BBoxCorrds, PastObject, SourceEvent};
+
+    fn past(frame_num: i32, object_id: u64) -> PastObject {
+        PastObject {
+            frame_num,
+            object_id,
+            class_id: 2,
+            label: "person".to_owned(),
+            confidence: 0.3,
+            bbox: BBoxCorrds {
+                left: 1.0,
+                top: 2.0,
+                width: 3.0,
+                height: 4.0,
+            },
+        }
+    }
+
+    fn frame_with_past(source_id: u32, frame_num: i32, past_objects: Vec<PastObject>) -> Record {
+        Record::Frame(frame(source_id, frame_num, vec![]).with_past_objects(past_objects))
+    }
+
+    /// `source_id/frame_num [object_id,..]` of frames with `*` on backfilled objects
+    fn summary(records: &[Record]) -> Vec<String> {
+        records
+            .iter()
+            .map(|r| match r {
+                Record::Frame(f) => {
+                    assert!(f.past_objects().is_empty());
+                    let objects = f
+                        .objects()
+                        .iter()
+                        .map(|o| format!("{}{}", o.object_id, if o.backfilled { "*" } else { "" }))
+                        .collect::<Vec<_>>();
+                    format!(
+                        "{}/{} [{}]",
+                        f.frame().source_id(),
+                        f.frame().frame_num(),
+                        objects.join(",")
+                    )
+                }
+                Record::Source(e) => format!("source {}", e.source_id),
+                _ => "other".to_owned(),
+            })
+            .collect()
+    }
+
+    fn export(window: u32, records: &[Record]) -> Vec<String> {
+        let (inner, written) = Collect::boxed();
+        let mut e = BackfillExporter::new(inner, window);
+        for r in records {
+            e.write_record(r).unwrap();
+        }
+        e.finish().unwrap();
+        let written = written.lock().unwrap();
+        summary(&written)
+    }
+
+    #[test]
+    fn objects_are_inserted_into_held_frames() {
+        let (inner, written) = Collect::boxed();
+        let mut e = BackfillExporter::new(inner, 2);
+        let records = [
+            Record::Frame(frame(0, 0, vec![object(0, 1, [0.; 4], 0.9)])),
+            Record::Frame(frame(0, 1, vec![object(0, 2, [0.; 4], 0.9)])),
+            frame_with_past(0, 2, vec![past(1, 1)]),
+        ];
+        for r in records.iter() {
+            e.write_record(r).unwrap();
+        }
+        // frames are held back by the window
+        assert_eq!(summary(&written.lock().unwrap()), ["0/0 [1]"]);
+        e.finish().unwrap();
+        let written = written.lock().unwrap();
+        assert_eq!(summary(&written), ["0/0 [1]", "0/1 [2,1*]", "0/2 []"]);
+
+        let o = match &written[1] {
+            Record::Frame(f) => &f.objects()[1],
+            r => panic!("unexpected {:?}", r),
+        };
+        assert_eq!(o.detection_index, 1);
+        assert_eq!(
+            (o.class_id, o.label.as_str(), o.confidence),
+            (2, "person", 0.3)
+        );
+        // the past-frame bbox is of the tracker
+        assert!(o.detector_bbox_info.is_empty());
+        assert_eq!(o.bbox().height, 4.0);
+    }
+
+    #[test]
+    fn written_frames_and_known_objects_are_not_backfilled() {
+        let records = [
+            Record::Frame(frame(0, 0, vec![])),
+            Record::Frame(frame(0, 1, vec![object(0, 5, [0.; 4], 0.9)])),
+            // frame 0 is written before frame 2 arrives, frame 1 has object 5
+            frame_with_past(0, 2, vec![past(0, 5), past(1, 5), past(1, 6), past(1, 6)]),
+        ];
+        assert_eq!(export(1, &records), ["0/0 []", "0/1 [5,6*]", "0/2 []"]);
+        assert_eq!(export(0, &records), ["0/0 []", "0/1 [5]", "0/2 []"]);
+    }
+
+    #[test]
+    fn removed_sources_are_flushed_before_their_event() {
+        let records = [
+            Record::Frame(frame(0, 0, vec![])),
+            Record::Frame(frame(1, 0, vec![])),
+            Record::Frame(frame(0, 1, vec![])),
+            Record::Source(SourceEvent::new(0, SourceState::Removed, "file:///a.mp4")),
+            frame_with_past(1, 1, vec![past(0, 3)]),
+            Record::Source(SourceEvent::new(2, SourceState::Added, "file:///b.mp4")),
+        ];
+        assert_eq!(
+            export(3, &records),
+            ["0/0 []", "0/1 []", "source 0", "source 2", "1/0 [3*]", "1/1 []"]
+        );
+    }
+}
@@ -2,100 +2,322 @@
 //!
 //! and Use to check the operation of nvdsmeta-sys.
 use std::path::PathBuf;
-use std::sync::mpsc::{channel, Sender};
-
-use anyhow::Error;
-
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Error};
+
+use examples::export::{Broadcast, DropPolicy, ExportWorker};
+use examples::exporter::ExportFormat;
+use examples::labels::{LabelExporter, LabelMapper};
+use examples::latency::LatencyEvent;
+use examples::msgconv::{MsgConv, MsgConvExporter};
+use examples::publish::{self, Endpoint, Publisher};
+use examples::timestamp::{Arrival, FrameTimestamps, NtpMode, TimestampPolicy};
 use examples::ObjectMeta;
-use examples::{BufferFrameInfo, FrameObjects};
+use examples::{AudioFrame, BufferFrameInfo, FrameObjects, PastObject, Record, SegmentationStats};
+use nvdsmeta_sys::latency::LatencyReporter;
+use nvdsmeta_sys::FrameMeta;
 use structopt::StructOpt;
 
-use gst::element_error;
 use gst::prelude::*;
+use gst::{element_error, element_warning};
 
-fn create_source(s: &Source, pipeline: &gst::Pipeline) -> Result<gst::Element, Error> {
-    match s {
-        Source::ImageFile { location } => {
-            let src = gst::ElementFactory::make("filesrc").build()?;
-            let dec = gst::ElementFactory::make("jpegdec").build()?;
-            let vidconv = gst::ElementFactory::make("videoconvert").build()?;
+mod config;
+mod source;
+use config::AppConfig;
+use source::{Control, Source, SourceManager};
 
-            src.set_property("location", location);
-            pipeline.add_many(&[&src, &dec, &vidconv])?;
-            gst::Element::link_many(&[&src, &dec, &vidconv])?;
-            Ok(vidconv)
-        }
-        Source::VideoFile {
-            location,
-            num_buffers,
-        } => {
-            let src = gst::ElementFactory::make("filesrc").build()?;
-            let parse = gst::ElementFactory::make("h264parse").build()?;
-            let dec = gst::ElementFactory::make("nvv4l2decoder").build()?;
+/// Element properties take paths as utf-8 strings
+fn path_str(p: &std::path::Path) -> Result<&str, Error> {
+    p.to_str()
+        .ok_or_else(|| anyhow!("path is not valid utf-8: {}", p.display()))
+}
 
-            src.set_property("location", location);
+fn create_tracker(
+    tracker: &config::TrackerConfig,
+    past_frame: bool,
+) -> Result<gst::Element, Error> {
+    let nvtracker = gst::ElementFactory::make("nvtracker").build()?;
+    nvtracker.set_property("ll-lib-file", path_str(&tracker.ll_lib_file)?);
+    if let Some(p) = tracker.ll_config_file.as_ref() {
+        nvtracker.set_property("ll-config-file", path_str(p)?);
+    }
+    nvtracker.set_property("tracker-width", tracker.width);
+    nvtracker.set_property("tracker-height", tracker.height);
+    if past_frame {
+        nvtracker.set_property("enable-past-frame", true);
+    }
+    Ok(nvtracker)
+}
 
-            src.set_property("num-buffers", num_buffers);
+fn create_infer_audio(
+    gie: &config::GieConfig,
+    audio: &config::AudioConfig,
+) -> Result<gst::Element, Error> {
+    let nvinferaudio = gst::ElementFactory::make("nvinferaudio").build()?;
+    nvinferaudio.set_property("config-file-path", path_str(&gie.config_file)?);
+    nvinferaudio.set_property("audio-transform", &audio.transform);
+    nvinferaudio.set_property("audio-framesize", audio.frame_size);
+    nvinferaudio.set_property("audio-hopsize", audio.hop_size);
+    Ok(nvinferaudio)
+}
 
-            pipeline.add_many(&[&src, &parse, &dec])?;
-            gst::Element::link_many(&[&src, &parse, &dec])?;
-            Ok(dec)
+fn create_msgbroker(broker: &config::BrokerConfig) -> Result<gst::Element, Error> {
+    let nvmsgbroker = gst::ElementFactory::make("nvmsgbroker").build()?;
+    nvmsgbroker.set_property("proto-lib", path_str(&broker.proto_lib)?);
+    nvmsgbroker.set_property("conn-str", &broker.conn_str);
+    if let Some(topic) = broker.topic.as_ref() {
+        nvmsgbroker.set_property("topic", topic);
+    }
+    if let Some(p) = broker.config.as_ref() {
+        nvmsgbroker.set_property("config", path_str(p)?);
+    }
+    nvmsgbroker.set_property("sync", false);
+    Ok(nvmsgbroker)
+}
+
+/// Attach nvmsgconv payloads of the frame records as NVDS_PAYLOAD_META
+fn attach_payloads(
+    buffer: &mut gst::BufferRef,
+    records: &[Record],
+    msgconv: &MsgConv,
+    component_id: u32,
+) -> Result<(), examples::Error> {
+    let mut meta = nvdsmeta_sys::NvDsMeta::from_buffer_mut(buffer)?;
+    let batch_meta = meta.batch_meta_mut()?;
+    for record in records.iter() {
+        let f = match record {
+            Record::Frame(f) => f,
+            _ => continue,
+        };
+        for payload in msgconv.payloads(f) {
+            let payload = serde_json::to_vec(&payload)?;
+            batch_meta.add_payload_meta(f.frame().source_id(), &payload, component_id)?;
         }
-        Source::V4l2Src {
-            device,
-            num_buffers,
-            width,
-            height,
-        } => {
-            let src = gst::ElementFactory::make("v4l2src").build()?;
-            let vidconv = gst::ElementFactory::make("videoconvert").build()?;
+    }
+    Ok(())
+}
 
-            src.set_property("device", device);
-            src.set_property("num-buffers", num_buffers);
+/// Build records at the tee and attach their payloads before nvmsgbroker branches
+///
+/// Records are sent to the exporters from here instead of the appsink, so they are
+/// built once and arrival times are taken at the tee.
+fn add_payload_probe(
+    tee: &gst::Element,
+    msgconv: &config::MsgConvSection,
+    sender: Arc<RecordSender>,
+) -> Result<(), Error> {
+    let pad = tee
+        .static_pad("sink")
+        .ok_or_else(|| anyhow!("{} has no sink pad", tee.name()))?;
+    let component_id = msgconv.component_id;
+    let msgconv = msgconv.load_msgconv()?;
+    // the pad owns the probe, so a strong ref would keep the tee alive
+    let tee = tee.downgrade();
+    // a shared buffer is likely to be shared for the whole stream
+    let warned_shared = AtomicBool::new(false);
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        let tee = match tee.upgrade() {
+            Some(tee) => tee,
+            None => return gst::PadProbeReturn::Ok,
+        };
+        if let Some(gst::PadProbeData::Buffer(ref mut buffer)) = info.data {
+            let arrival = Arrival::now(tee.current_running_time().map(|t| t.nseconds()));
+            let records = match sender.records(&tee, buffer, &arrival) {
+                Ok(Some(records)) => records,
+                // errors are posted to the bus
+                Ok(None) | Err(_) => return gst::PadProbeReturn::Ok,
+            };
+            // elements before the tee push buffers they own, so this does not copy the
+            // buffer and its batch meta as make_mut would for a shared one
+            match buffer.get_mut() {
+                Some(buffer) => {
+                    if let Err(e) = attach_payloads(buffer, &records, &msgconv, component_id) {
+                        log::warn!("failed to attach payload meta: {}", e);
+                    }
+                }
+                None => {
+                    if !warned_shared.swap(true, Ordering::Relaxed) {
+                        log::warn!(
+                            "buffer is shared, payload meta is not attached (logged only once)"
+                        );
+                    }
+                }
+            }
+            // closed exporters are reported by the appsink callback
+            let _ = sender.send(records);
+        }
+        gst::PadProbeReturn::Ok
+    });
+    Ok(())
+}
 
-            let caps = gst::Caps::builder("video/x-raw")
-                .field("width", width)
-                .field("height", height)
-                .build();
+/// Build records of batched buffers and send them to the exporters
+struct RecordSender {
+    ts_config: config::TimestampConfig,
+    mapper: Option<LabelMapper>,
+    sender: Broadcast,
+    /// set when all exporters are stopped
+    closed: AtomicBool,
+}
 
-            pipeline.add_many(&[&src, &vidconv])?;
-            src.link_filtered(&vidconv, &caps)?;
+impl RecordSender {
+    /// Records of the buffer, `None` for buffers to skip
+    fn records(
+        &self,
+        element: &gst::Element,
+        buffer: &gst::BufferRef,
+        arrival: &Arrival,
+    ) -> Result<Option<Vec<Record>>, gst::FlowError> {
+        match frame_records(buffer, arrival, &self.ts_config) {
+            Ok(records) => Ok(Some(records)),
+            Err(examples::Error::Meta(nvdsmeta_sys::Error::NoMeta)) => {
+                // buffers before nvstreammux caps negotiation may have no meta
+                element_warning!(
+                    element,
+                    gst::StreamError::Failed,
+                    ("No NvDsMeta found on the buffer, skip it")
+                );
+                Ok(None)
+            }
+            Err(e) => {
+                element_error!(
+                    element,
+                    gst::StreamError::Failed,
+                    ("Failed to read NvDsMeta: {}", e)
+                );
+                Err(gst::FlowError::Error)
+            }
+        }
+    }
 
-            Ok(vidconv)
+    fn send(&self, records: Vec<Record>) -> Result<(), gst::FlowError> {
+        for mut record in records {
+            if let Some(mapper) = self.mapper.as_ref() {
+                mapper.apply_record(&mut record);
+            }
+            if self.sender.send(record).is_err() {
+                self.closed.store(true, Ordering::SeqCst);
+                return Err(gst::FlowError::Eos);
+            }
         }
+        Ok(())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
     }
 }
 
-fn create_pipeline(opt: &Opt, sender: Sender<FrameObjects>) -> Result<gst::Pipeline, Error> {
+fn create_pipeline(
+    config: &AppConfig,
+    sender: Broadcast,
+) -> Result<(gst::Pipeline, SourceManager), Error> {
     gst::init()?;
 
     let pipeline = gst::Pipeline::new(None);
-    let srcbin = create_source(&opt.source, &pipeline)?;
 
-    let nvvidconv = gst::ElementFactory::make("nvvideoconvert").build()?;
     let nvstreammux = gst::ElementFactory::make("nvstreammux").build()?;
-    let nvinfer = gst::ElementFactory::make("nvinfer").build()?;
     let appsink = gst::ElementFactory::make("appsink").build()?;
 
-    nvstreammux.set_property("batch-size", 1u32);
-    nvstreammux.set_property("width", 1280u32);
-    nvstreammux.set_property("height", 720u32);
-    nvstreammux.set_property("batched-push-timeout", 40000i32);
-    // FIXME we can use GstNvBufMemoryType?
-    // nvstreammux.set_property("nvbuf-memory-type", "0");
+    let mux = &config.streammux;
+    let audio = config.is_audio();
+    nvstreammux.set_property("batch-size", mux.batch_size);
+    nvstreammux.set_property("batched-push-timeout", mux.batched_push_timeout);
+    // RTCP mode needs attach-sys-ts=false to write NTP time from sender reports
+    nvstreammux.set_property("attach-sys-ts", config.timestamp.ntp == NtpMode::System);
+    // the new nvstreammux for audio has no scaling properties
+    if !audio {
+        nvstreammux.set_property("width", mux.width);
+        nvstreammux.set_property("height", mux.height);
+        nvstreammux.set_property("live-source", mux.live_source);
+        if let Some(mem_type) = mux.nvbuf_memory_type.as_ref() {
+            // GstNvBufMemoryType is not exported, so set it by value or nick
+            nvstreammux.set_property_from_str("nvbuf-memory-type", mem_type);
+        }
+    }
 
-    nvinfer.set_property("config-file-path", opt.config_infer_file.to_str().unwrap());
+    let mut elements = vec![nvstreammux.clone()];
+    // tracker runs after primary detectors to give object ids to secondary classifiers
+    let past_frame = config.export.backfill_window > 0;
+    let mut nvtracker = config
+        .tracker
+        .as_ref()
+        .map(|t| create_tracker(t, past_frame))
+        .transpose()?;
+    for gie in config.gie.iter() {
+        if audio {
+            elements.push(create_infer_audio(gie, &config.audio)?);
+            continue;
+        }
+        if gie.load_infer_config()?.is_secondary() {
+            elements.extend(nvtracker.take());
+        }
+        let nvinfer = gst::ElementFactory::make("nvinfer").build()?;
+        nvinfer.set_property("config-file-path", path_str(&gie.config_file)?);
+        elements.push(nvinfer);
+    }
+    elements.extend(nvtracker);
+    let mapper = match config.labels.as_ref() {
+        Some(l) if l.stage == config::LabelStage::Records => Some(l.mapper(&config.gie)?),
+        _ => None,
+    };
+    let records = Arc::new(RecordSender {
+        ts_config: config.timestamp.clone(),
+        mapper,
+        sender: sender.clone(),
+        closed: AtomicBool::new(false),
+    });
+    // payloads are attached before the tee branching to nvmsgbroker
+    let broker = config
+        .msgconv
+        .as_ref()
+        .and_then(|m| m.broker.as_ref().map(|b| (m, b)));
+    let tee = if let Some((msgconv, _)) = broker {
+        let tee = gst::ElementFactory::make("tee").build()?;
+        add_payload_probe(&tee, msgconv, records.clone())?;
+        elements.push(tee.clone());
+        elements.push(gst::ElementFactory::make("queue").build()?);
+        Some(tee)
+    } else {
+        None
+    };
+    appsink.set_property("sync", config.sink.sync);
+    appsink.set_property("max-buffers", config.sink.max_buffers);
+    appsink.set_property("drop", config.sink.drop);
+    elements.push(appsink.clone());
+
+    pipeline.add_many(&elements.iter().collect::<Vec<_>>())?;
+    gst::Element::link_many(&elements.iter().collect::<Vec<_>>())?;
+    if let (Some(tee), Some((_, b))) = (tee, broker) {
+        let queue = gst::ElementFactory::make("queue").build()?;
+        let nvmsgbroker = create_msgbroker(b)?;
+        pipeline.add_many(&[&queue, &nvmsgbroker])?;
+        gst::Element::link_many(&[&tee, &queue, &nvmsgbroker])?;
+    }
 
-    pipeline.add_many(&[&nvvidconv, &nvstreammux, &nvinfer, &appsink])?;
-    gst::Element::link_many(&[&srcbin, &nvvidconv])?;
-    let src_pad = nvvidconv.static_pad("src").expect("has not src pad");
-    let sink_pad = nvstreammux
-        .request_pad_simple("sink_0")
-        .expect("has not sink pad");
-    src_pad.link(&sink_pad)?;
-    gst::Element::link_many(&[&nvstreammux, &nvinfer, &appsink])?;
+    let mut manager = SourceManager::new(
+        pipeline.clone(),
+        nvstreammux,
+        mux.batch_size,
+        config.timestamp.ntp,
+        audio,
+        sender.clone(),
+    );
+    for s in config.source.iter() {
+        manager.add(s)?;
+    }
 
-    let appsink = appsink.downcast::<gst_app::AppSink>().unwrap();
+    let records_at_tee = broker.is_some();
+    let mut latency = config
+        .latency
+        .as_ref()
+        .map(|l| LatencyProbe::new(l, sender.clone()));
+    let appsink = appsink
+        .downcast::<gst_app::AppSink>()
+        .map_err(|_| anyhow!("appsink is not an AppSink"))?;
 
     appsink.set_callbacks(
         gst_app::AppSinkCallbacks::builder()
@@ -110,27 +332,20 @@ fn create_pipeline(opt: &Opt, sender: Sender<FrameObjects>) -> Result<gst::Pipel
                     gst::FlowError::Error
                 })?;
 
-                let meta = buffer
-                    .meta::<nvdsmeta_sys::NvDsMeta>()
-                    .expect("No custom meta found");
-
-                if let Some(meta) = meta.get_batch_meta() {
-                    let list = meta.frame_meta_list();
-
-                    for meta in list {
-                        let frame_info = BufferFrameInfo::new(*buffer.pts().unwrap(), meta);
-
-                        let objs = meta.object_meta_list();
-                        let objects = objs
-                            .enumerate()
-                            .map(|(j, o)| {
-                                let mut obj = ObjectMeta::from(o);
-                                obj.detection_index = j as u32;
-                                obj
-                            })
-                            .collect();
-                        sender.send(FrameObjects::new(frame_info, objects)).unwrap();
-                    }
+                if let Some(latency) = latency.as_mut() {
+                    latency.push(buffer);
+                }
+                if records_at_tee {
+                    return if records.is_closed() {
+                        Err(gst::FlowError::Eos)
+                    } else {
+                        Ok(gst::FlowSuccess::Ok)
+                    };
+                }
+                let running_time = appsink.current_running_time().map(|t| t.nseconds());
+                let arrival = Arrival::now(running_time);
+                if let Some(r) = records.records(appsink.upcast_ref(), buffer, &arrival)? {
+                    records.send(r)?;
                 }
 
                 Ok(gst::FlowSuccess::Ok)
@@ -138,33 +353,268 @@ fn create_pipeline(opt: &Opt, sender: Sender<FrameObjects>) -> Result<gst::Pipel
             .build(),
     );
 
-    Ok(pipeline)
+    Ok((pipeline, manager))
 }
 
-fn example_main(opt: &Opt) {
-    use std::io::Write;
-    let (sender, receiver) = channel();
-    let pipeline = create_pipeline(opt, sender).unwrap();
+/// Collect latencies at the appsink and report them at the interval
+struct LatencyProbe {
+    reporter: LatencyReporter,
+    interval: std::time::Duration,
+    reported_at: std::time::Instant,
+    /// send reports as latency records if set
+    sender: Option<Broadcast>,
+}
+
+impl LatencyProbe {
+    fn new(config: &config::LatencyConfig, sender: Broadcast) -> Self {
+        Self {
+            reporter: LatencyReporter::new(),
+            interval: std::time::Duration::from_secs(config.interval),
+            reported_at: std::time::Instant::now(),
+            sender: config.export.then_some(sender),
+        }
+    }
+
+    fn push(&mut self, buffer: &gst::BufferRef) {
+        if let Err(e) = self.reporter.push(buffer) {
+            log::debug!("failed to measure latency: {}", e);
+        }
+        if self.reported_at.elapsed() < self.interval {
+            return;
+        }
+        self.reported_at = std::time::Instant::now();
+        let report = self.reporter.report();
+        for (source_id, l) in report.iter() {
+            let stats = l.frame.iter().map(|s| ("frame", s));
+            let components = l.components.iter().map(|(n, s)| (n.as_str(), s));
+            for (name, s) in stats.chain(components) {
+                log::info!(
+                    "latency source {} {}: p50 {:.1} p95 {:.1} p99 {:.1} max {:.1} ms ({} frames)",
+                    source_id,
+                    name,
+                    s.p50,
+                    s.p95,
+                    s.p99,
+                    s.max,
+                    s.count
+                );
+            }
+        }
+        if let Some(sender) = self.sender.as_ref() {
+            for event in LatencyEvent::from_report(&report) {
+                // closed exporters are reported by the appsink callback
+                let _ = sender.send(Record::Latency(event));
+            }
+        }
+    }
+}
+
+/// Convert metadata of the batched buffer into export records
+fn frame_records(
+    buffer: &gst::BufferRef,
+    arrival: &Arrival,
+    ts_config: &config::TimestampConfig,
+) -> Result<Vec<Record>, examples::Error> {
+    let meta = nvdsmeta_sys::NvDsMeta::from_buffer(buffer)?;
+    let buffer_pts = buffer.pts().map(|t| t.nseconds());
+    let batch_meta = meta.batch_meta()?;
+    let past_frame_meta = batch_meta.past_frame_meta().collect::<Vec<_>>();
+    let mut records = vec![];
+    for meta in batch_meta.frames() {
+        let meta = match meta {
+            FrameMeta::Video(meta) => meta,
+            FrameMeta::Audio(meta) => {
+                records.push(Record::Audio(AudioFrame::from(meta)));
+                continue;
+            }
+        };
+        let timestamps = FrameTimestamps::new(meta, buffer_pts, arrival, ts_config.ntp);
+        let frame_info = BufferFrameInfo::new(meta, timestamps, &ts_config.policy);
+        let objects = meta
+            .object_meta_list()
+            .enumerate()
+            .map(|(j, o)| {
+                let mut obj = ObjectMeta::from(o);
+                obj.detection_index = j as u32;
+                obj
+            })
+            .collect();
+        let segmentation = meta
+            .segmentation_meta()
+            .map(SegmentationStats::from)
+            .collect();
+        let past_objects = past_frame_meta
+            .iter()
+            .filter_map(|b| b.stream(meta.source_id()))
+            .flat_map(|s| s.object_lists())
+            .flat_map(PastObject::from_list)
+            .collect();
+        records.push(Record::Frame(
+            FrameObjects::new(frame_info, objects)
+                .with_segmentation(segmentation)
+                .with_past_objects(past_objects),
+        ));
+    }
+    Ok(records)
+}
+
+/// Validate nvinfer config files and print found issues, `false` if any is an error
+fn check_infer_configs(config: &AppConfig) -> Result<bool, Error> {
+    use examples::infer_config::{Severity, Validator};
+    let working_dir = std::env::current_dir()?;
+    let mut ok = true;
+    for gie in config.gie.iter() {
+        let infer_config = match gie.load_infer_config() {
+            Ok(c) => c,
+            Err(e) => {
+                println!("{:#}", e);
+                ok = false;
+                continue;
+            }
+        };
+        let issues = Validator::new(&gie.config_file, &working_dir).validate(&infer_config);
+        for issue in issues.iter() {
+            println!("{}: {}", gie.config_file.display(), issue);
+        }
+        ok &= !issues.iter().any(|i| i.severity == Severity::Error);
+    }
+    Ok(ok)
+}
+
+/// `--check-config`: print every finding of the nvinfer configs and the app config
+fn check_config(opt: &Opt) -> Result<ExitStatus, Error> {
+    let config = opt.merged_config().context("invalid config")?;
+    let mut ok = check_infer_configs(&config)?;
+    if let Err(e) = config.validate() {
+        println!("invalid config: {:#}", e);
+        ok = false;
+    }
+    Ok(if ok {
+        ExitStatus::Success
+    } else {
+        ExitStatus::PipelineError
+    })
+}
+
+/// Process exit status of nvdsmeta_app
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitStatus {
+    /// reached EOS or `--duration`
+    Success = 0,
+    /// error from the pipeline or invalid configuration
+    PipelineError = 1,
+    /// pipeline did not drain before the shutdown timeout
+    DrainTimeout = 2,
+    /// stopped by SIGINT/SIGTERM after draining
+    Interrupted = 130,
+}
+
+/// Why the pipeline is stopping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StopReason {
+    Signal,
+    Duration,
+}
+
+fn example_main(opt: &Opt) -> Result<ExitStatus, Error> {
+    use std::time::{Duration, Instant};
+
+    if opt.check_config {
+        return check_config(opt);
+    }
+    let config = opt.app_config().context("invalid config")?;
+    let f = std::fs::File::create(&config.export.path)
+        .with_context(|| format!("failed to create {}", config.export.path.display()))?;
+    let mut exporter = config.export.exporter(f)?;
+    if let Some(l) = config.labels.as_ref() {
+        if l.stage == config::LabelStage::Export {
+            exporter = Box::new(LabelExporter::new(exporter, l.mapper(&config.gie)?));
+        }
+    }
+    let (sender, worker) =
+        ExportWorker::spawn(exporter, opt.export_queue_size, opt.export_drop_policy);
+    // (name, worker, dropped records already reported)
+    let mut workers = vec![(config.export.path.display().to_string(), worker, 0)];
+    let mut senders = vec![sender];
+    for p in config.publish.iter() {
+        let interval = Duration::from_secs(p.reconnect_interval);
+        let transport = publish::transport(&p.endpoint, p.format, p.queue_size, interval)
+            .with_context(|| format!("failed to create publisher {}", p.endpoint))?;
+        let publisher = Publisher::new(
+            p.endpoint.clone(),
+            transport,
+            p.format,
+            p.topic.clone(),
+            interval,
+        );
+        // a slow subscriber must not stall the pipeline
+        let (sender, worker) =
+            ExportWorker::spawn(Box::new(publisher), p.queue_size, DropPolicy::DropNewest);
+        senders.push(sender);
+        workers.push((p.endpoint.to_string(), worker, 0));
+    }
+    if let Some(m) = config.msgconv.as_ref() {
+        if let Some(p) = m.path.as_ref() {
+            let f = std::fs::File::create(p)
+                .with_context(|| format!("failed to create {}", p.display()))?;
+            let exporter = MsgConvExporter::new(std::io::BufWriter::new(f), m.load_msgconv()?);
+            let (sender, worker) = ExportWorker::spawn(
+                Box::new(exporter),
+                opt.export_queue_size,
+                opt.export_drop_policy,
+            );
+            senders.push(sender);
+            workers.push((p.display().to_string(), worker, 0));
+        }
+    }
+    // nvstreammux plugin selects the new implementation which batches audio when it is
+    // loaded, which may be before this point, so the variable must be set on launch
+    if config.is_audio() && std::env::var("USE_NEW_NVSTREAMMUX").as_deref() != Ok("yes") {
+        bail!("audio sources need USE_NEW_NVSTREAMMUX=yes in the environment on launch");
+    }
+    if let Some(l) = config.latency.as_ref() {
+        // DeepStream reads them when elements are created
+        std::env::set_var("NVDS_ENABLE_LATENCY_MEASUREMENT", "1");
+        if l.component {
+            std::env::set_var("NVDS_ENABLE_COMPONENT_LATENCY_MEASUREMENT", "1");
+        }
+    }
+    let (pipeline, mut manager) =
+        create_pipeline(&config, Broadcast::new(senders)).context("failed to create pipeline")?;
+    let control = control_channel(opt);
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    {
+        let interrupted = interrupted.clone();
+        ctrlc::set_handler(move || interrupted.store(true, Ordering::SeqCst))
+            .context("failed to set signal handler")?;
+    }
 
     pipeline
         .set_state(gst::State::Playing)
-        .expect("Unable to set the pipeline to the `Playing` state");
-    let pipeline = pipeline.dynamic_cast::<gst::Pipeline>().unwrap();
+        .context("Unable to set the pipeline to the `Playing` state")?;
 
     let bus = pipeline
         .bus()
         .expect("Pipeline without bus. Shouldn't happen!");
-    let mut f = std::io::BufWriter::new(std::fs::File::create(&opt.export_json).unwrap());
-    'outer: loop {
-        if let Ok(v) = receiver.try_recv() {
-            serde_json::to_writer(&mut f, &v).unwrap();
-            writeln!(&mut f).unwrap();
-        }
-
-        for msg in bus.iter_timed(gst::ClockTime::MSECOND * 10) {
+    let started = Instant::now();
+    let duration = opt.duration.map(Duration::from_secs);
+    let shutdown_timeout = Duration::from_secs(opt.shutdown_timeout);
+    // (reason, deadline) after EOS is sent to drain the pipeline
+    let mut stopping: Option<(StopReason, Instant)> = None;
+    let mut status = ExitStatus::Success;
+    let mut reported_at = Instant::now();
+    loop {
+        // records are written by the export thread, the main thread only waits bus messages
+        if let Some(msg) = bus.timed_pop(gst::ClockTime::MSECOND * 100) {
             use gst::MessageView;
             match msg.view() {
-                MessageView::Eos(..) => break 'outer,
+                MessageView::Eos(..) => {
+                    if let Some((StopReason::Signal, _)) = stopping {
+                        status = ExitStatus::Interrupted;
+                    }
+                    break;
+                }
                 MessageView::Error(err) => {
                     println!(
                         "Error from {:?}: {} ({:?})",
@@ -172,54 +622,80 @@ fn example_main(opt: &Opt) {
                         err.error(),
                         err.debug()
                     );
-                    break 'outer;
+                    status = ExitStatus::PipelineError;
+                    break;
                 }
                 _ => (),
             }
         }
+
+        for c in control.iter().flat_map(|c| c.try_iter()) {
+            if let Err(e) = manager.apply(&c) {
+                log::error!("failed to apply {:?}: {}", c, e);
+            }
+        }
+
+        if reported_at.elapsed() >= Duration::from_secs(10) {
+            for (name, worker, reported) in workers.iter_mut() {
+                let dropped = worker.metrics().dropped();
+                if dropped != *reported {
+                    log::warn!("{} records are dropped for {}", dropped - *reported, name);
+                    *reported = dropped;
+                }
+            }
+            reported_at = Instant::now();
+        }
+
+        match stopping {
+            None => {
+                let reason = if interrupted.load(Ordering::SeqCst) {
+                    Some(StopReason::Signal)
+                } else if duration.map_or(false, |d| started.elapsed() >= d) {
+                    Some(StopReason::Duration)
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    log::info!("stopping by {:?}, waiting for EOS", reason);
+                    pipeline.send_event(gst::event::Eos::new());
+                    stopping = Some((reason, Instant::now() + shutdown_timeout));
+                }
+            }
+            Some((_, deadline)) if Instant::now() >= deadline => {
+                log::error!("pipeline did not drain in {:?}", shutdown_timeout);
+                status = ExitStatus::DrainTimeout;
+                break;
+            }
+            Some(_) => (),
+        }
     }
 
     pipeline
         .set_state(gst::State::Null)
-        .expect("Unable to set the pipeline to the `Null` state");
+        .context("Unable to set the pipeline to the `Null` state")?;
+
+    for (name, worker, _) in workers {
+        let metrics = worker.metrics().clone();
+        worker
+            .finish()
+            .with_context(|| format!("failed to write {}", name))?;
+        log::info!(
+            "{}: {} records are written, {} records are dropped",
+            name,
+            metrics.written(),
+            metrics.dropped()
+        );
+    }
+    Ok(status)
 }
 
-#[derive(Debug, StructOpt)]
-enum Source {
-    /// inference image file
-    ImageFile {
-        #[structopt(
-            short,
-            long,
-            default_value = "/opt/nvidia/deepstream/deepstream/samples/streams/sample_720p.jpg"
-        )]
-        location: String,
-    },
-    /// inference video file
-    VideoFile {
-        #[structopt(
-            short,
-            long,
-            default_value = "/opt/nvidia/deepstream/deepstream/samples/streams/sample_720p.h264"
-        )]
-        location: String,
-        /// Number of buffers to flow in the pipeline
-        #[structopt(long, default_value = "30")]
-        num_buffers: i32,
-    },
-    /// inference v4l2 camera source
-    V4l2Src {
-        #[structopt(short, long, default_value = "/dev/video0")]
-        device: String,
-        /// Number of buffers to flow in the pipeline
-        #[structopt(long, default_value = "30")]
-        num_buffers: i32,
-
-        #[structopt(long, default_value = "1280")]
-        width: i32,
-        #[structopt(long, default_value = "720")]
-        height: i32,
-    },
+fn control_channel(opt: &Opt) -> Option<Receiver<Control>> {
+    if !opt.control_stdin {
+        return None;
+    }
+    let (sender, receiver) = channel();
+    source::spawn_stdin_control(sender);
+    Some(receiver)
 }
 
 #[derive(Debug, StructOpt)]
@@ -228,19 +704,161 @@ enum Source {
     about = "test nvdsmeta with deepstremaer sample"
 )]
 struct Opt {
+    /// Source overrides `[[source]]` of the config file
     #[structopt(subcommand)]
-    source: Source,
+    source: Option<Source>,
+
+    /// Application config file in toml
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// nvinfer config files linked in the given order.
+    /// Repeat to chain secondary classifiers after the primary detector.
+    /// Overrides `[[gie]]` of the config file [default: config_infer_yolov3.txt]
+    #[structopt(long, parse(from_os_str), number_of_values = 1)]
+    config_infer_file: Vec<PathBuf>,
+
+    /// Overrides `export.path` of the config file [default: detect.json]
+    #[structopt(long, parse(from_os_str))]
+    export_json: Option<PathBuf>,
+
+    /// Maximum number of sources linked to nvstreammux at the same time.
+    /// Overrides `streammux.batch_size` of the config file [default: 1]
+    #[structopt(long)]
+    max_sources: Option<u32>,
+
+    /// Validate the config and nvinfer config files, print every finding and exit
+    /// without starting the pipeline
+    #[structopt(long)]
+    check_config: bool,
+
+    /// Candidates of the record timestamp in priority order from buf_pts, buffer_pts,
+    /// ntp, arrival_system and arrival_running.
+    /// Overrides `timestamp.policy` of the config file [default: ntp,arrival_system]
+    #[structopt(long)]
+    timestamp_policy: Option<TimestampPolicy>,
+
+    /// Source of ntp_timestamp: disabled, system (attach-sys-ts) or rtcp for RTSP sources.
+    /// Overrides `timestamp.ntp` of the config file [default: system]
+    #[structopt(long)]
+    ntp_mode: Option<NtpMode>,
+
+    /// Format of the export file: json (JSON Lines), csv, mot, coco or protobuf.
+    /// Overrides `export.format` of the config file [default: guessed from the extension]
+    #[structopt(long)]
+    export_format: Option<ExportFormat>,
+
+    /// Export a record per frame (frames) or per tracked object lifetime (tracks).
+    /// Overrides `export.mode` of the config file [default: frames]
+    #[structopt(long)]
+    export_mode: Option<config::ExportMode>,
+
+    /// Hold frames of each source to insert objects reported later by the tracker.
+    /// Overrides `export.backfill_window` of the config file [default: 0 (disabled)]
+    #[structopt(long)]
+    backfill_window: Option<u32>,
+
+    /// Publish records to the endpoint in addition to `[[publish]]` of the config file,
+    /// such as unix:///tmp/nvdsmeta.sock, tcp://localhost:5000, zmq+tcp://*:5556 or mqtt://localhost
+    #[structopt(long, number_of_values = 1)]
+    publish: Vec<Endpoint>,
+
+    /// Measure latencies of frames and components with `[latency]` of the config file
+    /// or its defaults
+    #[structopt(long)]
+    latency: bool,
+
+    /// Capacity of the queue between the pipeline and the export thread
+    #[structopt(long, default_value = "1024")]
+    export_queue_size: usize,
+
+    /// Policy when the export queue is full: `block` applies backpressure
+    /// to the pipeline and `drop-newest` drops records
+    #[structopt(long, default_value = "block")]
+    export_drop_policy: DropPolicy,
+
+    /// Stop the pipeline after the given seconds
+    #[structopt(long)]
+    duration: Option<u64>,
+
+    /// Seconds to wait for the pipeline to drain after EOS is sent on stop
+    #[structopt(long, default_value = "5")]
+    shutdown_timeout: u64,
+
+    /// Read `add <uri>` and `remove <source_id>` commands from stdin while playing
+    #[structopt(long)]
+    control_stdin: bool,
+}
 
-    #[structopt(long, parse(from_os_str), default_value = "config_infer_yolov3.txt")]
-    config_infer_file: PathBuf,
+impl Opt {
+    /// Load the config file and override it by command line flags
+    fn app_config(&self) -> Result<AppConfig, Error> {
+        let config = self.merged_config()?;
+        config.validate()?;
+        Ok(config)
+    }
 
-    #[structopt(long, parse(from_os_str), default_value = "detect.json")]
-    export_json: PathBuf,
+    /// [`Opt::app_config`] without validation
+    fn merged_config(&self) -> Result<AppConfig, Error> {
+        let mut config = match self.config.as_ref() {
+            Some(p) => AppConfig::load(p)?,
+            None => AppConfig::default(),
+        };
+        if let Some(s) = self.source.as_ref() {
+            config.source = vec![s.clone()];
+        }
+        if !self.config_infer_file.is_empty() {
+            config.gie = self
+                .config_infer_file
+                .iter()
+                .map(|p| config::GieConfig {
+                    config_file: p.clone(),
+                })
+                .collect();
+        }
+        if let Some(p) = self.export_json.as_ref() {
+            config.export.path = p.clone();
+        }
+        if let Some(format) = self.export_format {
+            config.export.format = Some(format);
+        }
+        if let Some(mode) = self.export_mode {
+            config.export.mode = mode;
+        }
+        if let Some(n) = self.backfill_window {
+            config.export.backfill_window = n;
+        }
+        config.publish.extend(
+            self.publish
+                .iter()
+                .map(|e| config::PublishConfig::new(e.clone())),
+        );
+        if self.latency && config.latency.is_none() {
+            config.latency = Some(config::LatencyConfig::default());
+        }
+        if let Some(n) = self.max_sources {
+            config.streammux.batch_size = n;
+        }
+        if let Some(policy) = self.timestamp_policy.as_ref() {
+            config.timestamp.policy = policy.clone();
+        }
+        if let Some(mode) = self.ntp_mode {
+            config.timestamp.ntp = mode;
+        }
+        Ok(config)
+    }
 }
 
 fn main() {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let opt = Opt::from_args();
     log::debug!("{:?}", opt);
-    example_main(&opt);
+    let status = match example_main(&opt) {
+        Ok(status) => status,
+        Err(e) => {
+            log::error!("{:#}", e);
+            ExitStatus::PipelineError
+        }
+    };
+    std::process::exit(status as i32);
 }
@@ -0,0 +1,767 @@
+//! Application config file of nvdsmeta_app
+//!
+//! Relative paths in the config file, including locations of file sources and `file://`
+//! uris, are resolved against the directory of the config file.
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Error};
+use examples::backfill::BackfillExporter;
+use examples::exporter::{CocoOptions, ExportFormat, Exporter};
+use examples::infer_config::InferConfig;
+use examples::labels::{LabelMapper, Labels, Ontology, OntologyClass};
+use examples::msgconv::{MsgConv, MsgConvConfig, PayloadType};
+use examples::publish::{Endpoint, PayloadFormat, TopicTemplate};
+use examples::timestamp::{NtpMode, TimestampPolicy};
+use examples::track::TrackExporter;
+use serde::Deserialize;
+
+use crate::source::Source;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreammuxConfig {
+    /// Maximum number of sources linked at the same time
+    pub batch_size: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Timeout in microseconds to push a batch after the first buffer is available
+    pub batched_push_timeout: i32,
+    /// Value or nick of GstNvBufMemoryType
+    pub nvbuf_memory_type: Option<String>,
+    pub live_source: bool,
+}
+
+impl Default for StreammuxConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1,
+            width: 1280,
+            height: 720,
+            batched_push_timeout: 40000,
+            nvbuf_memory_type: None,
+            live_source: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GieConfig {
+    /// nvinfer config file
+    pub config_file: PathBuf,
+}
+
+impl GieConfig {
+    pub fn load_infer_config(&self) -> Result<InferConfig, Error> {
+        InferConfig::load(&self.config_file)
+            .with_context(|| format!("failed to load {}", self.config_file.display()))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackerConfig {
+    pub ll_lib_file: PathBuf,
+    pub ll_config_file: Option<PathBuf>,
+    #[serde(default = "TrackerConfig::default_width")]
+    pub width: u32,
+    #[serde(default = "TrackerConfig::default_height")]
+    pub height: u32,
+}
+
+impl TrackerConfig {
+    fn default_width() -> u32 {
+        640
+    }
+    fn default_height() -> u32 {
+        384
+    }
+}
+
+/// nvmsgbroker sending payloads attached as NVDS_PAYLOAD_META
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BrokerConfig {
+    /// Protocol adapter such as libnvds_kafka_proto.so
+    pub proto_lib: PathBuf,
+    /// Connection string such as `localhost;9092`
+    pub conn_str: String,
+    pub topic: Option<String>,
+    /// Config file of the protocol adapter
+    pub config: Option<PathBuf>,
+}
+
+/// Payloads compatible with nvmsgconv
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MsgConvSection {
+    /// msgconv config file with `[sensorN]`, `[placeN]` and `[analyticsN]` sections
+    pub config_file: PathBuf,
+    #[serde(default)]
+    pub payload_type: PayloadType,
+    /// componentId of the payload meta, nvmsgbroker sends only matching ids if `comp-id` is set
+    #[serde(default)]
+    pub component_id: u32,
+    /// Write payloads as JSON Lines
+    pub path: Option<PathBuf>,
+    pub broker: Option<BrokerConfig>,
+}
+
+impl MsgConvSection {
+    pub fn load_msgconv(&self) -> Result<MsgConv, Error> {
+        let config = MsgConvConfig::load(&self.config_file)
+            .with_context(|| format!("failed to load {}", self.config_file.display()))?;
+        Ok(MsgConv::new(config, self.payload_type))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinkConfig {
+    /// Synchronize buffers to the clock
+    pub sync: bool,
+    /// Maximum number of buffers queued in appsink, 0 is unlimited
+    pub max_buffers: u32,
+    /// Drop old buffers when the queue is full
+    pub drop: bool,
+}
+
+/// What the export file contains
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportMode {
+    /// A record per frame
+    #[default]
+    Frames,
+    /// A record per tracked object lifetime
+    Tracks,
+}
+
+impl std::str::FromStr for ExportMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "frames" => Ok(ExportMode::Frames),
+            "tracks" => Ok(ExportMode::Tracks),
+            _ => Err(format!(
+                "unknown export mode {:?}, expect frames or tracks",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    pub path: PathBuf,
+    /// Guessed from the extension of `path` if not given
+    pub format: Option<ExportFormat>,
+    pub mode: ExportMode,
+    /// Frames without the object to end its track in the tracks mode
+    pub track_timeout: u32,
+    /// Frames held per source to insert objects from past-frame meta of the tracker,
+    /// 0 disables backfill
+    pub backfill_window: u32,
+    /// `image_id` and `category_id` of the coco format
+    pub coco: CocoOptions,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("detect.json"),
+            format: None,
+            mode: ExportMode::default(),
+            track_timeout: 30,
+            backfill_window: 0,
+            coco: CocoOptions::default(),
+        }
+    }
+}
+
+impl ExportConfig {
+    pub fn format(&self) -> Result<ExportFormat, Error> {
+        match self.format.or_else(|| ExportFormat::from_path(&self.path)) {
+            Some(format) => Ok(format),
+            None => bail!(
+                "export format is not given and unknown extension: {}",
+                self.path.display()
+            ),
+        }
+    }
+
+    pub fn exporter<W: std::io::Write + Send + 'static>(
+        &self,
+        w: W,
+    ) -> Result<Box<dyn Exporter>, Error> {
+        let exporter = self.format()?.exporter(w, &self.coco);
+        let exporter: Box<dyn Exporter> = match self.mode {
+            ExportMode::Frames => exporter,
+            ExportMode::Tracks => Box::new(TrackExporter::new(exporter, self.track_timeout)),
+        };
+        // backfilled frames go into track aggregation too
+        Ok(if self.backfill_window > 0 {
+            Box::new(BackfillExporter::new(exporter, self.backfill_window))
+        } else {
+            exporter
+        })
+    }
+}
+
+fn default_publish_queue_size() -> usize {
+    1024
+}
+
+fn default_reconnect_interval() -> u64 {
+    1
+}
+
+/// A publisher of records, each one has its own queue dropping new records when full
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublishConfig {
+    /// `unix://<path>`, `tcp://<host>:<port>`, `zmq+tcp://<address>` or `mqtt://<host>[:<port>]`
+    pub endpoint: Endpoint,
+    #[serde(default)]
+    pub format: PayloadFormat,
+    /// Topic of ZeroMQ and MQTT, `{source_id}` is replaced by the source id
+    #[serde(default)]
+    pub topic: TopicTemplate,
+    #[serde(default = "default_publish_queue_size")]
+    pub queue_size: usize,
+    /// Seconds to wait before reconnecting
+    #[serde(default = "default_reconnect_interval")]
+    pub reconnect_interval: u64,
+}
+
+impl PublishConfig {
+    pub fn new(endpoint: Endpoint) -> Self {
+        Self {
+            endpoint,
+            format: PayloadFormat::default(),
+            topic: TopicTemplate::default(),
+            queue_size: default_publish_queue_size(),
+            reconnect_interval: default_reconnect_interval(),
+        }
+    }
+}
+
+/// Properties of nvinferaudio used instead of nvinfer for audio sources
+///
+/// Defaults are of the SONYC model of the deepstream-audio sample.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// `audio-transform` such as `melsdb,fft_length=2560,...`
+    pub transform: String,
+    /// Samples per inference frame
+    pub frame_size: u32,
+    /// Samples between inference frames
+    pub hop_size: u32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            transform: "melsdb,fft_length=2560,hop_size=692,dsp_window=hann,num_mels=128,\
+                        sample_rate=44100,p2db_ref=(float)1.0,p2db_min_power=(float)0.0,\
+                        p2db_top_db=(float)80.0"
+                .to_owned(),
+            frame_size: 441000,
+            hop_size: 110250,
+        }
+    }
+}
+
+/// Latency measurement of DeepStream, reported at the appsink
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatencyConfig {
+    /// Also measure each component such as the decoder, nvstreammux and nvinfer
+    pub component: bool,
+    /// Seconds between reports
+    pub interval: u64,
+    /// Write reports to the export file and publishers as latency records
+    pub export: bool,
+}
+
+impl Default for LatencyConfig {
+    fn default() -> Self {
+        Self {
+            component: true,
+            interval: 10,
+            export: false,
+        }
+    }
+}
+
+/// Where [`LabelsConfig`] is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelStage {
+    /// records of the export file and publishers
+    #[default]
+    Records,
+    /// the export file only
+    Export,
+}
+
+/// Label fallback and class remapping of objects
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LabelsConfig {
+    /// Fill empty labels from `labelfile-path` of the first gie
+    pub fill: bool,
+    pub stage: LabelStage,
+    /// Classes of the application made of labels of the model, ids are in this order
+    pub remap: Vec<OntologyClass>,
+    /// Drop objects whose labels are not in `remap`, otherwise class_id is -1
+    pub drop_unmapped: bool,
+}
+
+impl Default for LabelsConfig {
+    fn default() -> Self {
+        Self {
+            fill: true,
+            stage: LabelStage::default(),
+            remap: vec![],
+            drop_unmapped: false,
+        }
+    }
+}
+
+impl LabelsConfig {
+    pub fn mapper(&self, gie: &[GieConfig]) -> Result<LabelMapper, Error> {
+        let labels = match (self.fill, gie.first()) {
+            (true, Some(g)) => {
+                Some(Labels::from_infer_config(&g.config_file).with_context(|| {
+                    format!("failed to load labels of {}", g.config_file.display())
+                })?)
+            }
+            _ => None,
+        };
+        let ontology = if self.remap.is_empty() {
+            None
+        } else {
+            Some(Ontology::new(self.remap.clone(), self.drop_unmapped)?)
+        };
+        Ok(LabelMapper::new(labels, ontology))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimestampConfig {
+    /// Candidates of the canonical timestamp such as `ntp,arrival_system`
+    pub policy: TimestampPolicy,
+    pub ntp: NtpMode,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub source: Vec<Source>,
+    pub streammux: StreammuxConfig,
+    pub gie: Vec<GieConfig>,
+    pub tracker: Option<TrackerConfig>,
+    pub sink: SinkConfig,
+    pub export: ExportConfig,
+    pub publish: Vec<PublishConfig>,
+    pub msgconv: Option<MsgConvSection>,
+    pub latency: Option<LatencyConfig>,
+    pub audio: AudioConfig,
+    pub timestamp: TimestampConfig,
+    pub labels: Option<LabelsConfig>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            source: vec![],
+            streammux: StreammuxConfig::default(),
+            gie: vec![GieConfig {
+                config_file: PathBuf::from("config_infer_yolov3.txt"),
+            }],
+            tracker: None,
+            sink: SinkConfig::default(),
+            export: ExportConfig::default(),
+            publish: vec![],
+            msgconv: None,
+            latency: None,
+            audio: AudioConfig::default(),
+            timestamp: TimestampConfig::default(),
+            labels: None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Load a toml config file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut config: AppConfig =
+            toml::from_str(&s).with_context(|| format!("failed to parse {}", path.display()))?;
+        if let Some(base) = path.parent() {
+            config.resolve_paths(base);
+        }
+        Ok(config)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        for s in self.source.iter_mut() {
+            s.resolve_paths(base);
+        }
+        for gie in self.gie.iter_mut() {
+            gie.config_file = base.join(&gie.config_file);
+        }
+        if let Some(tracker) = self.tracker.as_mut() {
+            tracker.ll_lib_file = base.join(&tracker.ll_lib_file);
+            if let Some(p) = tracker.ll_config_file.as_mut() {
+                *p = base.join(&p);
+            }
+        }
+        self.export.path = base.join(&self.export.path);
+        if let Some(m) = self.msgconv.as_mut() {
+            m.config_file = base.join(&m.config_file);
+            if let Some(p) = m.path.as_mut() {
+                *p = base.join(&p);
+            }
+            if let Some(b) = m.broker.as_mut() {
+                b.proto_lib = base.join(&b.proto_lib);
+                if let Some(p) = b.config.as_mut() {
+                    *p = base.join(&p);
+                }
+            }
+        }
+    }
+
+    /// Sources are audio, which are inferred by nvinferaudio
+    pub fn is_audio(&self) -> bool {
+        self.source.first().map_or(false, Source::is_audio)
+    }
+
+    /// Check consistency of values before building the pipeline
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.source.is_empty() {
+            bail!("no source is given");
+        }
+        if self.source.len() as u32 > self.streammux.batch_size {
+            bail!(
+                "number of sources {} exceeds streammux batch_size {}",
+                self.source.len(),
+                self.streammux.batch_size
+            );
+        }
+        if self.streammux.width == 0 || self.streammux.height == 0 {
+            bail!("streammux width and height must be greater than 0");
+        }
+        if self.gie.is_empty() {
+            bail!("no gie config is given");
+        }
+        for gie in self.gie.iter() {
+            if !gie.config_file.is_file() {
+                bail!(
+                    "gie config file does not exist: {}",
+                    gie.config_file.display()
+                );
+            }
+        }
+        self.validate_gie_chain()?;
+        if let Some(l) = self.labels.as_ref() {
+            l.mapper(&self.gie)?;
+        }
+        let format = self.export.format()?;
+        // other formats have no representation of track events
+        if self.export.mode == ExportMode::Tracks
+            && !matches!(format, ExportFormat::Json | ExportFormat::Protobuf)
+        {
+            bail!("tracks export mode needs json or protobuf format");
+        }
+        if self.export.backfill_window > 0 && self.tracker.is_none() {
+            bail!("export backfill_window needs a tracker");
+        }
+        for p in self.publish.iter() {
+            if p.queue_size == 0 {
+                bail!(
+                    "queue_size of publisher {} must be greater than 0",
+                    p.endpoint
+                );
+            }
+        }
+        if let Some(m) = self.msgconv.as_ref() {
+            m.load_msgconv()?;
+            if m.path.is_none() && m.broker.is_none() {
+                bail!("msgconv needs path or broker to send payloads");
+            }
+            if let Some(b) = m.broker.as_ref() {
+                if !b.proto_lib.is_file() {
+                    bail!("proto_lib does not exist: {}", b.proto_lib.display());
+                }
+            }
+        }
+        if self.source.iter().any(|s| s.is_audio() != self.is_audio()) {
+            bail!("audio and video sources cannot be mixed");
+        }
+        if self.is_audio() {
+            self.validate_audio(format)?;
+        }
+        if let Some(l) = self.latency.as_ref() {
+            if l.interval == 0 {
+                bail!("latency interval must be greater than 0");
+            }
+        }
+        if let Some(tracker) = self.tracker.as_ref() {
+            if !tracker.ll_lib_file.is_file() {
+                bail!(
+                    "tracker library does not exist: {}",
+                    tracker.ll_lib_file.display()
+                );
+            }
+            if let Some(p) = tracker.ll_config_file.as_ref() {
+                if !p.is_file() {
+                    bail!("tracker config file does not exist: {}", p.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Audio pipelines have only nvinferaudio and export audio frame records
+    fn validate_audio(&self, format: ExportFormat) -> Result<(), Error> {
+        if self.gie.len() != 1 {
+            bail!("audio sources need exactly one gie for nvinferaudio");
+        }
+        if self.tracker.is_some() {
+            bail!("tracker cannot be used with audio sources");
+        }
+        if self.msgconv.is_some() {
+            bail!("msgconv cannot be used with audio sources");
+        }
+        if self.export.mode != ExportMode::Frames {
+            bail!("audio sources need frames export mode");
+        }
+        // other formats have no representation of audio frames
+        if !matches!(format, ExportFormat::Json | ExportFormat::Protobuf) {
+            bail!("audio sources need json or protobuf format");
+        }
+        if self.audio.frame_size == 0 || self.audio.hop_size == 0 {
+            bail!("audio frame_size and hop_size must be greater than 0");
+        }
+        Ok(())
+    }
+
+    /// Secondary gie must operate on a gie placed before it
+    fn validate_gie_chain(&self) -> Result<(), Error> {
+        let mut unique_ids = vec![];
+        for (i, gie) in self.gie.iter().enumerate() {
+            let infer_config = gie.load_infer_config()?;
+            let p = &infer_config.property;
+            let path = gie.config_file.display();
+            if i == 0 && infer_config.is_secondary() {
+                bail!("first gie must be primary (process-mode=1): {}", path);
+            }
+            // negative operate-on-gie-id means any gie
+            let target = p.operate_on_gie_id.filter(|id| *id >= 0);
+            if let (true, Some(target)) = (infer_config.is_secondary(), target) {
+                if !unique_ids.contains(&(target as u32)) {
+                    bail!(
+                        "operate-on-gie-id={} does not match preceding gie-unique-id {:?}: {}",
+                        target,
+                        unique_ids,
+                        path
+                    );
+                }
+            }
+            let id = p.gie_unique_id.unwrap_or(0);
+            if unique_ids.contains(&id) {
+                bail!("gie-unique-id={} is duplicated: {}", id, path);
+            }
+            unique_ids.push(id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Empty directory under the temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "nvdsmeta-app-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn parse(s: &str) -> Result<AppConfig, toml::de::Error> {
+        toml::from_str(s)
+    }
+
+    #[test]
+    fn defaults_of_omitted_tables() {
+        let c = parse(
+            r#"
+            [[source]]
+            type = "video_file"
+            location = "a.h264"
+
+            [[publish]]
+            endpoint = "tcp://localhost:5000"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            &c.source[..],
+            [Source::VideoFile { location, num_buffers: 30 }] if location == "a.h264"
+        ));
+        assert_eq!(c.streammux.batch_size, 1);
+        assert_eq!((c.streammux.width, c.streammux.height), (1280, 720));
+        assert_eq!(
+            c.gie[0].config_file,
+            PathBuf::from("config_infer_yolov3.txt")
+        );
+        assert_eq!(c.export.path, PathBuf::from("detect.json"));
+        assert_eq!(c.export.mode, ExportMode::Frames);
+        assert_eq!(c.export.track_timeout, 30);
+        assert_eq!(c.publish[0].queue_size, 1024);
+        assert_eq!(c.publish[0].reconnect_interval, 1);
+        assert!(c.tracker.is_none() && c.msgconv.is_none() && c.latency.is_none());
+        assert!(!c.is_audio());
+
+        let c = parse("[latency]\ninterval = 5\n[labels]\nstage = \"export\"\n").unwrap();
+        let l = c.latency.unwrap();
+        assert!(l.component && !l.export);
+        assert_eq!(l.interval, 5);
+        let l = c.labels.unwrap();
+        assert!(l.fill);
+        assert_eq!(l.stage, LabelStage::Export);
+    }
+
+    #[test]
+    fn unknown_fields_are_errors() {
+        assert!(parse("[streammux]\nbatchsize = 2\n").is_err());
+        assert!(parse("[[source]]\ntype = \"rtsp\"\nuri = \"rtsp://a\"\n").is_err());
+        assert!(parse("[export]\nmode = \"objects\"\n").is_err());
+    }
+
+    #[test]
+    fn paths_are_resolved_against_the_config_dir() {
+        let dir = temp_dir("paths");
+        let path = dir.join("app.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [[source]]
+            type = "video_file"
+            location = "streams/a.h264"
+
+            [[source]]
+            type = "image_file"
+            location = "/opt/b.jpg"
+
+            [[source]]
+            type = "uri"
+            uri = "file://streams/c.mp4"
+
+            [[source]]
+            type = "uri"
+            uri = "rtsp://192.168.0.10:554/stream"
+
+            [[gie]]
+            config_file = "infer.txt"
+
+            [tracker]
+            ll_lib_file = "/opt/libnvds_nvmultiobjecttracker.so"
+            ll_config_file = "tracker.yml"
+
+            [export]
+            path = "out/detect.json"
+
+            [msgconv]
+            config_file = "msgconv.txt"
+            "#,
+        )
+        .unwrap();
+        let c = AppConfig::load(&path).unwrap();
+        let locations = c.source.iter().map(Source::location).collect::<Vec<_>>();
+        assert_eq!(
+            locations,
+            [
+                dir.join("streams/a.h264").to_str().unwrap(),
+                "/opt/b.jpg",
+                &format!("file://{}", dir.join("streams/c.mp4").display()),
+                "rtsp://192.168.0.10:554/stream",
+            ]
+        );
+        assert_eq!(c.gie[0].config_file, dir.join("infer.txt"));
+        let tracker = c.tracker.unwrap();
+        assert_eq!(
+            tracker.ll_lib_file,
+            PathBuf::from("/opt/libnvds_nvmultiobjecttracker.so")
+        );
+        assert_eq!(tracker.ll_config_file, Some(dir.join("tracker.yml")));
+        assert_eq!(c.export.path, dir.join("out/detect.json"));
+        assert_eq!(c.msgconv.unwrap().config_file, dir.join("msgconv.txt"));
+    }
+
+    #[test]
+    fn validate_consistency() {
+        let dir = temp_dir("validate");
+        std::fs::write(
+            dir.join("primary.txt"),
+            "[property]\ngie-unique-id=1\nprocess-mode=1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("secondary.txt"),
+            "[property]\ngie-unique-id=2\nprocess-mode=2\noperate-on-gie-id=3\n",
+        )
+        .unwrap();
+        let config = |s: &str| {
+            let mut c = parse(&format!(
+                "[[source]]\ntype = \"uri\"\nuri = \"file:///a.mp4\"\n\
+                 [[gie]]\nconfig_file = \"primary.txt\"\n{}",
+                s
+            ))
+            .unwrap();
+            c.resolve_paths(&dir);
+            c
+        };
+        let error = |s: &str| config(s).validate().unwrap_err().to_string();
+
+        config("").validate().unwrap();
+        assert!(AppConfig::default().validate().is_err());
+        assert_eq!(
+            error("[[source]]\ntype = \"uri\"\nuri = \"file:///b.mp4\"\n"),
+            "number of sources 2 exceeds streammux batch_size 1"
+        );
+        assert!(error("[[gie]]\nconfig_file = \"missing.txt\"\n")
+            .starts_with("gie config file does not exist"));
+        assert!(error("[[gie]]\nconfig_file = \"secondary.txt\"\n")
+            .starts_with("operate-on-gie-id=3 does not match"));
+        assert_eq!(
+            error("[export]\nmode = \"tracks\"\npath = \"a.csv\"\n"),
+            "tracks export mode needs json or protobuf format"
+        );
+        assert_eq!(
+            error("[export]\nbackfill_window = 5\n"),
+            "export backfill_window needs a tracker"
+        );
+        assert_eq!(
+            error("[[source]]\ntype = \"audio_uri\"\nuri = \"file:///a.wav\"\n[streammux]\nbatch_size = 2\n"),
+            "audio and video sources cannot be mixed"
+        );
+        assert_eq!(
+            error("[latency]\ninterval = 0\n"),
+            "latency interval must be greater than 0"
+        );
+    }
+}
@@ -0,0 +1,553 @@
+//! Source bins and runtime add/remove of sources connected to nvstreammux
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use anyhow::{anyhow, bail, Error};
+use examples::export::Broadcast;
+use examples::timestamp::NtpMode;
+use examples::{Record, SourceEvent, SourceState};
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use gst::prelude::*;
+
+#[link(name = "nvdsgst_helper")]
+extern "C" {
+    /// Let nvstreammux calculate NTP time from RTCP sender reports of the rtsp source
+    fn configure_source_for_ntp_sync(src_elem: *mut gst::ffi::GstElement);
+}
+
+/// Default RTSP latency of sources added from the control channel
+const DEFAULT_LATENCY: u32 = 100;
+/// Default RTSP reconnect interval of sources added from the control channel
+const DEFAULT_RECONNECT_INTERVAL: u32 = 10;
+
+fn default_num_buffers() -> i32 {
+    30
+}
+fn default_width() -> i32 {
+    1280
+}
+fn default_height() -> i32 {
+    720
+}
+fn default_latency() -> u32 {
+    DEFAULT_LATENCY
+}
+fn default_reconnect_interval() -> u32 {
+    DEFAULT_RECONNECT_INTERVAL
+}
+
+/// Source given by a subcommand or `[[source]]` tables of the config file
+#[derive(Debug, Clone, StructOpt, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+    /// inference image file
+    ImageFile {
+        #[structopt(
+            short,
+            long,
+            default_value = "/opt/nvidia/deepstream/deepstream/samples/streams/sample_720p.jpg"
+        )]
+        location: String,
+    },
+    /// inference video file
+    VideoFile {
+        #[structopt(
+            short,
+            long,
+            default_value = "/opt/nvidia/deepstream/deepstream/samples/streams/sample_720p.h264"
+        )]
+        location: String,
+        /// Number of buffers to flow in the pipeline
+        #[structopt(long, default_value = "30")]
+        #[serde(default = "default_num_buffers")]
+        num_buffers: i32,
+    },
+    /// inference v4l2 camera source
+    V4l2Src {
+        #[structopt(short, long, default_value = "/dev/video0")]
+        device: String,
+        /// Number of buffers to flow in the pipeline
+        #[structopt(long, default_value = "30")]
+        #[serde(default = "default_num_buffers")]
+        num_buffers: i32,
+
+        #[structopt(long, default_value = "1280")]
+        #[serde(default = "default_width")]
+        width: i32,
+        #[structopt(long, default_value = "720")]
+        #[serde(default = "default_height")]
+        height: i32,
+    },
+    /// inference any uri source (mp4, mkv, h265, mjpeg, rtsp) via nvurisrcbin, or
+    /// uridecodebin if it is not installed
+    Uri {
+        #[structopt(
+            short,
+            long,
+            default_value = "file:///opt/nvidia/deepstream/deepstream/samples/streams/sample_720p.mp4"
+        )]
+        uri: String,
+        /// RTSP jitterbuffer latency in milliseconds
+        #[structopt(long, default_value = "100")]
+        #[serde(default = "default_latency")]
+        latency: u32,
+        /// Seconds to wait before reconnecting a RTSP source after an error by nvurisrcbin.
+        /// 0 is disabled
+        #[structopt(long, default_value = "10")]
+        #[serde(default = "default_reconnect_interval")]
+        rtsp_reconnect_interval: u32,
+    },
+    /// inference audio of any uri source via nvurisrcbin, or uridecodebin if it is not
+    /// installed, with nvinferaudio
+    AudioUri {
+        #[structopt(
+            short,
+            long,
+            default_value = "file:///opt/nvidia/deepstream/deepstream/samples/streams/sonyc_mixed_audio.wav"
+        )]
+        uri: String,
+    },
+}
+
+impl Source {
+    /// Source from uri with default RTSP settings
+    pub fn from_uri(uri: &str) -> Self {
+        Source::Uri {
+            uri: uri.to_owned(),
+            latency: DEFAULT_LATENCY,
+            rtsp_reconnect_interval: DEFAULT_RECONNECT_INTERVAL,
+        }
+    }
+
+    /// Location of the source to record in lifecycle events
+    pub fn location(&self) -> &str {
+        match self {
+            Source::ImageFile { location } => location,
+            Source::VideoFile { location, .. } => location,
+            Source::V4l2Src { device, .. } => device,
+            Source::Uri { uri, .. } => uri,
+            Source::AudioUri { uri } => uri,
+        }
+    }
+
+    pub fn is_audio(&self) -> bool {
+        matches!(self, Source::AudioUri { .. })
+    }
+
+    /// Resolve relative file locations and `file://` uris against `base`
+    ///
+    /// `file://` uris need absolute paths, so a relative `base` is joined to the working dir.
+    pub fn resolve_paths(&mut self, base: &Path) {
+        match self {
+            Source::ImageFile { location } | Source::VideoFile { location, .. } => {
+                *location = base.join(&*location).to_string_lossy().into_owned();
+            }
+            Source::Uri { uri, .. } | Source::AudioUri { uri } => {
+                let path = match uri.strip_prefix("file://") {
+                    Some(path) if !Path::new(path).is_absolute() => path,
+                    _ => return,
+                };
+                let base = std::env::current_dir()
+                    .map(|d| d.join(base))
+                    .unwrap_or_else(|_| base.to_owned());
+                *uri = format!("file://{}", base.join(path).display());
+            }
+            Source::V4l2Src { .. } => {}
+        }
+    }
+}
+
+fn create_source(s: &Source, bin: &gst::Bin, ntp_mode: NtpMode) -> Result<gst::Element, Error> {
+    match s {
+        Source::ImageFile { location } => {
+            let src = gst::ElementFactory::make("filesrc").build()?;
+            let dec = gst::ElementFactory::make("jpegdec").build()?;
+            let vidconv = gst::ElementFactory::make("videoconvert").build()?;
+
+            src.set_property("location", location);
+            bin.add_many(&[&src, &dec, &vidconv])?;
+            gst::Element::link_many(&[&src, &dec, &vidconv])?;
+            Ok(vidconv)
+        }
+        Source::VideoFile {
+            location,
+            num_buffers,
+        } => {
+            let src = gst::ElementFactory::make("filesrc").build()?;
+            let parse = gst::ElementFactory::make("h264parse").build()?;
+            let dec = gst::ElementFactory::make("nvv4l2decoder").build()?;
+
+            src.set_property("location", location);
+
+            src.set_property("num-buffers", num_buffers);
+
+            bin.add_many(&[&src, &parse, &dec])?;
+            gst::Element::link_many(&[&src, &parse, &dec])?;
+            Ok(dec)
+        }
+        Source::V4l2Src {
+            device,
+            num_buffers,
+            width,
+            height,
+        } => {
+            let src = gst::ElementFactory::make("v4l2src").build()?;
+            let vidconv = gst::ElementFactory::make("videoconvert").build()?;
+
+            src.set_property("device", device);
+            src.set_property("num-buffers", num_buffers);
+
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("width", width)
+                .field("height", height)
+                .build();
+
+            bin.add_many(&[&src, &vidconv])?;
+            src.link_filtered(&vidconv, &caps)?;
+
+            Ok(vidconv)
+        }
+        Source::Uri {
+            uri,
+            latency,
+            rtsp_reconnect_interval,
+        } => {
+            let ntp_sync = ntp_mode == NtpMode::Rtcp && uri.starts_with("rtsp://");
+            let uribin =
+                create_uri_bin(uri, *latency, *rtsp_reconnect_interval, ntp_sync, "video/")?;
+            bin.add(&uribin)?;
+            Ok(uribin.upcast())
+        }
+        Source::AudioUri { uri } => {
+            let uribin: gst::Element =
+                create_uri_bin(uri, DEFAULT_LATENCY, 0, false, "audio/")?.upcast();
+            let audioconv = gst::ElementFactory::make("audioconvert").build()?;
+            let audioresample = gst::ElementFactory::make("audioresample").build()?;
+            bin.add_many(&[&uribin, &audioconv, &audioresample])?;
+            gst::Element::link_many(&[&uribin, &audioconv, &audioresample])?;
+            Ok(audioresample)
+        }
+    }
+}
+
+/// Create a source bin that decodes any uri (file, rtsp, http) into a single pad
+/// of the media type such as `video/` or `audio/`.
+///
+/// Prefer `nvurisrcbin` because it can reconnect rtsp sources after an error,
+/// and fallback to `uridecodebin` when it is not installed. Rtsp sources with
+/// `reconnect_interval` need `nvurisrcbin`, so they fail instead of losing reconnects.
+fn create_uri_bin(
+    uri: &str,
+    latency: u32,
+    reconnect_interval: u32,
+    ntp_sync: bool,
+    media: &'static str,
+) -> Result<gst::Bin, Error> {
+    let bin = gst::Bin::new(None);
+    let decodebin = if gst::ElementFactory::find("nvurisrcbin").is_some() {
+        let src = gst::ElementFactory::make("nvurisrcbin")
+            .property("uri", uri)
+            .build()?;
+        src.set_property("latency", latency);
+        src.set_property("rtsp-reconnect-interval", reconnect_interval);
+        src
+    } else {
+        if reconnect_interval > 0 && uri.starts_with("rtsp://") {
+            bail!(
+                "nvurisrcbin is not found, which is required to reconnect {}. \
+                 set rtsp_reconnect_interval to 0 to read it by uridecodebin",
+                uri
+            );
+        }
+        let src = gst::ElementFactory::make("uridecodebin")
+            .property("uri", uri)
+            .build()?;
+        src.connect("source-setup", false, move |args| {
+            // rtspsrc is created after the uri is resolved, so apply latency here.
+            if let Ok(source) = args[1].get::<gst::Element>() {
+                if source.has_property("latency", None) {
+                    source.set_property("latency", latency);
+                }
+            }
+            None
+        });
+        src
+    };
+    if ntp_sync {
+        unsafe { configure_source_for_ntp_sync(decodebin.as_ptr()) };
+    }
+    bin.add(&decodebin)?;
+
+    // the decoded pad appears after caps negotiation, so expose it through a ghost pad
+    let ghost = gst::GhostPad::new(Some("src"), gst::PadDirection::Src);
+    bin.add_pad(&ghost)?;
+    let ghost = ghost.downgrade();
+    decodebin.connect_pad_added(move |_, pad| {
+        let ghost = match ghost.upgrade() {
+            Some(ghost) => ghost,
+            None => return,
+        };
+        let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+        let is_media = caps
+            .structure(0)
+            .map_or(false, |s| s.name().starts_with(media));
+        if !is_media || ghost.target().is_some() {
+            return;
+        }
+        if let Err(e) = ghost.set_target(Some(pad)) {
+            log::error!("failed to link decoded pad {}: {}", pad.name(), e);
+        }
+    });
+    Ok(bin)
+}
+
+/// Create a bin of the source and nvvideoconvert that exposes a `src` pad for nvstreammux
+///
+/// Audio sources are linked to nvstreammux without conversion to NVMM memory.
+fn create_source_bin(id: u32, s: &Source, ntp_mode: NtpMode) -> Result<gst::Bin, Error> {
+    let bin = gst::Bin::new(Some(&format!("source-bin-{:02}", id)));
+    let srcbin = create_source(s, &bin, ntp_mode)?;
+    let last = if s.is_audio() {
+        srcbin
+    } else {
+        let nvvidconv = gst::ElementFactory::make("nvvideoconvert").build()?;
+        bin.add(&nvvidconv)?;
+        gst::Element::link_many(&[&srcbin, &nvvidconv])?;
+        nvvidconv
+    };
+
+    let src_pad = last.static_pad("src").expect("has not src pad");
+    let ghost = gst::GhostPad::with_target(Some("src"), &src_pad)?;
+    bin.add_pad(&ghost)?;
+    Ok(bin)
+}
+
+/// Commands to change sources while the pipeline is running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    /// add uri source
+    Add(String),
+    /// remove source by source_id
+    Remove(u32),
+}
+
+impl std::str::FromStr for Control {
+    type Err = Error;
+
+    /// Parse `add <uri>` or `remove <source_id>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("add"), Some(uri), None) => Ok(Control::Add(uri.to_owned())),
+            (Some("remove"), Some(id), None) => Ok(Control::Remove(id.parse()?)),
+            _ => bail!(
+                "unknown command {:?}, expect `add <uri>` or `remove <source_id>`",
+                s
+            ),
+        }
+    }
+}
+
+struct ManagedSource {
+    bin: gst::Bin,
+    sink_pad: gst::Pad,
+    location: String,
+}
+
+/// Add and remove source bins linked to the request pads of nvstreammux.
+///
+/// The index of `sink_N` pad is used as the source_id of frame meta.
+pub struct SourceManager {
+    pipeline: gst::Pipeline,
+    streammux: gst::Element,
+    sources: HashMap<u32, ManagedSource>,
+    next_id: u32,
+    max_sources: u32,
+    ntp_mode: NtpMode,
+    /// add uris from the control channel as audio sources
+    audio: bool,
+    sender: Broadcast,
+}
+
+impl SourceManager {
+    pub fn new(
+        pipeline: gst::Pipeline,
+        streammux: gst::Element,
+        max_sources: u32,
+        ntp_mode: NtpMode,
+        audio: bool,
+        sender: Broadcast,
+    ) -> Self {
+        Self {
+            pipeline,
+            streammux,
+            sources: HashMap::new(),
+            next_id: 0,
+            max_sources,
+            ntp_mode,
+            audio,
+            sender,
+        }
+    }
+
+    /// Add a source and link it to a new `sink_N` pad, returns the source_id
+    pub fn add(&mut self, s: &Source) -> Result<u32, Error> {
+        if self.sources.len() as u32 >= self.max_sources {
+            bail!("number of sources reached the limit {}", self.max_sources);
+        }
+        let id = self.next_id;
+        let bin = create_source_bin(id, s, self.ntp_mode)?;
+        self.pipeline.add(&bin)?;
+
+        let src_pad = bin.static_pad("src").expect("has not src pad");
+        let sink_pad = match self.streammux.request_pad_simple(&format!("sink_{}", id)) {
+            Some(pad) => pad,
+            None => {
+                self.pipeline.remove(&bin)?;
+                bail!("failed to request sink_{} pad of nvstreammux", id);
+            }
+        };
+        if let Err(e) = src_pad.link(&sink_pad) {
+            self.streammux.release_request_pad(&sink_pad);
+            self.pipeline.remove(&bin)?;
+            bail!("failed to link source {} to sink_{}: {:?}", id, id, e);
+        }
+        // no-op before the pipeline starts, otherwise catch up with the running pipeline
+        bin.sync_state_with_parent()?;
+
+        self.next_id += 1;
+        let location = s.location().to_owned();
+        log::info!("source {} added: {}", id, location);
+        self.notify(SourceEvent::new(id, SourceState::Added, &location));
+        self.sources.insert(
+            id,
+            ManagedSource {
+                bin,
+                sink_pad,
+                location,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Stop the source, release its `sink_N` pad and remove it from the pipeline
+    ///
+    /// Follow the runtime source add/delete sample of DeepStream: the pad receives
+    /// FlushStop and EOS so that nvstreammux does not wait for its data, and no flush
+    /// start is sent, which would also flush the other streams of the batch. When the
+    /// last source is removed, nvstreammux has only EOS pads and forwards EOS, which
+    /// ends the pipeline as when all sources reach the end.
+    pub fn remove(&mut self, id: u32) -> Result<(), Error> {
+        let s = self
+            .sources
+            .remove(&id)
+            .ok_or_else(|| anyhow!("source {} is not found", id))?;
+
+        s.bin.set_state(gst::State::Null)?;
+        // reset the pad only, the running time of the other pads is kept
+        s.sink_pad.send_event(gst::event::FlushStop::new(false));
+        // nvstreammux waits data of the pad until it receives EOS
+        if self.sources.is_empty() {
+            log::info!("last source {} is removed, the pipeline ends with EOS", id);
+        }
+        s.sink_pad.send_event(gst::event::Eos::new());
+        self.streammux.release_request_pad(&s.sink_pad);
+        self.pipeline.remove(&s.bin)?;
+
+        log::info!("source {} removed: {}", id, s.location);
+        self.notify(SourceEvent::new(id, SourceState::Removed, &s.location));
+        Ok(())
+    }
+
+    pub fn apply(&mut self, control: &Control) -> Result<(), Error> {
+        match control {
+            Control::Add(uri) if self.audio => self
+                .add(&Source::AudioUri {
+                    uri: uri.to_owned(),
+                })
+                .map(|_| ()),
+            Control::Add(uri) => self.add(&Source::from_uri(uri)).map(|_| ()),
+            Control::Remove(id) => self.remove(*id),
+        }
+    }
+
+    fn notify(&self, event: SourceEvent) {
+        if self.sender.send(Record::Source(event)).is_err() {
+            log::warn!("export channel is closed");
+        }
+    }
+}
+
+/// Read control commands from stdin line by line
+pub fn spawn_stdin_control(sender: Sender<Control>) {
+    std::thread::spawn(move || {
+        use std::io::BufRead;
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match line.parse() {
+                Ok(control) => {
+                    if sender.send(control).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_control() {
+        let parse = |s: &str| s.parse::<Control>();
+        assert_eq!(
+            parse("add rtsp://192.168.0.10:554/stream").unwrap(),
+            Control::Add("rtsp://192.168.0.10:554/stream".to_owned())
+        );
+        assert_eq!(parse("  remove   3 ").unwrap(), Control::Remove(3));
+        // bad ids
+        assert!(parse("remove -1").is_err());
+        assert!(parse("remove one").is_err());
+        assert!(parse("remove").is_err());
+        // unknown verbs and extra words
+        assert!(parse("delete 1").is_err());
+        assert!(parse("add a b").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn file_uris_are_made_absolute() {
+        let cwd = std::env::current_dir().unwrap();
+        let mut s = Source::from_uri("file://a.mp4");
+        s.resolve_paths(Path::new("configs"));
+        assert_eq!(
+            s.location(),
+            format!("file://{}", cwd.join("configs/a.mp4").display())
+        );
+        for uri in ["file:///opt/a.mp4", "rtsp://192.168.0.10:554/stream"] {
+            let mut s = Source::from_uri(uri);
+            s.resolve_paths(Path::new("configs"));
+            assert_eq!(s.location(), uri);
+        }
+        let mut s = Source::V4l2Src {
+            device: "/dev/video0".to_owned(),
+            num_buffers: 30,
+            width: 1280,
+            height: 720,
+        };
+        s.resolve_paths(Path::new("configs"));
+        assert_eq!(s.location(), "/dev/video0");
+    }
+}
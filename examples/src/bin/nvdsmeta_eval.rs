@@ -0,0 +1,142 @@
+//! Evaluate exported detections against ground truth
+//!
+//! Reads JSON Lines written by nvdsmeta_app and COCO or MOTChallenge ground truth,
+//! then prints precision/recall, mAP and MOTA/IDF1.
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use anyhow::{Context, Error};
+use examples::eval::{self, GroundTruth, Report};
+use examples::exporter::CocoOptions;
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GtFormat {
+    Coco,
+    Mot,
+}
+
+impl std::str::FromStr for GtFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "coco" => Ok(GtFormat::Coco),
+            "mot" => Ok(GtFormat::Mot),
+            _ => Err(format!(
+                "unknown ground truth format {:?}, expect coco or mot",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "nvdsmeta-eval",
+    about = "evaluate exported detections against ground truth"
+)]
+struct Opt {
+    /// Exported JSON Lines of nvdsmeta_app
+    #[structopt(parse(from_os_str))]
+    detections: PathBuf,
+
+    /// Ground truth, COCO annotations json or MOTChallenge gt.txt
+    #[structopt(parse(from_os_str))]
+    ground_truth: PathBuf,
+
+    /// Ground truth format `coco` or `mot`, guessed from the extension if omitted
+    #[structopt(long)]
+    gt_format: Option<GtFormat>,
+
+    /// Source id of the detections to evaluate
+    #[structopt(long, default_value = "0")]
+    source_id: u32,
+
+    /// COCO category id by class id of the detections, `class_id + 1` if omitted
+    #[structopt(long, use_delimiter = true)]
+    category_ids: Vec<i32>,
+
+    /// Class id of the detections evaluated against MOTChallenge ground truth
+    #[structopt(long, default_value = "0")]
+    class_id: i32,
+
+    /// Write the report as json
+    #[structopt(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+impl Opt {
+    fn gt_format(&self) -> GtFormat {
+        self.gt_format.unwrap_or_else(|| {
+            match self.ground_truth.extension().and_then(|e| e.to_str()) {
+                Some("txt") => GtFormat::Mot,
+                _ => GtFormat::Coco,
+            }
+        })
+    }
+}
+
+fn print_report(report: &Report) {
+    let d = &report.detection;
+    println!("frames: {}", report.num_frames);
+    println!(
+        "detection: precision {:.4} recall {:.4} mAP@.5 {:.4} mAP@[.5:.95] {:.4}",
+        d.precision, d.recall, d.map50, d.map
+    );
+    println!(
+        "{:>8} {:>8} {:>8} {:>9} {:>9} {:>8} {:>8}",
+        "class", "gt", "det", "precision", "recall", "AP50", "AP"
+    );
+    for (class_id, c) in d.classes.iter() {
+        println!(
+            "{:>8} {:>8} {:>8} {:>9.4} {:>9.4} {:>8.4} {:>8.4}",
+            class_id, c.num_gt, c.num_detections, c.precision, c.recall, c.ap50, c.ap
+        );
+    }
+    match report.tracking.as_ref() {
+        Some(t) => println!(
+            "tracking: MOTA {:.4} IDF1 {:.4} FP {} FN {} IDSW {}",
+            t.mota, t.idf1, t.false_positives, t.false_negatives, t.id_switches
+        ),
+        None => println!("tracking: skipped, no track ids"),
+    }
+}
+
+fn main() -> Result<(), Error> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let opt = Opt::from_args();
+    log::debug!("{:?}", opt);
+
+    let open = |p: &PathBuf| {
+        File::open(p)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open {}", p.display()))
+    };
+    let mut dets = eval::load_detections(open(&opt.detections)?, opt.source_id)
+        .with_context(|| format!("failed to load {}", opt.detections.display()))?;
+    let gt = match opt.gt_format() {
+        GtFormat::Coco => {
+            let coco = CocoOptions {
+                category_ids: opt.category_ids.clone(),
+                ..Default::default()
+            };
+            eval::map_classes(&mut dets, |c| coco.category_id(c));
+            GroundTruth::from_coco(open(&opt.ground_truth)?)
+        }
+        GtFormat::Mot => GroundTruth::from_mot(open(&opt.ground_truth)?, opt.class_id),
+    }
+    .with_context(|| format!("failed to load {}", opt.ground_truth.display()))?;
+    if dets.is_empty() {
+        log::warn!("no detections of source {}", opt.source_id);
+    }
+
+    let report = eval::evaluate(&gt, &dets);
+    print_report(&report);
+    if let Some(p) = opt.output.as_ref() {
+        let f = File::create(p).with_context(|| format!("failed to create {}", p.display()))?;
+        serde_json::to_writer_pretty(f, &report)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,7 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Meta(#[from] nvdsmeta_sys::Error),
+    #[error("failed to serialize a payload: {0}")]
+    Serialize(#[from] serde_json::Error),
+}
@@ -0,0 +1,1031 @@
+//! Offline accuracy evaluation of exported records against ground truth
+//!
+//! Detections and ground truth are matched by frame number of a single source and by
+//! class. COCO `image_id` is taken as the frame number (the default of
+//! [`crate::exporter::CocoExporter`]) and MOTChallenge frames are 1-based.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, BufRead};
+
+use nvdsmeta_sys::UNTRACKED_OBJECT_ID;
+use serde::{Deserialize, Serialize};
+
+use crate::{BBoxCorrds, Record};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("line {line}: {source}")]
+    Json {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("line {line}: {message}")]
+    Mot { line: usize, message: String },
+    #[error("invalid COCO ground truth: {0}")]
+    Coco(serde_json::Error),
+}
+
+/// Class of MOTChallenge ground truth evaluated as objects
+const MOT_PEDESTRIAN: i64 = 1;
+/// Classes of MOTChallenge ground truth which detections may match without penalty:
+/// person on vehicle, static person, distractor and reflection
+const MOT_DISTRACTORS: [i64; 4] = [2, 7, 8, 12];
+
+/// IoU thresholds of mAP@[.5:.95]
+const IOU_THRESHOLDS: [f32; 10] = [0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95];
+
+impl BBoxCorrds {
+    pub fn area(&self) -> f32 {
+        self.width.max(0.0) * self.height.max(0.0)
+    }
+
+    pub fn intersection(&self, other: &BBoxCorrds) -> f32 {
+        let w = (self.left + self.width).min(other.left + other.width) - self.left.max(other.left);
+        let h = (self.top + self.height).min(other.top + other.height) - self.top.max(other.top);
+        if w <= 0.0 || h <= 0.0 {
+            0.0
+        } else {
+            w * h
+        }
+    }
+
+    pub fn iou(&self, other: &BBoxCorrds) -> f32 {
+        let inter = self.intersection(other);
+        if inter == 0.0 {
+            return 0.0;
+        }
+        inter / (self.area() + other.area() - inter)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub class_id: i32,
+    pub track_id: Option<u64>,
+    pub bbox: BBoxCorrds,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct GtObject {
+    pub class_id: i32,
+    pub track_id: Option<u64>,
+    pub bbox: BBoxCorrds,
+    /// crowd or ignored region, detections matching it are neither true positive
+    /// nor false positive
+    pub ignore: bool,
+    /// COCO `iscrowd` region which any number of detections can match
+    pub crowd: bool,
+}
+
+impl GtObject {
+    /// IoU as COCO evaluation, crowd regions use the area of the detection as the union
+    pub fn match_iou(&self, det: &BBoxCorrds) -> f32 {
+        if self.crowd {
+            let area = det.area();
+            if area > 0.0 {
+                self.bbox.intersection(det) / area
+            } else {
+                0.0
+            }
+        } else {
+            self.bbox.iou(det)
+        }
+    }
+}
+
+/// Objects by frame number
+pub type Frames<T> = BTreeMap<i64, Vec<T>>;
+
+/// Map class ids of detections, e.g. to COCO categories, and drop unmapped ones
+pub fn map_classes<F: Fn(i32) -> Option<i32>>(dets: &mut Frames<Detection>, f: F) {
+    for v in dets.values_mut() {
+        let mapped = std::mem::take(v)
+            .into_iter()
+            .filter_map(|mut d| {
+                d.class_id = f(d.class_id)?;
+                Some(d)
+            })
+            .collect();
+        *v = mapped;
+    }
+}
+
+/// Load detections of the source from exported JSON Lines
+pub fn load_detections<R: BufRead>(r: R, source_id: u32) -> Result<Frames<Detection>, Error> {
+    let mut frames = Frames::new();
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record = serde_json::from_str(&line).map_err(|source| Error::Json {
+            line: i + 1,
+            source,
+        })?;
+        let f = match record {
+            Record::Frame(f) if f.frame().source_id() == source_id => f,
+            _ => continue,
+        };
+        let dets = frames.entry(f.frame().frame_num() as i64).or_default();
+        dets.extend(f.objects().iter().map(|o| Detection {
+            class_id: o.class_id,
+            track_id: (o.object_id != UNTRACKED_OBJECT_ID).then_some(o.object_id),
+            // objects tracked without a detection have only the tracker bbox
+            bbox: o.bbox().clone(),
+            score: o.confidence,
+        }));
+    }
+    Ok(frames)
+}
+
+/// Ground truth of a single source
+#[derive(Debug, Clone, Default)]
+pub struct GroundTruth {
+    /// frames annotated, detections on other frames are not evaluated
+    pub frames: BTreeSet<i64>,
+    pub objects: Frames<GtObject>,
+}
+
+#[derive(Deserialize)]
+struct CocoImage {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct CocoAnnotation {
+    image_id: i64,
+    category_id: i32,
+    bbox: [f32; 4],
+    #[serde(default)]
+    iscrowd: u8,
+}
+
+#[derive(Deserialize)]
+struct CocoGt {
+    #[serde(default)]
+    images: Vec<CocoImage>,
+    annotations: Vec<CocoAnnotation>,
+}
+
+impl GroundTruth {
+    /// Load COCO annotations, `image_id` is the frame number
+    pub fn from_coco<R: io::Read>(r: R) -> Result<Self, Error> {
+        let coco: CocoGt = serde_json::from_reader(r).map_err(Error::Coco)?;
+        let mut gt = GroundTruth::default();
+        gt.frames.extend(coco.images.iter().map(|i| i.id));
+        for a in coco.annotations {
+            gt.frames.insert(a.image_id);
+            gt.objects.entry(a.image_id).or_default().push(GtObject {
+                class_id: a.category_id,
+                track_id: None,
+                bbox: BBoxCorrds {
+                    left: a.bbox[0],
+                    top: a.bbox[1],
+                    width: a.bbox[2],
+                    height: a.bbox[3],
+                },
+                ignore: a.iscrowd != 0,
+                crowd: a.iscrowd != 0,
+            });
+        }
+        Ok(gt)
+    }
+
+    /// Load MOTChallenge `gt.txt` as objects of `class_id`, the class of detections
+    /// to evaluate.
+    ///
+    /// `frame,id,left,top,width,height,conf,class,visibility` where conf 0 marks ignored
+    /// objects. If the class column is given, pedestrians (1) are evaluated, distractors
+    /// (2, 7, 8, 12) are ignored and the others such as vehicles are skipped.
+    /// Every frame between the first and the last annotated frame is evaluated.
+    pub fn from_mot<R: BufRead>(r: R, class_id: i32) -> Result<Self, Error> {
+        let mut gt = GroundTruth::default();
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let err = |message: String| Error::Mot {
+                line: i + 1,
+                message,
+            };
+            let cols = line
+                .split(',')
+                .map(|c| c.trim().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| err(e.to_string()))?;
+            if cols.len() < 6 {
+                return Err(err(format!(
+                    "expect at least 6 columns, got {}",
+                    cols.len()
+                )));
+            }
+            let frame = cols[0] as i64 - 1;
+            let distractor = match cols.get(7).map(|c| *c as i64) {
+                None | Some(MOT_PEDESTRIAN) => false,
+                Some(c) if MOT_DISTRACTORS.contains(&c) => true,
+                Some(_) => continue,
+            };
+            gt.objects.entry(frame).or_default().push(GtObject {
+                class_id,
+                track_id: Some(cols[1] as u64),
+                bbox: BBoxCorrds {
+                    left: cols[2] as f32,
+                    top: cols[3] as f32,
+                    width: cols[4] as f32,
+                    height: cols[5] as f32,
+                },
+                ignore: distractor || cols.get(6) == Some(&0.0),
+                crowd: false,
+            });
+        }
+        if let (Some(first), Some(last)) = (gt.objects.keys().next(), gt.objects.keys().last()) {
+            gt.frames.extend(*first..=*last);
+        }
+        Ok(gt)
+    }
+}
+
+/// Minimum cost assignment, returns the assigned column of each row
+#[allow(clippy::needless_range_loop)]
+pub fn hungarian(cost: &[Vec<f64>]) -> Vec<Option<usize>> {
+    let n = cost.len();
+    let m = cost.first().map_or(0, |r| r.len());
+    if n == 0 || m == 0 {
+        return vec![None; n];
+    }
+    if n > m {
+        let transposed = (0..m)
+            .map(|j| (0..n).map(|i| cost[i][j]).collect())
+            .collect::<Vec<Vec<f64>>>();
+        let mut result = vec![None; n];
+        for (j, i) in hungarian(&transposed).into_iter().enumerate() {
+            if let Some(i) = i {
+                result[i] = Some(j);
+            }
+        }
+        return result;
+    }
+    // potentials method with 1-based rows and columns, column 0 is a sentinel
+    let mut u = vec![0.0; n + 1];
+    let mut v = vec![0.0; m + 1];
+    let mut p = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+    for i in 1..=n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if !used[j] {
+                    let cur = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                    if cur < minv[j] {
+                        minv[j] = cur;
+                        way[j] = j0;
+                    }
+                    if minv[j] < delta {
+                        delta = minv[j];
+                        j1 = j;
+                    }
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+    let mut result = vec![None; n];
+    for j in 1..=m {
+        if p[j] != 0 {
+            result[p[j] - 1] = Some(j - 1);
+        }
+    }
+    result
+}
+
+/// Pairs of (gt index, detection index) maximizing total IoU above the threshold
+fn match_by_iou(gts: &[&BBoxCorrds], dets: &[&BBoxCorrds], threshold: f32) -> Vec<(usize, usize)> {
+    let cost = gts
+        .iter()
+        .map(|g| {
+            dets.iter()
+                .map(|d| {
+                    let iou = g.iou(d);
+                    if iou >= threshold {
+                        1.0 - iou as f64
+                    } else {
+                        // large enough not to be preferred over any valid pair
+                        1e6
+                    }
+                })
+                .collect()
+        })
+        .collect::<Vec<Vec<f64>>>();
+    hungarian(&cost)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(g, d)| d.map(|d| (g, d)))
+        .filter(|(g, d)| gts[*g].iou(dets[*d]) >= threshold)
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClassReport {
+    pub num_gt: usize,
+    pub num_detections: usize,
+    /// at IoU 0.5
+    pub precision: f64,
+    /// at IoU 0.5
+    pub recall: f64,
+    pub ap50: f64,
+    /// mean AP over IoU 0.5:0.95
+    pub ap: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DetectionReport {
+    pub precision: f64,
+    pub recall: f64,
+    pub map50: f64,
+    /// mAP@[.5:.95]
+    pub map: f64,
+    pub classes: BTreeMap<i32, ClassReport>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackingReport {
+    pub num_gt: usize,
+    pub false_positives: usize,
+    pub false_negatives: usize,
+    pub id_switches: usize,
+    pub mota: f64,
+    pub idtp: usize,
+    pub idfp: usize,
+    pub idfn: usize,
+    pub idf1: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Report {
+    pub num_frames: usize,
+    pub detection: DetectionReport,
+    /// `None` if detections or ground truth have no track ids
+    pub tracking: Option<TrackingReport>,
+}
+
+/// true positive flags of detections sorted by score and the number of valid gt
+fn match_class(
+    gt: &GroundTruth,
+    dets: &Frames<Detection>,
+    class_id: i32,
+    threshold: f32,
+) -> (Vec<(f32, bool)>, usize) {
+    let mut results = vec![];
+    let mut num_gt = 0;
+    for frame in gt.frames.iter() {
+        let gts = gt
+            .objects
+            .get(frame)
+            .map(|v| {
+                v.iter()
+                    .filter(|g| g.class_id == class_id)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        num_gt += gts.iter().filter(|g| !g.ignore).count();
+        let mut ds = dets
+            .get(frame)
+            .map(|v| {
+                v.iter()
+                    .filter(|d| d.class_id == class_id)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        ds.sort_by(|a, b| b.score.total_cmp(&a.score));
+        // greedy matching in score order as COCO evaluation, a detection takes the
+        // best unmatched gt and falls back to ignored gt, crowd regions are never used up
+        let mut matched = vec![false; gts.len()];
+        for d in ds {
+            let best = |ignore: bool| {
+                gts.iter()
+                    .enumerate()
+                    .filter(|(i, g)| g.ignore == ignore && (g.crowd || !matched[*i]))
+                    .map(|(i, g)| (i, g.match_iou(&d.bbox)))
+                    .filter(|(_, iou)| *iou >= threshold)
+                    // the first of the same IoU as COCO
+                    .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))
+            };
+            match best(false).or_else(|| best(true)) {
+                Some((i, _)) => {
+                    matched[i] = true;
+                    if !gts[i].ignore {
+                        results.push((d.score, true));
+                    }
+                }
+                None => results.push((d.score, false)),
+            }
+        }
+    }
+    results.sort_by(|a, b| b.0.total_cmp(&a.0));
+    (results, num_gt)
+}
+
+/// 101 point interpolated AP
+fn average_precision(results: &[(f32, bool)], num_gt: usize) -> f64 {
+    if num_gt == 0 {
+        return 0.0;
+    }
+    let mut tp = 0;
+    let mut curve = Vec::with_capacity(results.len());
+    for (i, (_, is_tp)) in results.iter().enumerate() {
+        if *is_tp {
+            tp += 1;
+        }
+        curve.push((tp as f64 / num_gt as f64, tp as f64 / (i + 1) as f64));
+    }
+    // make precision monotonically decreasing
+    for i in (0..curve.len().saturating_sub(1)).rev() {
+        curve[i].1 = curve[i].1.max(curve[i + 1].1);
+    }
+    (0..=100)
+        .map(|r| {
+            let r = r as f64 / 100.0;
+            curve
+                .iter()
+                .find(|(recall, _)| *recall >= r)
+                .map_or(0.0, |(_, p)| *p)
+        })
+        .sum::<f64>()
+        / 101.0
+}
+
+pub fn evaluate_detection(gt: &GroundTruth, dets: &Frames<Detection>) -> DetectionReport {
+    let classes = gt
+        .objects
+        .values()
+        .flatten()
+        .map(|g| g.class_id)
+        .collect::<BTreeSet<_>>();
+    let mut report = DetectionReport::default();
+    let (mut total_tp, mut total_det, mut total_gt) = (0, 0, 0);
+    for class_id in classes {
+        let mut c = ClassReport::default();
+        let mut aps = vec![];
+        for threshold in IOU_THRESHOLDS {
+            let (results, num_gt) = match_class(gt, dets, class_id, threshold);
+            let ap = average_precision(&results, num_gt);
+            if threshold == 0.5 {
+                let tp = results.iter().filter(|(_, t)| *t).count();
+                c.num_gt = num_gt;
+                c.num_detections = results.len();
+                c.precision = ratio(tp, results.len());
+                c.recall = ratio(tp, num_gt);
+                c.ap50 = ap;
+                total_tp += tp;
+                total_det += results.len();
+                total_gt += num_gt;
+            }
+            aps.push(ap);
+        }
+        c.ap = aps.iter().sum::<f64>() / aps.len() as f64;
+        report.classes.insert(class_id, c);
+    }
+    let n = report.classes.len().max(1) as f64;
+    report.map50 = report.classes.values().map(|c| c.ap50).sum::<f64>() / n;
+    report.map = report.classes.values().map(|c| c.ap).sum::<f64>() / n;
+    report.precision = ratio(total_tp, total_det);
+    report.recall = ratio(total_tp, total_gt);
+    report
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if b == 0 {
+        0.0
+    } else {
+        a as f64 / b as f64
+    }
+}
+
+/// CLEAR MOT and identity metrics at IoU 0.5 summed over classes of the ground truth
+///
+/// Detections are matched to ground truth of the same class. Detections matching
+/// ignored ground truth are removed before evaluation as the MOTChallenge devkit.
+pub fn evaluate_tracking(gt: &GroundTruth, dets: &Frames<Detection>) -> Option<TrackingReport> {
+    let has_gt_ids = gt.objects.values().flatten().any(|g| g.track_id.is_some());
+    let has_det_ids = dets.values().flatten().any(|d| d.track_id.is_some());
+    if !has_gt_ids || !has_det_ids {
+        return None;
+    }
+    let classes = gt
+        .objects
+        .values()
+        .flatten()
+        .map(|g| g.class_id)
+        .collect::<BTreeSet<_>>();
+    let mut report = TrackingReport::default();
+    for class_id in classes {
+        track_class(gt, dets, class_id, &mut report);
+    }
+    report.mota = 1.0
+        - ratio(
+            report.false_negatives + report.false_positives + report.id_switches,
+            report.num_gt,
+        );
+    report.idf1 = ratio(2 * report.idtp, 2 * report.idtp + report.idfp + report.idfn);
+    Some(report)
+}
+
+/// Add counts of the class to the report
+fn track_class(
+    gt: &GroundTruth,
+    dets: &Frames<Detection>,
+    class_id: i32,
+    report: &mut TrackingReport,
+) {
+    // last detection id matched to each gt id
+    let mut last_match: HashMap<u64, u64> = HashMap::new();
+    // frames where a gt id and a detection id overlap
+    let mut overlaps: HashMap<(u64, u64), usize> = HashMap::new();
+    let mut gt_counts: HashMap<u64, usize> = HashMap::new();
+    let mut det_counts: HashMap<u64, usize> = HashMap::new();
+
+    let empty = vec![];
+    for frame in gt.frames.iter() {
+        let class_gts = gt
+            .objects
+            .get(frame)
+            .unwrap_or(&empty)
+            .iter()
+            .filter(|g| g.class_id == class_id && (g.ignore || g.track_id.is_some()))
+            .collect::<Vec<_>>();
+        let ds = dets
+            .get(frame)
+            .map(|v| {
+                v.iter()
+                    .filter(|d| d.class_id == class_id && d.track_id.is_some())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        // drop detections which are assigned to ignored gt
+        let all_boxes = class_gts.iter().map(|g| &g.bbox).collect::<Vec<_>>();
+        let det_boxes = ds.iter().map(|d| &d.bbox).collect::<Vec<_>>();
+        let mut ignored = vec![false; ds.len()];
+        for (gi, di) in match_by_iou(&all_boxes, &det_boxes, 0.5) {
+            ignored[di] = class_gts[gi].ignore;
+        }
+        let ds = ds
+            .into_iter()
+            .zip(ignored)
+            .filter_map(|(d, ignored)| if ignored { None } else { Some(d) })
+            .collect::<Vec<_>>();
+        let gts = class_gts
+            .into_iter()
+            .filter(|g| !g.ignore)
+            .collect::<Vec<_>>();
+
+        for g in gts.iter() {
+            *gt_counts.entry(g.track_id.unwrap()).or_default() += 1;
+        }
+        for d in ds.iter() {
+            *det_counts.entry(d.track_id.unwrap()).or_default() += 1;
+        }
+
+        let gt_boxes = gts.iter().map(|g| &g.bbox).collect::<Vec<_>>();
+        let det_boxes = ds.iter().map(|d| &d.bbox).collect::<Vec<_>>();
+        for (gi, di) in match_by_iou(&gt_boxes, &det_boxes, 0.5) {
+            let (g, d) = (gts[gi].track_id.unwrap(), ds[di].track_id.unwrap());
+            *overlaps.entry((g, d)).or_default() += 1;
+        }
+
+        // keep correspondences of the previous frame as CLEAR MOT
+        let mut gt_used = vec![false; gts.len()];
+        let mut det_used = vec![false; ds.len()];
+        for (gi, g) in gts.iter().enumerate() {
+            let prev = match last_match.get(&g.track_id.unwrap()) {
+                Some(prev) => *prev,
+                None => continue,
+            };
+            if let Some(di) = ds.iter().position(|d| d.track_id == Some(prev)) {
+                if !det_used[di] && g.bbox.iou(&ds[di].bbox) >= 0.5 {
+                    gt_used[gi] = true;
+                    det_used[di] = true;
+                }
+            }
+        }
+        let rest_gts = (0..gts.len()).filter(|i| !gt_used[*i]).collect::<Vec<_>>();
+        let rest_dets = (0..ds.len()).filter(|i| !det_used[*i]).collect::<Vec<_>>();
+        let rest_gt_boxes = rest_gts.iter().map(|i| &gts[*i].bbox).collect::<Vec<_>>();
+        let rest_det_boxes = rest_dets.iter().map(|i| &ds[*i].bbox).collect::<Vec<_>>();
+        for (gi, di) in match_by_iou(&rest_gt_boxes, &rest_det_boxes, 0.5) {
+            let (gi, di) = (rest_gts[gi], rest_dets[di]);
+            gt_used[gi] = true;
+            det_used[di] = true;
+            let (g, d) = (gts[gi].track_id.unwrap(), ds[di].track_id.unwrap());
+            if let Some(prev) = last_match.insert(g, d) {
+                if prev != d {
+                    report.id_switches += 1;
+                }
+            }
+        }
+        report.num_gt += gts.len();
+        report.false_negatives += gt_used.iter().filter(|u| !**u).count();
+        report.false_positives += det_used.iter().filter(|u| !**u).count();
+    }
+
+    // global one-to-one assignment of trajectories maximizing identity true positives
+    let gt_ids = gt_counts.keys().copied().collect::<Vec<_>>();
+    let det_ids = det_counts.keys().copied().collect::<Vec<_>>();
+    let cost = gt_ids
+        .iter()
+        .map(|g| {
+            det_ids
+                .iter()
+                .map(|d| -(overlaps.get(&(*g, *d)).copied().unwrap_or(0) as f64))
+                .collect()
+        })
+        .collect::<Vec<Vec<f64>>>();
+    let idtp = hungarian(&cost)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(gi, di)| di.map(|di| (gi, di)))
+        .map(|(gi, di)| {
+            overlaps
+                .get(&(gt_ids[gi], det_ids[di]))
+                .copied()
+                .unwrap_or(0)
+        })
+        .sum::<usize>();
+    report.idtp += idtp;
+    report.idfn += gt_counts.values().sum::<usize>() - idtp;
+    report.idfp += det_counts.values().sum::<usize>() - idtp;
+}
+
+pub fn evaluate(gt: &GroundTruth, dets: &Frames<Detection>) -> Report {
+    Report {
+        num_frames: gt.frames.len(),
+        detection: evaluate_detection(gt, dets),
+        tracking: evaluate_tracking(gt, dets),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(left: f32, top: f32, width: f32, height: f32) -> BBoxCorrds {
+        BBoxCorrds {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn detections_use_the_tracker_bbox_without_detection() {
+        use crate::tests::{frame, object};
+        let mut shadow = object(0, 3, [0.0; 4], 0.0);
+        shadow.tracker_bbox_info = Some(bbox(10.0, 20.0, 30.0, 40.0));
+        let mut detected = object(0, 4, [1.0, 2.0, 3.0, 4.0], 0.5);
+        detected.tracker_bbox_info = Some(bbox(5.0, 6.0, 7.0, 8.0));
+        let untracked = object(1, UNTRACKED_OBJECT_ID, [1.0, 1.0, 1.0, 1.0], 0.5);
+        let lines = [
+            Record::Frame(frame(0, 2, vec![shadow, detected, untracked])),
+            Record::Frame(frame(1, 2, vec![object(0, 9, [1.0; 4], 0.5)])),
+        ]
+        .iter()
+        .map(|r| serde_json::to_string(r).unwrap())
+        .collect::<Vec<_>>()
+        .join("\n");
+        let frames = load_detections(lines.as_bytes(), 0).unwrap();
+        let dets = frames[&2]
+            .iter()
+            .map(|d| {
+                (
+                    d.track_id,
+                    [d.bbox.left, d.bbox.top, d.bbox.width, d.bbox.height],
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dets,
+            [
+                (Some(3), [10.0, 20.0, 30.0, 40.0]),
+                (Some(4), [1.0, 2.0, 3.0, 4.0]),
+                (None, [1.0, 1.0, 1.0, 1.0]),
+            ]
+        );
+        assert_eq!(frames.len(), 1);
+    }
+
+    fn gt_object(track_id: u64, bbox: BBoxCorrds) -> GtObject {
+        GtObject {
+            class_id: 0,
+            track_id: Some(track_id),
+            bbox,
+            ignore: false,
+            crowd: false,
+        }
+    }
+
+    fn det(class_id: i32, track_id: u64, bbox: BBoxCorrds, score: f32) -> Detection {
+        Detection {
+            class_id,
+            track_id: Some(track_id),
+            bbox,
+            score,
+        }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn iou_of_boxes() {
+        let a = bbox(0.0, 0.0, 10.0, 10.0);
+        // 9x10 intersection over 100 + 100 - 90
+        assert_close(a.iou(&bbox(1.0, 0.0, 10.0, 10.0)) as f64, 90.0 / 110.0);
+        assert_eq!(a.iou(&bbox(10.0, 0.0, 10.0, 10.0)), 0.0);
+        let crowd = GtObject {
+            crowd: true,
+            ..gt_object(0, bbox(0.0, 0.0, 100.0, 100.0))
+        };
+        // the detection inside the crowd region
+        assert_eq!(crowd.match_iou(&bbox(10.0, 10.0, 10.0, 10.0)), 1.0);
+        assert_close(crowd.match_iou(&bbox(95.0, 0.0, 10.0, 10.0)) as f64, 0.5);
+    }
+
+    #[test]
+    fn hungarian_square() {
+        // 1 + 2 + 2 is the unique minimum
+        let cost = vec![
+            vec![4.0, 1.0, 3.0],
+            vec![2.0, 0.0, 5.0],
+            vec![3.0, 2.0, 2.0],
+        ];
+        assert_eq!(hungarian(&cost), vec![Some(1), Some(0), Some(2)]);
+    }
+
+    #[test]
+    fn hungarian_rectangular() {
+        assert_eq!(hungarian(&[vec![3.0, 1.0, 2.0]]), vec![Some(1)]);
+        assert_eq!(
+            hungarian(&[vec![5.0], vec![1.0], vec![3.0]]),
+            vec![None, Some(0), None]
+        );
+        assert_eq!(
+            hungarian(&[vec![1.0, 9.0], vec![2.0, 9.0], vec![9.0, 1.0]]),
+            vec![Some(0), None, Some(1)]
+        );
+        assert_eq!(hungarian(&[]), vec![]);
+        assert_eq!(hungarian(&[vec![], vec![]]), vec![None, None]);
+    }
+
+    #[test]
+    fn average_precision_101_points() {
+        // recall/precision 0.5/1, 0.5/0.5, 1/0.67: recall 0..=0.5 at precision 1 and
+        // 0.51..=1 at precision 2/3
+        let results = [(0.9, true), (0.8, false), (0.7, true)];
+        assert_close(
+            average_precision(&results, 2),
+            (51.0 + 50.0 * 2.0 / 3.0) / 101.0,
+        );
+        assert_close(average_precision(&[(0.9, true), (0.8, true)], 2), 1.0);
+        // half of gt is never found
+        assert_close(average_precision(&[(0.9, true)], 2), 51.0 / 101.0);
+        assert_eq!(average_precision(&[], 0), 0.0);
+    }
+
+    fn single_frame(gts: Vec<GtObject>, dets: Vec<Detection>) -> (GroundTruth, Frames<Detection>) {
+        let mut gt = GroundTruth::default();
+        gt.frames.insert(0);
+        gt.objects.insert(0, gts);
+        (gt, Frames::from([(0, dets)]))
+    }
+
+    #[test]
+    fn crowd_regions_absorb_detections() {
+        let crowd = GtObject {
+            ignore: true,
+            crowd: true,
+            ..gt_object(0, bbox(100.0, 0.0, 100.0, 100.0))
+        };
+        let (gt, dets) = single_frame(
+            vec![gt_object(0, bbox(0.0, 0.0, 10.0, 10.0)), crowd],
+            vec![
+                det(0, 1, bbox(0.0, 0.0, 10.0, 10.0), 0.9),
+                det(0, 2, bbox(110.0, 10.0, 10.0, 10.0), 0.8),
+                det(0, 3, bbox(150.0, 10.0, 10.0, 10.0), 0.7),
+                det(0, 4, bbox(300.0, 300.0, 10.0, 10.0), 0.6),
+            ],
+        );
+        assert_eq!(
+            match_class(&gt, &dets, 0, 0.5),
+            (vec![(0.9, true), (0.6, false)], 1)
+        );
+        let report = evaluate_detection(&gt, &dets);
+        let c = &report.classes[&0];
+        assert_eq!((c.num_gt, c.num_detections), (1, 2));
+        assert_close(c.precision, 0.5);
+        assert_close(c.recall, 1.0);
+        assert_close(c.ap50, 1.0);
+    }
+
+    #[test]
+    fn valid_gt_is_preferred_to_ignored() {
+        let ignored = GtObject {
+            ignore: true,
+            ..gt_object(0, bbox(1.0, 0.0, 10.0, 10.0))
+        };
+        let on_ignored = bbox(1.0, 0.0, 10.0, 10.0);
+        let (gt, dets) = single_frame(
+            vec![gt_object(0, bbox(0.0, 0.0, 10.0, 10.0)), ignored],
+            vec![
+                det(0, 1, on_ignored.clone(), 0.9),
+                det(0, 2, on_ignored.clone(), 0.8),
+                det(0, 3, on_ignored, 0.7),
+            ],
+        );
+        // IoU 0.82 with the valid gt, then the ignored gt, which is used up
+        assert_eq!(
+            match_class(&gt, &dets, 0, 0.5),
+            (vec![(0.9, true), (0.7, false)], 1)
+        );
+        // IoU 0.82 is below 0.85, the first detection falls back to the ignored gt
+        assert_eq!(
+            match_class(&gt, &dets, 0, 0.85),
+            (vec![(0.8, false), (0.7, false)], 1)
+        );
+    }
+
+    #[test]
+    fn detection_is_per_class() {
+        let (gt, dets) = single_frame(
+            vec![gt_object(0, bbox(0.0, 0.0, 10.0, 10.0))],
+            vec![
+                det(1, 1, bbox(0.0, 0.0, 10.0, 10.0), 0.9),
+                det(0, 2, bbox(0.0, 0.0, 10.0, 10.0), 0.5),
+            ],
+        );
+        let report = evaluate_detection(&gt, &dets);
+        assert_eq!(report.classes.keys().copied().collect::<Vec<_>>(), vec![0]);
+        assert_close(report.precision, 1.0);
+        assert_close(report.map50, 1.0);
+        // mean over IoU 0.5:0.95 of exact boxes
+        assert_close(report.map, 1.0);
+    }
+
+    /// gt 1 at P and gt 2 at Q in frames 0-3. The detection of gt 1 changes id from
+    /// 10 to 30 at frame 2 and gt 2 is missed at frame 3 where a false positive appears.
+    /// Detections of class 1 are not evaluated against gt of class 0.
+    fn tracking_scene() -> (GroundTruth, Frames<Detection>) {
+        let p = bbox(0.0, 0.0, 10.0, 10.0);
+        let q = bbox(50.0, 0.0, 10.0, 10.0);
+        let mut gt = GroundTruth::default();
+        let mut dets = Frames::new();
+        for frame in 0..4 {
+            gt.frames.insert(frame);
+            gt.objects.insert(
+                frame,
+                vec![gt_object(1, p.clone()), gt_object(2, q.clone())],
+            );
+            let mut ds = vec![
+                det(0, if frame < 2 { 10 } else { 30 }, p.clone(), 0.9),
+                det(1, 50, p.clone(), 0.9),
+            ];
+            if frame < 3 {
+                ds.push(det(0, 20, q.clone(), 0.9));
+            } else {
+                ds.push(det(0, 40, bbox(200.0, 200.0, 10.0, 10.0), 0.9));
+            }
+            dets.insert(frame, ds);
+        }
+        (gt, dets)
+    }
+
+    #[test]
+    fn clear_mot_and_idf1() {
+        let (gt, dets) = tracking_scene();
+        let t = evaluate_tracking(&gt, &dets).unwrap();
+        assert_eq!(t.num_gt, 8);
+        assert_eq!(t.id_switches, 1);
+        assert_eq!(t.false_negatives, 1);
+        assert_eq!(t.false_positives, 1);
+        // 1 - (1 + 1 + 1) / 8
+        assert_close(t.mota, 0.625);
+        // gt 1 is assigned to 10 or 30 for 2 frames, gt 2 to 20 for 3 frames
+        assert_eq!((t.idtp, t.idfn, t.idfp), (5, 3, 3));
+        // 2 * 5 / (2 * 5 + 3 + 3)
+        assert_close(t.idf1, 0.625);
+    }
+
+    #[test]
+    fn detections_on_ignored_gt_are_removed() {
+        let ignored = GtObject {
+            ignore: true,
+            ..gt_object(3, bbox(100.0, 0.0, 10.0, 10.0))
+        };
+        let (gt, dets) = single_frame(
+            vec![gt_object(1, bbox(0.0, 0.0, 10.0, 10.0)), ignored],
+            vec![
+                det(0, 10, bbox(0.0, 0.0, 10.0, 10.0), 0.9),
+                det(0, 60, bbox(101.0, 0.0, 10.0, 10.0), 0.9),
+            ],
+        );
+        let t = evaluate_tracking(&gt, &dets).unwrap();
+        assert_eq!((t.num_gt, t.false_positives, t.false_negatives), (1, 0, 0));
+        assert_eq!((t.idtp, t.idfp, t.idfn), (1, 0, 0));
+        assert_close(t.mota, 1.0);
+    }
+
+    #[test]
+    fn tracking_needs_ids() {
+        let (gt, mut dets) = tracking_scene();
+        dets.values_mut().flatten().for_each(|d| d.track_id = None);
+        assert!(evaluate_tracking(&gt, &dets).is_none());
+    }
+
+    #[test]
+    fn mot_ground_truth() {
+        let s = "\
+1,1,0,0,10,10,1,1,1.0
+1,2,20,0,10,10,1,3,1.0
+1,3,40,0,10,10,0,1,1.0
+3,4,60,0,10,10,1,7,0.5
+3,1,0,0,10,10,1,1,1.0
+";
+        let gt = GroundTruth::from_mot(s.as_bytes(), 2).unwrap();
+        assert_eq!(gt.frames.iter().copied().collect::<Vec<_>>(), vec![0, 1, 2]);
+        let summary = |frame| {
+            gt.objects[&frame]
+                .iter()
+                .map(|g| (g.class_id, g.track_id.unwrap(), g.ignore))
+                .collect::<Vec<_>>()
+        };
+        // the car of id 2 is skipped, conf 0 and static person are ignored
+        assert_eq!(summary(0), vec![(2, 1, false), (2, 3, true)]);
+        assert_eq!(summary(2), vec![(2, 4, true), (2, 1, false)]);
+
+        let gt = GroundTruth::from_mot("2,5,0,0,10,10".as_bytes(), 0).unwrap();
+        assert!(!gt.objects[&1][0].ignore);
+        assert!(matches!(
+            GroundTruth::from_mot("1,1,0,0".as_bytes(), 0),
+            Err(Error::Mot { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn coco_ground_truth_and_categories() {
+        let s = r#"{
+            "images": [{"id": 0}, {"id": 1}],
+            "annotations": [
+                {"image_id": 1, "category_id": 1, "bbox": [0, 0, 10, 10]},
+                {"image_id": 1, "category_id": 1, "bbox": [50, 0, 90, 90], "iscrowd": 1}
+            ]
+        }"#;
+        let gt = GroundTruth::from_coco(s.as_bytes()).unwrap();
+        assert_eq!(gt.frames.len(), 2);
+        let g = &gt.objects[&1];
+        assert!(!g[0].crowd && !g[0].ignore);
+        assert!(g[1].crowd && g[1].ignore);
+
+        let mut dets = Frames::from([(
+            1,
+            vec![
+                det(0, 1, bbox(0.0, 0.0, 10.0, 10.0), 0.9),
+                det(-1, 2, bbox(0.0, 0.0, 10.0, 10.0), 0.9),
+            ],
+        )]);
+        let coco = crate::exporter::CocoOptions::default();
+        map_classes(&mut dets, |c| coco.category_id(c));
+        assert_eq!(dets[&1].len(), 1);
+        assert_eq!(dets[&1][0].class_id, 1);
+        let report = evaluate_detection(&gt, &dets);
+        assert_close(report.classes[&1].ap50, 1.0);
+    }
+}
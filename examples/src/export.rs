@@ -0,0 +1,399 @@
+//! Write records on a dedicated thread through a bounded channel
+//!
+//! The streaming thread of the pipeline only pushes records to the channel,
+//! so export throughput does not depend on the bus polling of the main thread.
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::exporter::Exporter;
+use crate::Record;
+
+/// What to do when the export queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Block the streaming thread until the writer catches up
+    Block,
+    /// Drop the new record and count it
+    DropNewest,
+}
+
+impl std::str::FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(DropPolicy::Block),
+            "drop-newest" => Ok(DropPolicy::DropNewest),
+            _ => Err(format!(
+                "unknown drop policy {:?}, expect block or drop-newest",
+                s
+            )),
+        }
+    }
+}
+
+/// Counters shared between senders and the writer thread
+///
+/// `dropped` counts records dropped by a full queue and by [`Exporter::dropped`],
+/// `written` counts records taken by the exporter less those it dropped.
+#[derive(Debug, Default)]
+pub struct ExportMetrics {
+    written: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl ExportMetrics {
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Error on sending a record after the writer stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
+
+#[derive(Debug, Clone)]
+pub struct ExportSender {
+    sender: SyncSender<Record>,
+    policy: DropPolicy,
+    metrics: Arc<ExportMetrics>,
+}
+
+impl ExportSender {
+    pub fn send(&self, record: Record) -> Result<(), Closed> {
+        match self.policy {
+            DropPolicy::Block => self.sender.send(record).map_err(|_| Closed),
+            DropPolicy::DropNewest => match self.sender.try_send(record) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => {
+                    self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                    Ok(())
+                }
+                Err(TrySendError::Disconnected(_)) => Err(Closed),
+            },
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<ExportMetrics> {
+        &self.metrics
+    }
+}
+
+/// Send each record to every worker, such as the export file and publishers
+#[derive(Debug, Clone, Default)]
+pub struct Broadcast(Vec<ExportSender>);
+
+impl Broadcast {
+    pub fn new(senders: Vec<ExportSender>) -> Self {
+        Self(senders)
+    }
+
+    /// Fails only when all workers are stopped
+    pub fn send(&self, record: Record) -> Result<(), Closed> {
+        let (last, rest) = match self.0.split_last() {
+            Some(x) => x,
+            None => return Err(Closed),
+        };
+        let mut sent = false;
+        for s in rest {
+            sent |= s.send(record.clone()).is_ok();
+        }
+        sent |= last.send(record).is_ok();
+        if sent {
+            Ok(())
+        } else {
+            Err(Closed)
+        }
+    }
+}
+
+/// Handle of the writer thread
+pub struct ExportWorker {
+    handle: JoinHandle<io::Result<()>>,
+    stop: Arc<AtomicBool>,
+    metrics: Arc<ExportMetrics>,
+}
+
+impl ExportWorker {
+    /// Spawn a thread writing records by the exporter
+    pub fn spawn(
+        exporter: Box<dyn Exporter>,
+        capacity: usize,
+        policy: DropPolicy,
+    ) -> (ExportSender, Self) {
+        let (sender, receiver) = sync_channel(capacity);
+        let stop = Arc::new(AtomicBool::new(false));
+        let metrics = Arc::new(ExportMetrics::default());
+        let handle = {
+            let stop = stop.clone();
+            let metrics = metrics.clone();
+            std::thread::Builder::new()
+                .name("export".to_owned())
+                .spawn(move || write_loop(exporter, receiver, &stop, &metrics))
+                .expect("failed to spawn export thread")
+        };
+        let sender = ExportSender {
+            sender,
+            policy,
+            metrics: metrics.clone(),
+        };
+        (
+            sender,
+            Self {
+                handle,
+                stop,
+                metrics,
+            },
+        )
+    }
+
+    pub fn metrics(&self) -> &Arc<ExportMetrics> {
+        &self.metrics
+    }
+
+    /// Write queued records, flush the writer and wait for the thread to exit
+    pub fn finish(self) -> io::Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.handle.join().unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "export thread panicked",
+            ))
+        })
+    }
+}
+
+/// Records written by the exporter, those it reported by [`Exporter::dropped`] are
+/// moved from `written` to `dropped`
+struct WriteCount<'a> {
+    metrics: &'a ExportMetrics,
+    taken: u64,
+    dropped: u64,
+}
+
+impl WriteCount<'_> {
+    fn update(&mut self, exporter: &dyn Exporter) {
+        let n = exporter.dropped();
+        if n > self.dropped {
+            self.metrics
+                .dropped
+                .fetch_add(n - self.dropped, Ordering::Relaxed);
+            self.dropped = n;
+        }
+        self.metrics
+            .written
+            .store(self.taken.saturating_sub(self.dropped), Ordering::Relaxed);
+    }
+
+    fn write(&mut self, exporter: &mut Box<dyn Exporter>, record: Record) -> io::Result<()> {
+        exporter.write_record(&record)?;
+        self.taken += 1;
+        self.update(exporter.as_ref());
+        Ok(())
+    }
+}
+
+fn write_loop(
+    mut exporter: Box<dyn Exporter>,
+    receiver: Receiver<Record>,
+    stop: &AtomicBool,
+    metrics: &ExportMetrics,
+) -> io::Result<()> {
+    let mut count = WriteCount {
+        metrics,
+        taken: 0,
+        dropped: 0,
+    };
+    loop {
+        // senders may be kept by elements of the pipeline, so stop by the flag
+        if stop.load(Ordering::SeqCst) {
+            for record in receiver.try_iter() {
+                count.write(&mut exporter, record)?;
+            }
+            break;
+        }
+        match receiver.recv_timeout(Duration::from_millis(100)) {
+            Ok(record) => count.write(&mut exporter, record)?,
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    let result = exporter.finish();
+    // records held by the exporter may be dropped on finish
+    count.update(exporter.as_ref());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::frame;
+    use std::sync::mpsc::{channel, Sender};
+    use std::sync::Mutex;
+
+    fn record(frame_num: i32) -> Record {
+        Record::Frame(frame(0, frame_num, vec![]))
+    }
+
+    /// Exporter waiting for the gate before each write
+    struct Gated {
+        started: Sender<()>,
+        gate: Receiver<()>,
+        written: Arc<Mutex<Vec<i32>>>,
+        finished: Arc<AtomicBool>,
+    }
+
+    impl Exporter for Gated {
+        fn write_record(&mut self, record: &Record) -> io::Result<()> {
+            let _ = self.started.send(());
+            // a closed gate lets every write through
+            let _ = self.gate.recv();
+            if let Record::Frame(f) = record {
+                self.written.lock().unwrap().push(f.frame().frame_num());
+            }
+            Ok(())
+        }
+        fn finish(&mut self) -> io::Result<()> {
+            self.finished.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct Mock {
+        started: Receiver<()>,
+        gate: Sender<()>,
+        written: Arc<Mutex<Vec<i32>>>,
+        finished: Arc<AtomicBool>,
+    }
+
+    fn gated(capacity: usize, policy: DropPolicy) -> (ExportSender, ExportWorker, Mock) {
+        let (started, started_rx) = channel();
+        let (gate, gate_rx) = channel();
+        let written = Arc::new(Mutex::new(vec![]));
+        let finished = Arc::new(AtomicBool::new(false));
+        let exporter = Gated {
+            started,
+            gate: gate_rx,
+            written: written.clone(),
+            finished: finished.clone(),
+        };
+        let (sender, worker) = ExportWorker::spawn(Box::new(exporter), capacity, policy);
+        let mock = Mock {
+            started: started_rx,
+            gate,
+            written,
+            finished,
+        };
+        (sender, worker, mock)
+    }
+
+    #[test]
+    fn block_waits_for_the_writer() {
+        let (sender, worker, mock) = gated(1, DropPolicy::Block);
+        sender.send(record(0)).unwrap();
+        mock.started.recv().unwrap();
+        // record 0 is being written and record 1 fills the queue
+        sender.send(record(1)).unwrap();
+        let (done, done_rx) = channel();
+        let blocked = {
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                sender.send(record(2)).unwrap();
+                done.send(()).unwrap();
+            })
+        };
+        assert!(done_rx.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(mock.gate);
+        done_rx.recv().unwrap();
+        blocked.join().unwrap();
+        worker.finish().unwrap();
+        assert_eq!(*mock.written.lock().unwrap(), [0, 1, 2]);
+        assert_eq!(sender.metrics().dropped(), 0);
+        assert_eq!(sender.metrics().written(), 3);
+    }
+
+    #[test]
+    fn drop_newest_counts_dropped_records() {
+        let (sender, worker, mock) = gated(1, DropPolicy::DropNewest);
+        sender.send(record(0)).unwrap();
+        mock.started.recv().unwrap();
+        for i in 1..4 {
+            sender.send(record(i)).unwrap();
+        }
+        // record 1 is queued, 2 and 3 are dropped without blocking
+        assert_eq!(sender.metrics().dropped(), 2);
+        drop(mock.gate);
+        worker.finish().unwrap();
+        assert_eq!(*mock.written.lock().unwrap(), [0, 1]);
+        assert_eq!(sender.metrics().written(), 2);
+    }
+
+    #[test]
+    fn finish_drains_the_queue() {
+        let (sender, worker, mock) = gated(4, DropPolicy::Block);
+        for i in 0..4 {
+            sender.send(record(i)).unwrap();
+        }
+        mock.started.recv().unwrap();
+        // the stop flag is set while record 0 is being written
+        let finish = std::thread::spawn(move || worker.finish());
+        std::thread::sleep(Duration::from_millis(50));
+        drop(mock.gate);
+        finish.join().unwrap().unwrap();
+        assert_eq!(*mock.written.lock().unwrap(), [0, 1, 2, 3]);
+        assert!(mock.finished.load(Ordering::SeqCst));
+        assert_eq!(sender.send(record(4)), Err(Closed));
+    }
+
+    #[test]
+    fn broadcast_is_closed_when_every_worker_stopped() {
+        assert_eq!(Broadcast::default().send(record(0)), Err(Closed));
+        let (a, worker_a, mock_a) = gated(4, DropPolicy::Block);
+        let (b, worker_b, mock_b) = gated(4, DropPolicy::DropNewest);
+        drop((mock_a.gate, mock_b.gate));
+        let broadcast = Broadcast::new(vec![a, b]);
+        broadcast.send(record(0)).unwrap();
+        worker_a.finish().unwrap();
+        broadcast.send(record(1)).unwrap();
+        worker_b.finish().unwrap();
+        assert_eq!(broadcast.send(record(2)), Err(Closed));
+        assert_eq!(*mock_a.written.lock().unwrap(), [0]);
+        assert_eq!(*mock_b.written.lock().unwrap(), [0, 1]);
+    }
+
+    /// Exporter dropping every second record without an error
+    struct Lossy(u64);
+
+    impl Exporter for Lossy {
+        fn write_record(&mut self, _: &Record) -> io::Result<()> {
+            self.0 += 1;
+            Ok(())
+        }
+        fn dropped(&self) -> u64 {
+            self.0 / 2
+        }
+        fn finish(&mut self) -> io::Result<()> {
+            // the last odd record is dropped on finish
+            self.0 += self.0 % 2;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn records_dropped_by_the_exporter_are_not_written() {
+        let (sender, worker) = ExportWorker::spawn(Box::new(Lossy(0)), 8, DropPolicy::Block);
+        for i in 0..5 {
+            sender.send(record(i)).unwrap();
+        }
+        worker.finish().unwrap();
+        let metrics = sender.metrics();
+        assert_eq!((metrics.written(), metrics.dropped()), (2, 3));
+    }
+}
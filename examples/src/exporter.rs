@@ -0,0 +1,559 @@
+//! Export formats of records
+//!
+//! Every exporter writes records as they come, so long runs do not buffer
+//! results in memory. Formats other than JSON Lines and protobuf skip records but
+//! [`Record::Frame`].
+use std::io::{self, Write};
+use std::path::Path;
+
+use nvdsmeta_sys::UNTRACKED_OBJECT_ID;
+use serde::{Deserialize, Serialize};
+
+use crate::{FrameObjects, Record};
+
+pub trait Exporter: Send {
+    fn write_record(&mut self, record: &Record) -> io::Result<()>;
+
+    /// Number of records dropped without an error so far, such as by a disconnected publisher
+    fn dropped(&self) -> u64 {
+        0
+    }
+
+    /// Write trailing data and flush
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    /// One JSON object of [`Record`] per line
+    Json,
+    /// One row per object
+    Csv,
+    /// MOTChallenge `frame,id,bb_left,bb_top,bb_width,bb_height,conf,x,y,z`
+    Mot,
+    /// COCO detection results
+    Coco,
+    /// Length delimited protobuf of [`crate::proto::Record`]
+    Protobuf,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            "mot" => Ok(ExportFormat::Mot),
+            "coco" => Ok(ExportFormat::Coco),
+            "protobuf" => Ok(ExportFormat::Protobuf),
+            _ => Err(format!(
+                "unknown export format {:?}, expect json, csv, mot, coco or protobuf",
+                s
+            )),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// Guess the format from the file extension.
+    ///
+    /// `.json` is JSON Lines, so COCO results must be selected explicitly.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" | "jsonl" => Some(ExportFormat::Json),
+            "csv" => Some(ExportFormat::Csv),
+            "txt" => Some(ExportFormat::Mot),
+            "pb" => Some(ExportFormat::Protobuf),
+            _ => None,
+        }
+    }
+
+    /// `coco` is used by [`ExportFormat::Coco`] only
+    pub fn exporter<W: Write + Send + 'static>(
+        &self,
+        w: W,
+        coco: &CocoOptions,
+    ) -> Box<dyn Exporter> {
+        let w = io::BufWriter::new(w);
+        match self {
+            ExportFormat::Json => Box::new(JsonLinesExporter::new(w)),
+            ExportFormat::Csv => Box::new(CsvExporter::new(w)),
+            ExportFormat::Mot => Box::new(MotExporter::new(w)),
+            ExportFormat::Coco => Box::new(CocoExporter::with_options(w, coco.clone())),
+            ExportFormat::Protobuf => Box::new(ProtobufExporter::new(w)),
+        }
+    }
+}
+
+pub struct JsonLinesExporter<W> {
+    w: W,
+}
+
+impl<W: Write> JsonLinesExporter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w }
+    }
+}
+
+impl<W: Write + Send> Exporter for JsonLinesExporter<W> {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.w, record)?;
+        writeln!(&mut self.w)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Quote a field if it contains a delimiter, quote or line break
+fn csv_field(s: &str) -> std::borrow::Cow<str> {
+    if s.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
+
+pub struct CsvExporter<W> {
+    w: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvExporter<W> {
+    pub const HEADER: &'static str = "source_id,frame_num,timestamp,detection_index,class_id,object_id,label,confidence,left,top,width,height";
+
+    pub fn new(w: W) -> Self {
+        Self {
+            w,
+            header_written: false,
+        }
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            writeln!(&mut self.w, "{}", Self::HEADER)?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> Exporter for CsvExporter<W> {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        self.write_header()?;
+        let f = match record {
+            Record::Frame(f) => f,
+            _ => return Ok(()),
+        };
+        let frame = f.frame();
+        let timestamp = frame.timestamp().map(|t| t.to_string()).unwrap_or_default();
+        for o in f.objects() {
+            let b = o.bbox();
+            writeln!(
+                &mut self.w,
+                "{},{},{},{},{},{},{},{},{},{},{},{}",
+                frame.source_id(),
+                frame.frame_num(),
+                timestamp,
+                o.detection_index,
+                o.class_id,
+                o.object_id,
+                csv_field(&o.label),
+                o.confidence,
+                b.left,
+                b.top,
+                b.width,
+                b.height
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The header is written even without records
+    fn finish(&mut self) -> io::Result<()> {
+        self.write_header()?;
+        self.w.flush()
+    }
+}
+
+/// MOTChallenge format for a single sequence.
+///
+/// Frame numbers are 1-based and untracked objects have id -1.
+/// Records of all sources are written, so run with one source per file.
+pub struct MotExporter<W> {
+    w: W,
+}
+
+impl<W: Write> MotExporter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w }
+    }
+}
+
+impl<W: Write + Send> Exporter for MotExporter<W> {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let f = match record {
+            Record::Frame(f) => f,
+            _ => return Ok(()),
+        };
+        for o in f.objects() {
+            let id = if o.object_id == UNTRACKED_OBJECT_ID {
+                -1
+            } else {
+                o.object_id as i64
+            };
+            let b = o.bbox();
+            writeln!(
+                &mut self.w,
+                "{},{},{},{},{},{},{},-1,-1,-1",
+                f.frame().frame_num() + 1,
+                id,
+                b.left,
+                b.top,
+                b.width,
+                b.height,
+                o.confidence
+            )?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// How COCO results identify the image of a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CocoImageId {
+    /// Frame number, for a single source
+    #[default]
+    FrameNum,
+    /// `source_id << 32 | frame_num`, unique among sources
+    SourceFrame,
+}
+
+impl CocoImageId {
+    pub fn image_id(&self, source_id: u32, frame_num: i32) -> i64 {
+        match self {
+            CocoImageId::FrameNum => frame_num as i64,
+            CocoImageId::SourceFrame => ((source_id as i64) << 32) | frame_num as u32 as i64,
+        }
+    }
+}
+
+/// `image_id` and `category_id` of COCO results
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CocoOptions {
+    pub image_id: CocoImageId,
+    /// `category_id` by class id, `class_id + 1` if empty as categories of COCO
+    /// annotations start at 1
+    pub category_ids: Vec<i32>,
+}
+
+impl CocoOptions {
+    /// `None` if the class has no category, such as unmapped classes of -1
+    pub fn category_id(&self, class_id: i32) -> Option<i32> {
+        let index = usize::try_from(class_id).ok()?;
+        if self.category_ids.is_empty() {
+            Some(class_id + 1)
+        } else {
+            self.category_ids.get(index).copied()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CocoResult {
+    pub image_id: i64,
+    pub category_id: i32,
+    /// `[x, y, width, height]`
+    pub bbox: [f32; 4],
+    pub score: f32,
+}
+
+impl CocoResult {
+    /// Results of the frame, objects of classes without a category are skipped
+    pub fn from_frame<'a>(
+        f: &'a FrameObjects,
+        options: &'a CocoOptions,
+    ) -> impl Iterator<Item = CocoResult> + 'a {
+        let frame = f.frame();
+        let image_id = options
+            .image_id
+            .image_id(frame.source_id(), frame.frame_num());
+        f.objects().iter().filter_map(move |o| {
+            let b = o.bbox();
+            Some(CocoResult {
+                image_id,
+                category_id: options.category_id(o.class_id)?,
+                bbox: [b.left, b.top, b.width, b.height],
+                score: o.confidence,
+            })
+        })
+    }
+}
+
+/// COCO detection results, a JSON array written element by element
+pub struct CocoExporter<W> {
+    w: W,
+    options: CocoOptions,
+    count: usize,
+}
+
+impl<W: Write> CocoExporter<W> {
+    pub fn new(w: W) -> Self {
+        Self::with_options(w, CocoOptions::default())
+    }
+
+    pub fn with_options(w: W, options: CocoOptions) -> Self {
+        Self {
+            w,
+            options,
+            count: 0,
+        }
+    }
+}
+
+impl<W: Write + Send> Exporter for CocoExporter<W> {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let f = match record {
+            Record::Frame(f) => f,
+            _ => return Ok(()),
+        };
+        for r in CocoResult::from_frame(f, &self.options) {
+            let sep = if self.count == 0 { "[\n" } else { ",\n" };
+            self.w.write_all(sep.as_bytes())?;
+            serde_json::to_writer(&mut self.w, &r)?;
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let tail = if self.count == 0 { "[]\n" } else { "\n]\n" };
+        self.w.write_all(tail.as_bytes())?;
+        self.w.flush()
+    }
+}
+
+/// Length delimited protobuf records, read back by [`crate::proto::RecordReader`]
+pub struct ProtobufExporter<W> {
+    w: W,
+}
+
+impl<W: Write> ProtobufExporter<W> {
+    pub fn new(w: W) -> Self {
+        Self { w }
+    }
+}
+
+impl<W: Write + Send> Exporter for ProtobufExporter<W> {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        crate::proto::write_record(&mut self.w, record)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{frame, object};
+
+    fn export<E: Exporter>(mut e: E, records: &[Record]) -> E {
+        for r in records {
+            e.write_record(r).unwrap();
+        }
+        e.finish().unwrap();
+        e
+    }
+
+    fn frames() -> Vec<Record> {
+        let mut car = object(2, 7, [10.0, 20.0, 30.0, 40.0], 0.5);
+        car.label = "car, red".to_owned();
+        vec![
+            Record::Frame(frame(0, 0, vec![car])),
+            Record::Source(crate::SourceEvent::new(1, crate::SourceState::Added, "a")),
+            Record::Frame(frame(
+                1,
+                3,
+                vec![
+                    object(0, UNTRACKED_OBJECT_ID, [1.0, 2.0, 3.0, 4.0], 0.25),
+                    object(-1, 8, [5.0, 6.0, 7.0, 8.0], 0.75),
+                ],
+            )),
+        ]
+    }
+
+    #[test]
+    fn csv_rows() {
+        let e = export(CsvExporter::new(vec![]), &frames());
+        let s = String::from_utf8(e.w).unwrap();
+        let lines = s.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], CsvExporter::<Vec<u8>>::HEADER);
+        assert_eq!(lines[1], "0,0,,0,2,7,\"car, red\",0.5,10,20,30,40");
+        assert_eq!(
+            lines[2],
+            format!("1,3,,0,0,{},,0.25,1,2,3,4", UNTRACKED_OBJECT_ID)
+        );
+    }
+
+    #[test]
+    fn csv_header_without_records() {
+        let e = export(CsvExporter::new(vec![]), &[]);
+        assert_eq!(
+            String::from_utf8(e.w).unwrap(),
+            format!("{}\n", CsvExporter::<Vec<u8>>::HEADER)
+        );
+    }
+
+    #[test]
+    fn mot_rows() {
+        let e = export(MotExporter::new(vec![]), &frames());
+        assert_eq!(
+            String::from_utf8(e.w).unwrap(),
+            "1,7,10,20,30,40,0.5,-1,-1,-1\n\
+             4,-1,1,2,3,4,0.25,-1,-1,-1\n\
+             4,8,5,6,7,8,0.75,-1,-1,-1\n"
+        );
+    }
+
+    fn coco(options: CocoOptions) -> Vec<CocoResult> {
+        let e = export(CocoExporter::with_options(vec![], options), &frames());
+        serde_json::from_slice(&e.w).unwrap()
+    }
+
+    #[test]
+    fn coco_default_options() {
+        // 0-based class ids are 1-based categories, class -1 has no category
+        assert_eq!(
+            coco(CocoOptions::default()),
+            vec![
+                CocoResult {
+                    image_id: 0,
+                    category_id: 3,
+                    bbox: [10.0, 20.0, 30.0, 40.0],
+                    score: 0.5,
+                },
+                CocoResult {
+                    image_id: 3,
+                    category_id: 1,
+                    bbox: [1.0, 2.0, 3.0, 4.0],
+                    score: 0.25,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn coco_source_frame_and_categories() {
+        let results = coco(CocoOptions {
+            image_id: CocoImageId::SourceFrame,
+            category_ids: vec![5, 6],
+        });
+        // class 2 is out of category_ids
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].image_id, (1 << 32) + 3);
+        assert_eq!(results[0].category_id, 5);
+        assert_eq!(CocoImageId::SourceFrame.image_id(0, 3), 3);
+        assert_eq!(CocoImageId::FrameNum.image_id(2, 3), 3);
+    }
+
+    #[test]
+    fn coco_empty_array() {
+        let e = export(CocoExporter::new(vec![]), &[]);
+        assert_eq!(String::from_utf8(e.w).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn json_lines_round_trip() {
+        let e = export(JsonLinesExporter::new(vec![]), &frames());
+        let s = String::from_utf8(e.w).unwrap();
+        let records = s
+            .lines()
+            .map(|l| serde_json::from_str::<Record>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(records.len(), 3);
+        match &records[2] {
+            Record::Frame(f) => {
+                assert_eq!(f.frame().source_id(), 1);
+                assert_eq!(f.objects()[1].class_id, -1);
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+        assert!(matches!(records[1], Record::Source(_)));
+    }
+
+    #[test]
+    fn tracker_bbox_of_objects_without_detection() {
+        let tracker = |[left, top, width, height]: [f32; 4]| crate::BBoxCorrds {
+            left,
+            top,
+            width,
+            height,
+        };
+        // shadow tracked by the tracker without a detection in this frame
+        let mut shadow = object(0, 9, [0.0; 4], 0.0);
+        shadow.tracker_bbox_info = Some(tracker([11.0, 12.0, 13.0, 14.0]));
+        // detected and tracked, the detector bbox is kept
+        let mut detected = object(0, 10, [1.0, 2.0, 3.0, 4.0], 0.5);
+        detected.tracker_bbox_info = Some(tracker([5.0, 6.0, 7.0, 8.0]));
+        let backfilled = crate::PastObject {
+            frame_num: 0,
+            object_id: 11,
+            class_id: 0,
+            label: String::new(),
+            confidence: 0.25,
+            bbox: tracker([21.0, 22.0, 23.0, 24.0]),
+        }
+        .to_object(2);
+        let records = [Record::Frame(frame(
+            0,
+            0,
+            vec![shadow, detected, backfilled],
+        ))];
+
+        let e = export(MotExporter::new(vec![]), &records);
+        assert_eq!(
+            String::from_utf8(e.w).unwrap(),
+            "1,9,11,12,13,14,0,-1,-1,-1\n\
+             1,10,1,2,3,4,0.5,-1,-1,-1\n\
+             1,11,21,22,23,24,0.25,-1,-1,-1\n"
+        );
+        let e = export(CsvExporter::new(vec![]), &records);
+        let s = String::from_utf8(e.w).unwrap();
+        assert!(s.lines().nth(1).unwrap().ends_with(",11,12,13,14"));
+        let e = export(CocoExporter::new(vec![]), &records);
+        let results: Vec<CocoResult> = serde_json::from_slice(&e.w).unwrap();
+        let boxes = results.iter().map(|r| r.bbox).collect::<Vec<_>>();
+        assert_eq!(
+            boxes,
+            [
+                [11.0, 12.0, 13.0, 14.0],
+                [1.0, 2.0, 3.0, 4.0],
+                [21.0, 22.0, 23.0, 24.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn format_from_path() {
+        let format = |p: &str| ExportFormat::from_path(Path::new(p));
+        assert_eq!(format("a.jsonl"), Some(ExportFormat::Json));
+        assert_eq!(format("a.json"), Some(ExportFormat::Json));
+        assert_eq!(format("gt.txt"), Some(ExportFormat::Mot));
+        assert_eq!(format("a.pb"), Some(ExportFormat::Protobuf));
+        assert_eq!(format("a"), None);
+        assert_eq!("coco".parse(), Ok(ExportFormat::Coco));
+        assert!("yaml".parse::<ExportFormat>().is_err());
+    }
+}
@@ -0,0 +1,648 @@
+//! Typed model of nvinfer config file
+//!
+//! nvinfer config is an INI style file with `[property]`, `[class-attrs-all]`
+//! and `[class-attrs-N]` sections. Keys which are not modeled are kept in `others`
+//! so that a parsed config can be written back without losing them.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("line {line}: {message}")]
+    Parse { line: usize, message: String },
+}
+
+/// Conversion between a value in the config file and a rust type
+pub trait ConfigValue: Sized {
+    fn parse_value(s: &str) -> Result<Self, String>;
+    fn format_value(&self) -> String;
+}
+
+macro_rules! impl_config_value {
+    ($($t:ty),*) => {
+        $(impl ConfigValue for $t {
+            fn parse_value(s: &str) -> Result<Self, String> {
+                s.parse().map_err(|e| format!("{}", e))
+            }
+            fn format_value(&self) -> String {
+                self.to_string()
+            }
+        })*
+    };
+}
+
+impl_config_value!(i32, u32, f32, f64, String);
+
+impl ConfigValue for bool {
+    fn parse_value(s: &str) -> Result<Self, String> {
+        match s {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            _ => Err(format!("invalid boolean {:?}", s)),
+        }
+    }
+    fn format_value(&self) -> String {
+        if *self { "1" } else { "0" }.to_owned()
+    }
+}
+
+impl ConfigValue for PathBuf {
+    fn parse_value(s: &str) -> Result<Self, String> {
+        Ok(PathBuf::from(s))
+    }
+    fn format_value(&self) -> String {
+        self.display().to_string()
+    }
+}
+
+/// List separated by `;`
+impl<T: ConfigValue> ConfigValue for Vec<T> {
+    fn parse_value(s: &str) -> Result<Self, String> {
+        s.split(';')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(T::parse_value)
+            .collect()
+    }
+    fn format_value(&self) -> String {
+        self.iter()
+            .map(|x| x.format_value())
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
+/// Define a section struct whose fields are mapped to `kebab-case` keys
+macro_rules! config_section {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $($(#[$fmeta:meta])* $field:ident: $t:ty => $key:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, PartialEq)]
+        pub struct $name {
+            $($(#[$fmeta])* pub $field: Option<$t>,)*
+            /// keys which are not modeled
+            pub others: BTreeMap<String, String>,
+        }
+
+        impl $name {
+            fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+                match key {
+                    $($key => {
+                        self.$field = Some(<$t as ConfigValue>::parse_value(value)
+                            .map_err(|e| format!("{}: {}", key, e))?);
+                    })*
+                    _ => {
+                        self.others.insert(key.to_owned(), value.to_owned());
+                    }
+                }
+                Ok(())
+            }
+
+            fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+                $(if let Some(v) = self.$field.as_ref() {
+                    writeln!(w, "{}={}", $key, v.format_value())?;
+                })*
+                for (k, v) in self.others.iter() {
+                    writeln!(w, "{}={}", k, v)?;
+                }
+                Ok(())
+            }
+        }
+    };
+}
+pub(crate) use config_section;
+
+config_section! {
+    /// `[property]` section
+    pub struct Property {
+        gpu_id: u32 => "gpu-id",
+        net_scale_factor: f64 => "net-scale-factor",
+        offsets: Vec<f32> => "offsets",
+        /// 0=RGB, 1=BGR, 2=GRAY
+        model_color_format: u32 => "model-color-format",
+        custom_network_config: PathBuf => "custom-network-config",
+        model_file: PathBuf => "model-file",
+        proto_file: PathBuf => "proto-file",
+        onnx_file: PathBuf => "onnx-file",
+        uff_file: PathBuf => "uff-file",
+        tlt_encoded_model: PathBuf => "tlt-encoded-model",
+        model_engine_file: PathBuf => "model-engine-file",
+        labelfile_path: PathBuf => "labelfile-path",
+        int8_calib_file: PathBuf => "int8-calib-file",
+        mean_file: PathBuf => "mean-file",
+        custom_lib_path: PathBuf => "custom-lib-path",
+        /// 0=FP32, 1=INT8, 2=FP16
+        network_mode: u32 => "network-mode",
+        /// 0=Detector, 1=Classifier, 2=Segmentation, 3=Instance Segmentation, 100=Other
+        network_type: u32 => "network-type",
+        num_detected_classes: u32 => "num-detected-classes",
+        gie_unique_id: u32 => "gie-unique-id",
+        is_classifier: bool => "is-classifier",
+        classifier_threshold: f32 => "classifier-threshold",
+        /// 1=Primary, 2=Secondary
+        process_mode: u32 => "process-mode",
+        operate_on_gie_id: i32 => "operate-on-gie-id",
+        operate_on_class_ids: Vec<i32> => "operate-on-class-ids",
+        batch_size: u32 => "batch-size",
+        interval: u32 => "interval",
+        /// 1=DBSCAN, 2=NMS, 3=DBSCAN+NMS Hybrid, 4=None
+        cluster_mode: u32 => "cluster-mode",
+        maintain_aspect_ratio: bool => "maintain-aspect-ratio",
+        output_blob_names: Vec<String> => "output-blob-names",
+        output_tensor_meta: bool => "output-tensor-meta",
+        parse_bbox_func_name: String => "parse-bbox-func-name",
+        engine_create_func_name: String => "engine-create-func-name",
+    }
+}
+
+config_section! {
+    /// `[class-attrs-all]` and `[class-attrs-N]` sections
+    pub struct ClassAttrs {
+        threshold: f32 => "threshold",
+        pre_cluster_threshold: f32 => "pre-cluster-threshold",
+        post_cluster_threshold: f32 => "post-cluster-threshold",
+        nms_iou_threshold: f32 => "nms-iou-threshold",
+        topk: i32 => "topk",
+        eps: f32 => "eps",
+        group_threshold: i32 => "group-threshold",
+        min_boxes: i32 => "minBoxes",
+        detected_min_w: u32 => "detected-min-w",
+        detected_min_h: u32 => "detected-min-h",
+        detected_max_w: u32 => "detected-max-w",
+        detected_max_h: u32 => "detected-max-h",
+        roi_top_offset: u32 => "roi-top-offset",
+        roi_bottom_offset: u32 => "roi-bottom-offset",
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InferConfig {
+    pub property: Property,
+    pub class_attrs_all: Option<ClassAttrs>,
+    /// `[class-attrs-N]` by class id
+    pub class_attrs: BTreeMap<u32, ClassAttrs>,
+}
+
+enum Section {
+    Property,
+    ClassAttrsAll,
+    ClassAttrs(u32),
+}
+
+impl InferConfig {
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut config = InferConfig::default();
+        let mut section = None;
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse_err = |message: String| Error::Parse {
+                line: i + 1,
+                message,
+            };
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = Some(match name {
+                    "property" => Section::Property,
+                    "class-attrs-all" => Section::ClassAttrsAll,
+                    _ => match name.strip_prefix("class-attrs-").map(str::parse) {
+                        Some(Ok(id)) => Section::ClassAttrs(id),
+                        _ => return Err(parse_err(format!("unknown section [{}]", name))),
+                    },
+                });
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| parse_err(format!("expect key=value: {:?}", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+            let result = match section {
+                None => return Err(parse_err(format!("{} is outside of a section", key))),
+                Some(Section::Property) => config.property.set(key, value),
+                Some(Section::ClassAttrsAll) => config
+                    .class_attrs_all
+                    .get_or_insert_with(Default::default)
+                    .set(key, value),
+                Some(Section::ClassAttrs(id)) => {
+                    config.class_attrs.entry(id).or_default().set(key, value)
+                }
+            };
+            result.map_err(parse_err)?;
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(w, "[property]")?;
+        self.property.write_to(w)?;
+        if let Some(attrs) = self.class_attrs_all.as_ref() {
+            writeln!(w, "\n[class-attrs-all]")?;
+            attrs.write_to(w)?;
+        }
+        for (id, attrs) in self.class_attrs.iter() {
+            writeln!(w, "\n[class-attrs-{}]", id)?;
+            attrs.write_to(w)?;
+        }
+        Ok(())
+    }
+
+    /// Whether nvinfer runs as a secondary GIE operating on objects
+    pub fn is_secondary(&self) -> bool {
+        self.property.process_mode == Some(2)
+    }
+
+    /// Whether the network is a classifier
+    pub fn is_classifier(&self) -> bool {
+        self.property.network_type == Some(1) || self.property.is_classifier == Some(true)
+    }
+}
+
+impl fmt::Display for InferConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut buf = vec![];
+        self.write_to(&mut buf).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&buf))
+    }
+}
+
+/// Directory which a relative path of the key is resolved against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathBase {
+    WorkingDir,
+    ConfigFile,
+}
+
+impl fmt::Display for PathBase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathBase::WorkingDir => write!(f, "working dir"),
+            PathBase::ConfigFile => write!(f, "config file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub key: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}: {}", s, self.key, self.message)
+    }
+}
+
+/// Resolve paths as nvinfer does and report inconsistent settings
+pub struct Validator<'a> {
+    config_dir: &'a Path,
+    working_dir: &'a Path,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(config_path: &'a Path, working_dir: &'a Path) -> Self {
+        Self {
+            config_dir: config_path.parent().unwrap_or_else(|| Path::new("")),
+            working_dir,
+        }
+    }
+
+    pub fn resolve(&self, path: &Path, base: PathBase) -> PathBuf {
+        match base {
+            PathBase::WorkingDir => self.working_dir.join(path),
+            PathBase::ConfigFile => self.config_dir.join(path),
+        }
+    }
+
+    pub fn validate(&self, config: &InferConfig) -> Vec<Issue> {
+        let p = &config.property;
+        let mut issues = vec![];
+        let files: [(&'static str, &Option<PathBuf>, PathBase); 10] = [
+            (
+                "custom-network-config",
+                &p.custom_network_config,
+                PathBase::WorkingDir,
+            ),
+            ("model-file", &p.model_file, PathBase::ConfigFile),
+            ("proto-file", &p.proto_file, PathBase::ConfigFile),
+            ("onnx-file", &p.onnx_file, PathBase::ConfigFile),
+            ("uff-file", &p.uff_file, PathBase::ConfigFile),
+            (
+                "tlt-encoded-model",
+                &p.tlt_encoded_model,
+                PathBase::ConfigFile,
+            ),
+            ("labelfile-path", &p.labelfile_path, PathBase::ConfigFile),
+            ("int8-calib-file", &p.int8_calib_file, PathBase::ConfigFile),
+            ("mean-file", &p.mean_file, PathBase::ConfigFile),
+            ("custom-lib-path", &p.custom_lib_path, PathBase::ConfigFile),
+        ];
+        for (key, path, base) in files {
+            if let Some(path) = path {
+                let resolved = self.resolve(path, base);
+                if !resolved.exists() {
+                    issues.push(Issue {
+                        severity: Severity::Error,
+                        key,
+                        message: format!(
+                            "File does not exist : {} (resolved against {} as {})",
+                            path.display(),
+                            base,
+                            resolved.display()
+                        ),
+                    });
+                }
+            }
+        }
+
+        let engine_exists = match p.model_engine_file.as_ref() {
+            Some(path) => {
+                let resolved = self.resolve(path, PathBase::ConfigFile);
+                let exists = resolved.exists();
+                if !exists {
+                    let generated = path
+                        .file_name()
+                        .map(|name| self.resolve(Path::new(name), PathBase::WorkingDir));
+                    issues.push(Issue {
+                        severity: Severity::Warning,
+                        key: "model-engine-file",
+                        message: format!(
+                            "{} does not exist, engine will be built and saved as {}",
+                            resolved.display(),
+                            generated.unwrap_or_default().display()
+                        ),
+                    });
+                }
+                exists
+            }
+            None => false,
+        };
+
+        if !engine_exists {
+            let has_model = p.custom_network_config.is_some()
+                || p.model_file.is_some()
+                || p.onnx_file.is_some()
+                || p.uff_file.is_some()
+                || p.tlt_encoded_model.is_some();
+            if !has_model {
+                issues.push(Issue {
+                    severity: Severity::Error,
+                    key: "model-engine-file",
+                    message: "no engine file nor model file to build the engine".to_owned(),
+                });
+            }
+            if p.network_mode == Some(1) && p.int8_calib_file.is_none() {
+                issues.push(Issue {
+                    severity: Severity::Warning,
+                    key: "int8-calib-file",
+                    message: "INT8 mode without calibration file".to_owned(),
+                });
+            }
+        }
+
+        if config.is_classifier() {
+            if p.classifier_threshold.is_none() {
+                issues.push(Issue {
+                    severity: Severity::Error,
+                    key: "classifier-threshold",
+                    message: "mandatory for classifiers".to_owned(),
+                });
+            }
+        } else if p.network_type.unwrap_or(0) == 0 && p.num_detected_classes.is_none() {
+            issues.push(Issue {
+                severity: Severity::Error,
+                key: "num-detected-classes",
+                message: "mandatory for detectors".to_owned(),
+            });
+        }
+
+        if let Some(n) = p.num_detected_classes {
+            if let Some(id) = config.class_attrs.keys().find(|id| **id >= n) {
+                issues.push(Issue {
+                    severity: Severity::Warning,
+                    key: "class-attrs",
+                    message: format!("[class-attrs-{}] is out of num-detected-classes={}", id, n),
+                });
+            }
+        }
+
+        if let (false, Some(n), Some(path)) = (
+            config.is_classifier(),
+            p.num_detected_classes,
+            p.labelfile_path.as_ref(),
+        ) {
+            let resolved = self.resolve(path, PathBase::ConfigFile);
+            if let Ok(labels) = crate::labels::Labels::load(&resolved, false) {
+                if let Err(found) = labels.validate_count(n) {
+                    issues.push(Issue {
+                        severity: Severity::Warning,
+                        key: "labelfile-path",
+                        message: format!(
+                            "{} has {} labels but num-detected-classes={}",
+                            resolved.display(),
+                            found,
+                            n
+                        ),
+                    });
+                }
+            }
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# detector
+[property]
+gpu-id=0
+net-scale-factor=0.0039215697906911373
+offsets=1.0;2.5;3
+model-engine-file=model.engine
+labelfile-path=labels.txt
+num-detected-classes=80
+maintain-aspect-ratio=1
+output-blob-names=conv1;conv2
+custom-key=kept
+
+[class-attrs-all]
+pre-cluster-threshold=0.25
+minBoxes=3
+
+[class-attrs-2]
+threshold=0.5
+";
+
+    #[test]
+    fn parse_typed_and_others() {
+        let c = InferConfig::parse(CONFIG).unwrap();
+        let p = &c.property;
+        assert_eq!(p.gpu_id, Some(0));
+        assert_eq!(p.offsets, Some(vec![1.0, 2.5, 3.0]));
+        assert_eq!(p.model_engine_file, Some(PathBuf::from("model.engine")));
+        assert_eq!(p.num_detected_classes, Some(80));
+        assert_eq!(p.maintain_aspect_ratio, Some(true));
+        assert_eq!(
+            p.output_blob_names,
+            Some(vec!["conv1".to_owned(), "conv2".to_owned()])
+        );
+        assert_eq!(p.others.get("custom-key").map(String::as_str), Some("kept"));
+        let all = c.class_attrs_all.as_ref().unwrap();
+        assert_eq!(all.pre_cluster_threshold, Some(0.25));
+        assert_eq!(all.min_boxes, Some(3));
+        assert_eq!(c.class_attrs[&2].threshold, Some(0.5));
+        assert!(!c.is_classifier());
+        assert!(!c.is_secondary());
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        let line = |s: &str| match InferConfig::parse(s) {
+            Err(Error::Parse { line, .. }) => line,
+            r => panic!("unexpected {:?}", r),
+        };
+        assert_eq!(line("gpu-id=0"), 1);
+        assert_eq!(line("[property]\n\ngpu-id=x"), 3);
+        assert_eq!(line("[property]\nmaintain-aspect-ratio=2"), 2);
+        assert_eq!(line("[property]\nno value"), 2);
+        assert_eq!(line("[class-attrs-x]"), 1);
+    }
+
+    #[test]
+    fn write_then_parse() {
+        let c = InferConfig::parse(CONFIG).unwrap();
+        let written = c.to_string();
+        assert!(written.starts_with("[property]\ngpu-id=0\n"));
+        assert!(written.contains("\n[class-attrs-2]\nthreshold=0.5\n"));
+        assert!(written.contains("maintain-aspect-ratio=1\n"));
+        assert_eq!(InferConfig::parse(&written).unwrap(), c);
+    }
+
+    /// Empty directory under the temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "nvdsmeta-infer-config-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn keys(issues: &[Issue]) -> Vec<(Severity, &'static str)> {
+        issues.iter().map(|i| (i.severity, i.key)).collect()
+    }
+
+    #[test]
+    fn validate_resolves_paths() {
+        let dir = temp_dir("paths");
+        let config_dir = dir.join("configs");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(config_dir.join("model.onnx"), b"").unwrap();
+        std::fs::write(dir.join("yolo.cfg"), b"").unwrap();
+        std::fs::write(config_dir.join("labels.txt"), "a\nb\n").unwrap();
+        let config_path = config_dir.join("infer.txt");
+        let validator = Validator::new(&config_path, &dir);
+        let c = InferConfig::parse(
+            "[property]\n\
+             onnx-file=model.onnx\n\
+             custom-network-config=yolo.cfg\n\
+             labelfile-path=labels.txt\n\
+             num-detected-classes=2\n",
+        )
+        .unwrap();
+        assert_eq!(keys(&validator.validate(&c)), vec![]);
+
+        // custom-network-config is relative to the working dir, others to the config
+        let c = InferConfig::parse(
+            "[property]\n\
+             onnx-file=../yolo.cfg\n\
+             custom-network-config=model.onnx\n\
+             num-detected-classes=2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            keys(&validator.validate(&c)),
+            vec![(Severity::Error, "custom-network-config")]
+        );
+    }
+
+    #[test]
+    fn validate_settings() {
+        let dir = temp_dir("settings");
+        std::fs::write(dir.join("labels.txt"), "a\nb\nc\n").unwrap();
+        let config_path = dir.join("infer.txt");
+        let validator = Validator::new(&config_path, &dir);
+
+        let c = InferConfig::parse("[property]\nnetwork-mode=1\n").unwrap();
+        assert_eq!(
+            keys(&validator.validate(&c)),
+            vec![
+                (Severity::Error, "model-engine-file"),
+                (Severity::Warning, "int8-calib-file"),
+                (Severity::Error, "num-detected-classes"),
+            ]
+        );
+
+        let c =
+            InferConfig::parse("[property]\nmodel-engine-file=missing.engine\nnetwork-type=1\n")
+                .unwrap();
+        let issues = validator.validate(&c);
+        assert_eq!(
+            keys(&issues),
+            vec![
+                (Severity::Warning, "model-engine-file"),
+                (Severity::Error, "model-engine-file"),
+                (Severity::Error, "classifier-threshold"),
+            ]
+        );
+        assert!(issues[0]
+            .message
+            .ends_with(&dir.join("missing.engine").display().to_string()));
+
+        let c = InferConfig::parse(
+            "[property]\n\
+             onnx-file=labels.txt\n\
+             labelfile-path=labels.txt\n\
+             num-detected-classes=2\n\
+             [class-attrs-2]\n\
+             threshold=0.5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            keys(&validator.validate(&c)),
+            vec![
+                (Severity::Warning, "class-attrs"),
+                (Severity::Warning, "labelfile-path"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,545 @@
+//! Labels of nvinfer label files and remapping of classes
+//!
+//! A detector label file has one label per line, so `class_id` is the line index.
+//! A classifier label file has one line per output layer with labels separated by `;`.
+//! Labels fill `ObjectMeta.label` when the parser of the detector leaves it empty,
+//! and an [`Ontology`] merges labels of the model into the classes of the application.
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::exporter::Exporter;
+use crate::infer_config::{self, InferConfig};
+use crate::{FrameObjects, ObjectMeta, Record};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error(transparent)]
+    InferConfig(#[from] infer_config::Error),
+    #[error("no labelfile-path in the infer config")]
+    NoLabelFile,
+    #[error("{} has {found} labels but num-detected-classes is {expected}", .path.display())]
+    ClassCount {
+        path: PathBuf,
+        expected: u32,
+        found: usize,
+    },
+    #[error("label {0:?} is a source of more than one class")]
+    DuplicateSource(String),
+}
+
+/// Labels of a label file by output layer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels {
+    layers: Vec<Vec<String>>,
+}
+
+impl Labels {
+    /// One label per line, trailing empty lines are ignored
+    pub fn parse_detector(s: &str) -> Self {
+        let mut labels = s.lines().map(|l| l.trim().to_owned()).collect::<Vec<_>>();
+        while labels.last().map_or(false, String::is_empty) {
+            labels.pop();
+        }
+        Self {
+            layers: vec![labels],
+        }
+    }
+
+    /// One output layer per line with labels separated by `;`
+    pub fn parse_classifier(s: &str) -> Self {
+        let layers = s
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                l.split(';')
+                    .map(|x| x.trim().to_owned())
+                    .filter(|x| !x.is_empty())
+                    .collect()
+            })
+            .collect();
+        Self { layers }
+    }
+
+    pub fn load(path: &Path, classifier: bool) -> Result<Self, Error> {
+        let s = std::fs::read_to_string(path).map_err(|source| Error::Io {
+            path: path.to_owned(),
+            source,
+        })?;
+        Ok(if classifier {
+            Self::parse_classifier(&s)
+        } else {
+            Self::parse_detector(&s)
+        })
+    }
+
+    /// Load `labelfile-path` of the infer config resolved against the config file
+    ///
+    /// The number of detector labels must match `num-detected-classes` if given.
+    pub fn from_infer_config(config_path: &Path) -> Result<Self, Error> {
+        let config = InferConfig::load(config_path)?;
+        let p = &config.property;
+        let path = p.labelfile_path.as_ref().ok_or(Error::NoLabelFile)?;
+        let path = config_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(path);
+        let labels = Self::load(&path, config.is_classifier())?;
+        if let (false, Some(n)) = (config.is_classifier(), p.num_detected_classes) {
+            labels
+                .validate_count(n)
+                .map_err(|found| Error::ClassCount {
+                    path,
+                    expected: n,
+                    found,
+                })?;
+        }
+        Ok(labels)
+    }
+
+    /// `Err` with the number of detector labels if it is not `num_classes`
+    pub fn validate_count(&self, num_classes: u32) -> Result<(), usize> {
+        let found = self.detector_labels().len();
+        if found == num_classes as usize {
+            Ok(())
+        } else {
+            Err(found)
+        }
+    }
+
+    pub fn layers(&self) -> &[Vec<String>] {
+        &self.layers
+    }
+
+    /// Labels of the first layer, which are classes of a detector
+    pub fn detector_labels(&self) -> &[String] {
+        self.layers.first().map_or(&[], Vec::as_slice)
+    }
+
+    /// Label of the class of a detector
+    pub fn label(&self, class_id: i32) -> Option<&str> {
+        self.classifier_label(0, class_id)
+    }
+
+    pub fn classifier_label(&self, layer: usize, class_id: i32) -> Option<&str> {
+        let class_id = usize::try_from(class_id).ok()?;
+        self.layers.get(layer)?.get(class_id).map(String::as_str)
+    }
+
+    /// Set the label of the object from its class id if empty
+    pub fn fill(&self, o: &mut ObjectMeta) {
+        self.fill_label(o.class_id, &mut o.label);
+    }
+
+    fn fill_label(&self, class_id: i32, label: &mut String) {
+        if label.is_empty() {
+            if let Some(l) = self.label(class_id) {
+                *label = l.to_owned();
+            }
+        }
+    }
+}
+
+/// A class of the application made of labels of the model
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OntologyClass {
+    pub name: String,
+    /// labels of the model merged into this class
+    pub sources: Vec<String>,
+}
+
+/// Remap labels of the model to classes whose ids are their indices
+///
+/// Objects of unmapped labels are dropped, or kept with `class_id` -1 and their label.
+#[derive(Debug, Clone)]
+pub struct Ontology {
+    classes: Vec<OntologyClass>,
+    by_source: HashMap<String, usize>,
+    drop_unmapped: bool,
+}
+
+impl Ontology {
+    pub const UNMAPPED_CLASS_ID: i32 = -1;
+
+    pub fn new(classes: Vec<OntologyClass>, drop_unmapped: bool) -> Result<Self, Error> {
+        let mut by_source = HashMap::new();
+        for (i, c) in classes.iter().enumerate() {
+            for s in c.sources.iter() {
+                if by_source.insert(s.clone(), i).is_some() {
+                    return Err(Error::DuplicateSource(s.clone()));
+                }
+            }
+        }
+        Ok(Self {
+            classes,
+            by_source,
+            drop_unmapped,
+        })
+    }
+
+    pub fn classes(&self) -> &[OntologyClass] {
+        &self.classes
+    }
+
+    /// Class id and name of the label
+    pub fn map(&self, label: &str) -> Option<(i32, &str)> {
+        self.by_source
+            .get(label)
+            .map(|i| (*i as i32, self.classes[*i].name.as_str()))
+    }
+
+    /// Remap the object in place, `false` if it is to be dropped
+    pub fn apply(&self, o: &mut ObjectMeta) -> bool {
+        self.remap(&mut o.class_id, &mut o.label)
+    }
+
+    fn remap(&self, class_id: &mut i32, label: &mut String) -> bool {
+        match self.map(label) {
+            Some((id, name)) => {
+                *class_id = id;
+                *label = name.to_owned();
+                true
+            }
+            None if self.drop_unmapped => false,
+            None => {
+                *class_id = Self::UNMAPPED_CLASS_ID;
+                true
+            }
+        }
+    }
+}
+
+/// Fill labels then remap classes of frames
+#[derive(Debug, Clone, Default)]
+pub struct LabelMapper {
+    labels: Option<Labels>,
+    ontology: Option<Ontology>,
+}
+
+impl LabelMapper {
+    pub fn new(labels: Option<Labels>, ontology: Option<Ontology>) -> Self {
+        Self { labels, ontology }
+    }
+
+    /// Map objects and past-frame objects of the frame, so backfilled objects match
+    pub fn apply(&self, f: &mut FrameObjects) {
+        if let Some(labels) = self.labels.as_ref() {
+            f.objects.iter_mut().for_each(|o| labels.fill(o));
+            for p in f.past_objects.iter_mut() {
+                labels.fill_label(p.class_id, &mut p.label);
+            }
+        }
+        if let Some(ontology) = self.ontology.as_ref() {
+            let objects = std::mem::take(&mut f.objects);
+            f.objects = objects
+                .into_iter()
+                .filter_map(|mut o| {
+                    if ontology.apply(&mut o) {
+                        Some(o)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            let past_objects = std::mem::take(&mut f.past_objects);
+            f.past_objects = past_objects
+                .into_iter()
+                .filter_map(|mut p| {
+                    if ontology.remap(&mut p.class_id, &mut p.label) {
+                        Some(p)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+        }
+    }
+
+    /// Apply to frame records, others are kept as is
+    pub fn apply_record(&self, record: &mut Record) {
+        if let Record::Frame(f) = record {
+            self.apply(f);
+        }
+    }
+}
+
+/// Exporter writing frames mapped by [`LabelMapper`] by the inner exporter
+pub struct LabelExporter {
+    inner: Box<dyn Exporter>,
+    mapper: LabelMapper,
+}
+
+impl LabelExporter {
+    pub fn new(inner: Box<dyn Exporter>, mapper: LabelMapper) -> Self {
+        Self { inner, mapper }
+    }
+}
+
+impl Exporter for LabelExporter {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        match record {
+            Record::Frame(_) => {
+                let mut record = record.clone();
+                self.mapper.apply_record(&mut record);
+                self.inner.write_record(&record)
+            }
+            _ => self.inner.write_record(record),
+        }
+    }
+
+    fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{frame, object, Collect};
+    use crate::{BBoxCorrds, PastObject, SourceEvent, SourceState};
+
+    fn class(name: &str, sources: &[&str]) -> OntologyClass {
+        OntologyClass {
+            name: name.to_owned(),
+            sources: sources.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Classes of the model merged into `vehicle` and `person`
+    fn ontology(drop_unmapped: bool) -> Ontology {
+        Ontology::new(
+            vec![
+                class("vehicle", &["car", "truck", "bus"]),
+                class("person", &["person"]),
+            ],
+            drop_unmapped,
+        )
+        .unwrap()
+    }
+
+    fn labelled(class_id: i32, object_id: u64, label: &str) -> ObjectMeta {
+        ObjectMeta {
+            label: label.to_owned(),
+            ..object(class_id, object_id, [0.; 4], 0.9)
+        }
+    }
+
+    fn past(class_id: i32, label: &str) -> PastObject {
+        PastObject {
+            frame_num: 0,
+            object_id: 1,
+            class_id,
+            label: label.to_owned(),
+            confidence: 0.5,
+            bbox: BBoxCorrds {
+                left: 0.,
+                top: 0.,
+                width: 0.,
+                height: 0.,
+            },
+        }
+    }
+
+    /// `class_id:label` of the objects
+    fn summary(objects: &[ObjectMeta]) -> Vec<String> {
+        objects
+            .iter()
+            .map(|o| format!("{}:{}", o.class_id, o.label))
+            .collect()
+    }
+
+    #[test]
+    fn parse_detector_labels() {
+        let labels = Labels::parse_detector("car\n bicycle \n\nperson\n\n\n");
+        // empty lines inside are classes without a label
+        assert_eq!(labels.detector_labels(), ["car", "bicycle", "", "person"]);
+        assert_eq!(labels.label(1), Some("bicycle"));
+        assert_eq!(labels.label(3), Some("person"));
+        assert_eq!(labels.label(4), None);
+        assert_eq!(labels.label(-1), None);
+        assert_eq!(labels.validate_count(4), Ok(()));
+        assert_eq!(labels.validate_count(3), Err(4));
+        assert_eq!(Labels::parse_detector("").detector_labels(), [""; 0]);
+    }
+
+    #[test]
+    fn parse_classifier_layers() {
+        let labels = Labels::parse_classifier("black;blue; red;\n\nsedan;suv\n");
+        assert_eq!(labels.layers().len(), 2);
+        assert_eq!(labels.detector_labels(), ["black", "blue", "red"]);
+        assert_eq!(labels.classifier_label(0, 2), Some("red"));
+        assert_eq!(labels.classifier_label(1, 1), Some("suv"));
+        assert_eq!(labels.classifier_label(1, 2), None);
+        assert_eq!(labels.classifier_label(2, 0), None);
+        assert_eq!(labels.classifier_label(1, -1), None);
+    }
+
+    #[test]
+    fn fill_only_empty_labels() {
+        let labels = Labels::parse_detector("car\nperson\n");
+        let mut o = object(1, 1, [0.; 4], 0.9);
+        labels.fill(&mut o);
+        assert_eq!(o.label, "person");
+        let mut o = labelled(1, 1, "pedestrian");
+        labels.fill(&mut o);
+        assert_eq!(o.label, "pedestrian");
+        let mut o = object(2, 1, [0.; 4], 0.9);
+        labels.fill(&mut o);
+        assert_eq!(o.label, "");
+    }
+
+    /// Empty directory under the temp dir
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("nvdsmeta-labels-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn labels_of_infer_config() {
+        let dir = temp_dir("infer-config");
+        let config_dir = dir.join("configs");
+        std::fs::create_dir_all(&config_dir).unwrap();
+        std::fs::write(config_dir.join("labels.txt"), "car\nperson\n").unwrap();
+        std::fs::write(config_dir.join("colors.txt"), "black;white\n").unwrap();
+        let write = |name: &str, s: &str| {
+            let path = config_dir.join(name);
+            std::fs::write(&path, s).unwrap();
+            path
+        };
+
+        // labelfile-path is relative to the config file
+        let path = write(
+            "detector.txt",
+            "[property]\nlabelfile-path=labels.txt\nnum-detected-classes=2\n",
+        );
+        let labels = Labels::from_infer_config(&path).unwrap();
+        assert_eq!(labels.detector_labels(), ["car", "person"]);
+
+        let path = write(
+            "count.txt",
+            "[property]\nlabelfile-path=labels.txt\nnum-detected-classes=3\n",
+        );
+        match Labels::from_infer_config(&path) {
+            Err(Error::ClassCount {
+                path,
+                expected,
+                found,
+            }) => {
+                assert_eq!(path, config_dir.join("labels.txt"));
+                assert_eq!((expected, found), (3, 2));
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+
+        // the count is not checked for classifiers
+        let path = write(
+            "classifier.txt",
+            "[property]\nlabelfile-path=colors.txt\nnetwork-type=1\nnum-detected-classes=3\n",
+        );
+        let labels = Labels::from_infer_config(&path).unwrap();
+        assert_eq!(labels.layers(), [vec!["black", "white"]]);
+
+        let path = write("none.txt", "[property]\nnum-detected-classes=2\n");
+        assert!(matches!(
+            Labels::from_infer_config(&path),
+            Err(Error::NoLabelFile)
+        ));
+        let path = write(
+            "missing.txt",
+            "[property]\nlabelfile-path=missing-labels.txt\n",
+        );
+        assert!(matches!(
+            Labels::from_infer_config(&path),
+            Err(Error::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn ontology_maps_labels_to_class_indices() {
+        let o = ontology(false);
+        assert_eq!(o.classes().len(), 2);
+        assert_eq!(o.map("truck"), Some((0, "vehicle")));
+        assert_eq!(o.map("person"), Some((1, "person")));
+        assert_eq!(o.map("dog"), None);
+
+        let mut car = labelled(2, 1, "car");
+        assert!(o.apply(&mut car));
+        assert_eq!(summary(&[car]), ["0:vehicle"]);
+        // unmapped objects keep their label
+        let mut dog = labelled(16, 2, "dog");
+        assert!(o.apply(&mut dog));
+        assert_eq!(dog.class_id, Ontology::UNMAPPED_CLASS_ID);
+        assert_eq!(dog.label, "dog");
+        let mut dog = labelled(16, 2, "dog");
+        assert!(!ontology(true).apply(&mut dog));
+
+        let duplicate = Ontology::new(
+            vec![class("vehicle", &["car"]), class("car", &["car"])],
+            false,
+        );
+        assert!(matches!(duplicate, Err(Error::DuplicateSource(s)) if s == "car"));
+    }
+
+    #[test]
+    fn mapper_fills_then_remaps_objects_and_past_objects() {
+        let labels = Labels::parse_detector("car\nperson\ndog\n");
+        let mapper = LabelMapper::new(Some(labels.clone()), Some(ontology(true)));
+        let mut f = frame(
+            0,
+            0,
+            vec![
+                object(0, 1, [0.; 4], 0.9),
+                object(2, 2, [0.; 4], 0.9),
+                labelled(0, 3, "person"),
+            ],
+        )
+        .with_past_objects(vec![past(1, ""), past(2, "")]);
+        mapper.apply(&mut f);
+        // the parser label wins over the label file, dogs are dropped
+        assert_eq!(summary(f.objects()), ["0:vehicle", "1:person"]);
+        let past_objects = f
+            .past_objects()
+            .iter()
+            .map(|p| format!("{}:{}", p.class_id, p.label))
+            .collect::<Vec<_>>();
+        assert_eq!(past_objects, ["1:person"]);
+
+        // labels only
+        let mut f = frame(0, 0, vec![object(2, 1, [0.; 4], 0.9)]);
+        LabelMapper::new(Some(labels), None).apply(&mut f);
+        assert_eq!(summary(f.objects()), ["2:dog"]);
+    }
+
+    #[test]
+    fn exporter_maps_frames_only() {
+        let (inner, written) = Collect::boxed();
+        let mapper = LabelMapper::new(None, Some(ontology(false)));
+        let mut e = LabelExporter::new(inner, mapper);
+        let source = SourceEvent::new(0, SourceState::Added, "file:///a.mp4");
+        e.write_record(&Record::Source(source)).unwrap();
+        let f = frame(0, 0, vec![labelled(2, 1, "bus"), labelled(16, 2, "dog")]);
+        e.write_record(&Record::Frame(f)).unwrap();
+        e.finish().unwrap();
+        assert_eq!(e.dropped(), 0);
+
+        let written = written.lock().unwrap();
+        assert!(matches!(&written[0], Record::Source(s) if s.source_id == 0));
+        match &written[1] {
+            Record::Frame(f) => assert_eq!(summary(f.objects()), ["0:vehicle", "-1:dog"]),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Export records of latencies reported by [`nvdsmeta_sys::latency::LatencyReporter`]
+use std::collections::BTreeMap;
+
+use chrono::serde::ts_nanoseconds;
+use chrono::{DateTime, Utc};
+use nvdsmeta_sys::latency::{self, SourceLatency};
+use serde::{Deserialize, Serialize};
+
+/// Percentiles of latencies in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl From<&latency::LatencyStats> for LatencyStats {
+    fn from(s: &latency::LatencyStats) -> Self {
+        Self {
+            count: s.count,
+            mean: s.mean,
+            p50: s.p50,
+            p95: s.p95,
+            p99: s.p99,
+            max: s.max,
+        }
+    }
+}
+
+/// Latencies of a source over a reporting interval
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyEvent {
+    pub source_id: u32,
+    /// end of the interval
+    #[serde(with = "ts_nanoseconds")]
+    pub timestamp: DateTime<Utc>,
+    /// from the decoder input to the measurement point
+    pub frame: Option<LatencyStats>,
+    /// by component name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<String, LatencyStats>,
+}
+
+impl LatencyEvent {
+    /// One event per source of a report
+    pub fn from_report(report: &BTreeMap<u32, SourceLatency>) -> Vec<Self> {
+        let timestamp = Utc::now();
+        report
+            .iter()
+            .map(|(&source_id, l)| Self {
+                source_id,
+                timestamp,
+                frame: l.frame.as_ref().map(LatencyStats::from),
+                components: l
+                    .components
+                    .iter()
+                    .map(|(name, s)| (name.clone(), s.into()))
+                    .collect(),
+            })
+            .collect()
+    }
+}
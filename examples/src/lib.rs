@@ -1,8 +1,30 @@
+use std::ffi::CStr;
+
 use chrono::serde::ts_nanoseconds;
-use chrono::{DateTime, NaiveDateTime, Utc};
-use nvdsmeta_sys::{NvBbox_Coords, NvDsFrameMeta, NvDsObjectMeta};
+use chrono::{DateTime, TimeZone, Utc};
+use nvdsmeta_sys::{
+    NvBbox_Coords, NvDsAudioFrameMeta, NvDsClassifierMeta, NvDsFrameMeta,
+    NvDsInferSegmentationMeta, NvDsObjectMeta, NvDsPastFrameObjList, UNTRACKED_OBJECT_ID,
+};
 use serde::{Deserialize, Serialize};
 
+pub mod backfill;
+mod error;
+pub mod eval;
+pub mod export;
+pub mod exporter;
+pub mod infer_config;
+pub mod labels;
+pub mod latency;
+pub mod msgconv;
+pub mod proto;
+pub mod publish;
+pub mod timestamp;
+pub mod track;
+
+pub use error::Error;
+use timestamp::{FrameTimestamps, TimestampPolicy, TimestampSource};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BBoxCorrds {
     pub left: f32,
@@ -11,6 +33,13 @@ pub struct BBoxCorrds {
     pub height: f32,
 }
 
+impl BBoxCorrds {
+    /// No area, such as the detector bbox of objects only seen by the tracker
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0.0 || self.height <= 0.0
+    }
+}
+
 impl From<&NvBbox_Coords> for BBoxCorrds {
     fn from(c: &NvBbox_Coords) -> Self {
         Self {
@@ -22,14 +51,71 @@ impl From<&NvBbox_Coords> for BBoxCorrds {
     }
 }
 
+/// Label written by a parser, invalid UTF-8 is replaced so one bad label does not
+/// stop the pipeline
+fn label_string(s: &CStr) -> String {
+    s.to_string_lossy().into_owned()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelInfo {
+    pub label_id: u32,
+    pub class_id: u32,
+    pub prob: f32,
+    pub label: String,
+}
+
+/// Result of a secondary classifier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClassifierMeta {
+    pub unique_component_id: i32,
+    pub labels: Vec<LabelInfo>,
+}
+
+impl From<&NvDsClassifierMeta> for ClassifierMeta {
+    fn from(x: &NvDsClassifierMeta) -> Self {
+        Self {
+            unique_component_id: x.unique_component_id(),
+            labels: x
+                .label_info_list()
+                .map(|l| LabelInfo {
+                    label_id: l.label_id(),
+                    class_id: l.result_class_id(),
+                    prob: l.result_prob(),
+                    label: label_string(l.result_label()),
+                })
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObjectMeta {
     pub detection_index: u32,
     pub class_id: i32,
     pub object_id: u64,
     pub detector_bbox_info: BBoxCorrds,
+    /// bbox of nvtracker, `None` for untracked objects
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracker_bbox_info: Option<BBoxCorrds>,
     pub confidence: f32,
     pub label: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classifiers: Vec<ClassifierMeta>,
+    /// inserted from past-frame meta of the tracker by [`backfill`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backfilled: bool,
+}
+
+impl ObjectMeta {
+    /// Detector bbox, or the tracker bbox if the detector did not find the object
+    /// in this frame, such as shadow tracking and backfilled objects
+    pub fn bbox(&self) -> &BBoxCorrds {
+        match self.tracker_bbox_info.as_ref() {
+            Some(t) if self.detector_bbox_info.is_empty() => t,
+            _ => &self.detector_bbox_info,
+        }
+    }
 }
 
 impl From<&NvDsObjectMeta> for ObjectMeta {
@@ -39,8 +125,61 @@ impl From<&NvDsObjectMeta> for ObjectMeta {
             class_id: x.class_id(),
             object_id: x.object_id(),
             detector_bbox_info: BBoxCorrds::from(x.detector_bbox()),
+            tracker_bbox_info: (x.object_id() != UNTRACKED_OBJECT_ID)
+                .then(|| BBoxCorrds::from(x.tracker_bbox())),
             confidence: x.confidence(),
-            label: x.label().to_str().unwrap().to_owned(),
+            label: label_string(x.label()),
+            classifiers: x.classifier_meta_list().map(ClassifierMeta::from).collect(),
+            backfilled: false,
+        }
+    }
+}
+
+/// Position of a tracked object in an earlier frame, reported by the tracker later
+#[derive(Debug, Clone)]
+pub struct PastObject {
+    pub frame_num: i32,
+    pub object_id: u64,
+    pub class_id: i32,
+    pub label: String,
+    pub confidence: f32,
+    pub bbox: BBoxCorrds,
+}
+
+impl PastObject {
+    /// One per past frame of the object
+    pub fn from_list(l: &NvDsPastFrameObjList) -> Vec<Self> {
+        let label = label_string(l.label());
+        l.objects()
+            .iter()
+            .map(|o| Self {
+                frame_num: o.frame_num() as i32,
+                object_id: l.unique_id(),
+                class_id: l.class_id() as i32,
+                label: label.clone(),
+                confidence: o.confidence(),
+                bbox: BBoxCorrds::from(&o.bbox()),
+            })
+            .collect()
+    }
+
+    /// Object meta of the frame the object was missed in, the bbox is of the tracker
+    pub fn to_object(&self, detection_index: u32) -> ObjectMeta {
+        ObjectMeta {
+            detection_index,
+            class_id: self.class_id,
+            object_id: self.object_id,
+            detector_bbox_info: BBoxCorrds {
+                left: 0.0,
+                top: 0.0,
+                width: 0.0,
+                height: 0.0,
+            },
+            tracker_bbox_info: Some(self.bbox.clone()),
+            confidence: self.confidence,
+            label: self.label.clone(),
+            classifiers: vec![],
+            backfilled: true,
         }
     }
 }
@@ -51,26 +190,96 @@ pub struct BufferFrameInfo {
     width: u32,
     height: u32,
     frame_num: i32,
+    /// PTS of the batched buffer, 0 if unavailable. Kept for readers of the format
+    /// before [`TimestampPolicy`]
     pts: u64,
+    /// `ntp_timestamp`, the epoch if unavailable. Kept as `pts`
     #[serde(with = "ts_nanoseconds")]
     infer_ts: DateTime<Utc>,
+    /// canonical timestamp in nanoseconds selected by [`TimestampPolicy`]
+    timestamp: Option<u64>,
+    timestamp_source: Option<TimestampSource>,
+    #[serde(default)]
+    timestamps: FrameTimestamps,
 }
 
 impl BufferFrameInfo {
-    pub fn new(pts: u64, meta: &NvDsFrameMeta) -> Self {
-        let infer_ts = meta.ntp_timestamp();
-        let naive = NaiveDateTime::from_timestamp_opt(
-            infer_ts as i64 / 1_000_000_000,
-            (infer_ts % 1_000_000_000) as u32,
-        )
-        .unwrap();
+    pub fn new(
+        meta: &NvDsFrameMeta,
+        timestamps: FrameTimestamps,
+        policy: &TimestampPolicy,
+    ) -> Self {
+        let selected = policy.select(&timestamps);
+        let (pts, infer_ts) = Self::legacy_timestamps(&timestamps);
         Self {
             source_id: meta.source_id(),
             width: meta.source_frame_width(),
             height: meta.source_frame_height(),
             frame_num: meta.frame_num(),
             pts,
-            infer_ts: DateTime::<Utc>::from_utc(naive, Utc),
+            infer_ts,
+            timestamp: selected.map(|(t, _)| t),
+            timestamp_source: selected.map(|(_, s)| s),
+            timestamps,
+        }
+    }
+
+    pub fn source_id(&self) -> u32 {
+        self.source_id
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    pub fn frame_num(&self) -> i32 {
+        self.frame_num
+    }
+    pub fn pts(&self) -> u64 {
+        self.pts
+    }
+    pub fn infer_ts(&self) -> DateTime<Utc> {
+        self.infer_ts
+    }
+    /// canonical timestamp in nanoseconds
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+    pub fn timestamp_source(&self) -> Option<TimestampSource> {
+        self.timestamp_source
+    }
+    pub fn timestamps(&self) -> &FrameTimestamps {
+        &self.timestamps
+    }
+
+    /// `pts` and `infer_ts` derived from the timestamps
+    pub(crate) fn legacy_timestamps(ts: &FrameTimestamps) -> (u64, DateTime<Utc>) {
+        let infer_ts = Utc.timestamp_nanos(ts.ntp.unwrap_or(0) as i64);
+        (ts.buffer_pts.unwrap_or(0), infer_ts)
+    }
+}
+
+/// Summary of a segmentation output
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SegmentationStats {
+    pub width: u32,
+    pub height: u32,
+    pub classes: u32,
+    /// number of pixels by class id
+    pub class_areas: Vec<u64>,
+    /// number of connected regions by class id
+    pub class_regions: Vec<u32>,
+}
+
+impl From<&NvDsInferSegmentationMeta> for SegmentationStats {
+    fn from(x: &NvDsInferSegmentationMeta) -> Self {
+        Self {
+            width: x.width(),
+            height: x.height(),
+            classes: x.classes(),
+            class_areas: x.class_areas(),
+            class_regions: x.class_regions(),
         }
     }
 }
@@ -79,10 +288,261 @@ impl BufferFrameInfo {
 pub struct FrameObjects {
     frame: BufferFrameInfo,
     objects: Vec<ObjectMeta>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    segmentation: Vec<SegmentationStats>,
+    /// consumed by [`backfill::BackfillExporter`], not exported
+    #[serde(skip)]
+    past_objects: Vec<PastObject>,
 }
 
 impl FrameObjects {
     pub fn new(frame: BufferFrameInfo, objects: Vec<ObjectMeta>) -> Self {
-        Self { frame, objects }
+        Self {
+            frame,
+            objects,
+            segmentation: vec![],
+            past_objects: vec![],
+        }
+    }
+
+    pub fn with_past_objects(mut self, past_objects: Vec<PastObject>) -> Self {
+        self.past_objects = past_objects;
+        self
+    }
+
+    pub fn with_segmentation(mut self, segmentation: Vec<SegmentationStats>) -> Self {
+        self.segmentation = segmentation;
+        self
+    }
+
+    pub fn frame(&self) -> &BufferFrameInfo {
+        &self.frame
+    }
+    pub fn objects(&self) -> &[ObjectMeta] {
+        &self.objects
+    }
+    pub fn segmentation(&self) -> &[SegmentationStats] {
+        &self.segmentation
+    }
+    pub fn past_objects(&self) -> &[PastObject] {
+        &self.past_objects
+    }
+}
+
+/// Classification result of an audio frame by nvinferaudio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioFrame {
+    pub source_id: u32,
+    pub frame_num: i32,
+    /// buf_pts in nanoseconds
+    pub timestamp: Option<u64>,
+    pub sample_rate: u32,
+    pub num_channels: u32,
+    pub num_samples_per_frame: i32,
+    pub class_id: i32,
+    pub confidence: f32,
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub classifiers: Vec<ClassifierMeta>,
+}
+
+impl From<&NvDsAudioFrameMeta> for AudioFrame {
+    fn from(x: &NvDsAudioFrameMeta) -> Self {
+        Self {
+            source_id: x.source_id(),
+            frame_num: x.frame_num(),
+            timestamp: x.buf_pts().map(|t| t.nseconds()),
+            sample_rate: x.sample_rate(),
+            num_channels: x.num_channels(),
+            num_samples_per_frame: x.num_samples_per_frame(),
+            class_id: x.class_id(),
+            confidence: x.confidence(),
+            label: label_string(x.class_label()),
+            classifiers: x.classifier_meta_list().map(ClassifierMeta::from).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceState {
+    Added,
+    Removed,
+}
+
+/// Lifecycle event of a source connected to nvstreammux at runtime
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceEvent {
+    pub source_id: u32,
+    pub state: SourceState,
+    pub location: String,
+    #[serde(with = "ts_nanoseconds")]
+    pub timestamp: DateTime<Utc>,
+}
+
+impl SourceEvent {
+    pub fn new(source_id: u32, state: SourceState, location: &str) -> Self {
+        Self {
+            source_id,
+            state,
+            location: location.to_owned(),
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// A line of the export stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Record {
+    Frame(FrameObjects),
+    Source(SourceEvent),
+    /// Lifetime of a tracked object in the track export mode
+    Track(track::TrackEvent),
+    /// Latency percentiles of a source over a reporting interval
+    Latency(latency::LatencyEvent),
+    /// Audio frame of audio sources
+    Audio(AudioFrame),
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Inner exporter keeping written records
+    pub(crate) struct Collect(pub(crate) Arc<Mutex<Vec<Record>>>);
+
+    impl Collect {
+        pub(crate) fn boxed() -> (Box<dyn exporter::Exporter>, Arc<Mutex<Vec<Record>>>) {
+            let records = Arc::new(Mutex::new(vec![]));
+            (Box::new(Self(records.clone())), records)
+        }
+    }
+
+    impl exporter::Exporter for Collect {
+        fn write_record(&mut self, record: &Record) -> std::io::Result<()> {
+            self.0.lock().unwrap().push(record.clone());
+            Ok(())
+        }
+        fn finish(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn frame_info(
+        source_id: u32,
+        frame_num: i32,
+        timestamps: FrameTimestamps,
+    ) -> BufferFrameInfo {
+        let policy = TimestampPolicy::default();
+        let selected = policy.select(&timestamps);
+        let (pts, infer_ts) = BufferFrameInfo::legacy_timestamps(&timestamps);
+        BufferFrameInfo {
+            source_id,
+            width: 1920,
+            height: 1080,
+            frame_num,
+            pts,
+            infer_ts,
+            timestamp: selected.map(|(t, _)| t),
+            timestamp_source: selected.map(|(_, s)| s),
+            timestamps,
+        }
+    }
+
+    /// Frame of 1920x1080 without timestamps
+    pub(crate) fn frame(source_id: u32, frame_num: i32, objects: Vec<ObjectMeta>) -> FrameObjects {
+        FrameObjects::new(
+            frame_info(source_id, frame_num, FrameTimestamps::default()),
+            objects,
+        )
+    }
+
+    /// Object of `[left, top, width, height]`
+    pub(crate) fn object(
+        class_id: i32,
+        object_id: u64,
+        bbox: [f32; 4],
+        confidence: f32,
+    ) -> ObjectMeta {
+        ObjectMeta {
+            detection_index: 0,
+            class_id,
+            object_id,
+            detector_bbox_info: BBoxCorrds {
+                left: bbox[0],
+                top: bbox[1],
+                width: bbox[2],
+                height: bbox[3],
+            },
+            tracker_bbox_info: None,
+            confidence,
+            label: String::new(),
+            classifiers: vec![],
+            backfilled: false,
+        }
+    }
+
+    #[test]
+    fn frame_info_keeps_legacy_fields() {
+        let info = frame_info(
+            1,
+            3,
+            FrameTimestamps {
+                buffer_pts: Some(33_000_000),
+                ntp: Some(1_600_000_000_123_456_789),
+                ..Default::default()
+            },
+        );
+        let v = serde_json::to_value(&info).unwrap();
+        assert_eq!(v["pts"], 33_000_000);
+        assert_eq!(v["infer_ts"], 1_600_000_000_123_456_789u64);
+        assert_eq!(v["timestamp"], 1_600_000_000_123_456_789u64);
+        assert_eq!(v["timestamp_source"], "ntp");
+        assert_eq!(v["timestamps"]["buffer_pts"], 33_000_000);
+
+        let info = frame_info(1, 3, FrameTimestamps::default());
+        let v = serde_json::to_value(&info).unwrap();
+        assert_eq!(v["pts"], 0);
+        assert_eq!(v["infer_ts"], 0);
+        assert!(v["timestamp"].is_null());
+    }
+
+    #[test]
+    fn frame_info_reads_legacy_format() {
+        let s =
+            r#"{"source_id":0,"width":640,"height":480,"frame_num":5,"pts":100,"infer_ts":2000}"#;
+        let info: BufferFrameInfo = serde_json::from_str(s).unwrap();
+        assert_eq!(info.pts(), 100);
+        assert_eq!(info.infer_ts().timestamp_subsec_nanos(), 2000);
+        assert_eq!(info.timestamp(), None);
+        assert_eq!(info.timestamps().buffer_pts, None);
+    }
+
+    /// Write a C string into the label array of a zeroed meta, `field` locates the array
+    fn write_label<T>(meta: &mut T, field: fn(&T) -> &CStr, bytes: &[u8]) {
+        let offset = field(meta).as_ptr() as usize - meta as *const T as usize;
+        unsafe {
+            let dst = (meta as *mut T as *mut u8).add(offset);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst, bytes.len());
+            *dst.add(bytes.len()) = 0;
+        }
+    }
+
+    #[test]
+    fn invalid_labels_are_replaced() {
+        let mut meta: NvDsObjectMeta = unsafe { std::mem::zeroed() };
+        write_label(&mut meta, NvDsObjectMeta::label, b"car");
+        assert_eq!(ObjectMeta::from(&meta).label, "car");
+        write_label(&mut meta, NvDsObjectMeta::label, b"car\xff");
+        let o = ObjectMeta::from(&meta);
+        assert_eq!(o.label, "car\u{fffd}");
+        assert!(o.classifiers.is_empty());
+
+        let mut meta: NvDsAudioFrameMeta = unsafe { std::mem::zeroed() };
+        assert_eq!(AudioFrame::from(&meta).label, "");
+        write_label(&mut meta, NvDsAudioFrameMeta::class_label, b"\xe9t\xe9");
+        assert_eq!(AudioFrame::from(&meta).label, "\u{fffd}t\u{fffd}");
     }
 }
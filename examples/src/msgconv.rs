@@ -0,0 +1,825 @@
+//! Payloads compatible with the DeepStream nvmsgconv schema
+//!
+//! The full schema is one message per object with `sensor`, `place`, `analyticsModule`,
+//! `object` and `event`. The minimal schema is one message per frame with objects
+//! as `id|left|top|right|bottom|label` strings.
+//! Static descriptions come from a msgconv config file with `[sensorN]`, `[placeN]` and
+//! `[analyticsN]` sections, where `N` is the source id.
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use nvdsmeta_sys::UNTRACKED_OBJECT_ID;
+use serde::{Deserialize, Serialize};
+
+use crate::exporter::Exporter;
+use crate::infer_config::{config_section, ConfigValue, Error};
+use crate::{FrameObjects, ObjectMeta, Record};
+
+config_section! {
+    /// `[sensorN]` section
+    pub struct SensorConfig {
+        enable: bool => "enable",
+        sensor_type: String => "type",
+        id: String => "id",
+        description: String => "description",
+        /// `lat;lon;alt`
+        location: Vec<f64> => "location",
+        /// `x;y;z`
+        coordinate: Vec<f64> => "coordinate",
+    }
+}
+
+config_section! {
+    /// `[placeN]` section
+    pub struct PlaceConfig {
+        enable: bool => "enable",
+        id: String => "id",
+        place_type: String => "type",
+        name: String => "name",
+        location: Vec<f64> => "location",
+        coordinate: Vec<f64> => "coordinate",
+        sub_field1: String => "place-sub-field1",
+        sub_field2: String => "place-sub-field2",
+        sub_field3: String => "place-sub-field3",
+    }
+}
+
+config_section! {
+    /// `[analyticsN]` section
+    pub struct AnalyticsConfig {
+        enable: bool => "enable",
+        id: String => "id",
+        description: String => "description",
+        source: String => "source",
+        version: String => "version",
+    }
+}
+
+/// msgconv config file, sections are keyed by the source id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MsgConvConfig {
+    pub sensors: BTreeMap<u32, SensorConfig>,
+    pub places: BTreeMap<u32, PlaceConfig>,
+    pub analytics: BTreeMap<u32, AnalyticsConfig>,
+}
+
+enum Section {
+    Sensor(u32),
+    Place(u32),
+    Analytics(u32),
+}
+
+impl MsgConvConfig {
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut config = MsgConvConfig::default();
+        let mut section = None;
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse_err = |message: String| Error::Parse {
+                line: i + 1,
+                message,
+            };
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let index = |prefix: &str| name.strip_prefix(prefix)?.parse().ok();
+                section = Some(if let Some(id) = index("sensor") {
+                    Section::Sensor(id)
+                } else if let Some(id) = index("place") {
+                    Section::Place(id)
+                } else if let Some(id) = index("analytics") {
+                    Section::Analytics(id)
+                } else {
+                    return Err(parse_err(format!("unknown section [{}]", name)));
+                });
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| parse_err(format!("expect key=value: {:?}", line)))?;
+            let (key, value) = (key.trim(), value.trim());
+            let result = match section {
+                None => return Err(parse_err(format!("{} is outside of a section", key))),
+                Some(Section::Sensor(id)) => config.sensors.entry(id).or_default().set(key, value),
+                Some(Section::Place(id)) => config.places.entry(id).or_default().set(key, value),
+                Some(Section::Analytics(id)) => {
+                    config.analytics.entry(id).or_default().set(key, value)
+                }
+            };
+            result.map_err(parse_err)?;
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        for (id, s) in self.sensors.iter() {
+            writeln!(w, "[sensor{}]", id)?;
+            s.write_to(w)?;
+            writeln!(w)?;
+        }
+        for (id, s) in self.places.iter() {
+            writeln!(w, "[place{}]", id)?;
+            s.write_to(w)?;
+            writeln!(w)?;
+        }
+        for (id, s) in self.analytics.iter() {
+            writeln!(w, "[analytics{}]", id)?;
+            s.write_to(w)?;
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    /// Section of the source if enabled, `enable` defaults to true
+    fn section<T>(
+        sections: &BTreeMap<u32, T>,
+        source_id: u32,
+        enable: impl Fn(&T) -> Option<bool>,
+    ) -> Option<&T> {
+        sections
+            .get(&source_id)
+            .filter(|s| enable(s).unwrap_or(true))
+    }
+}
+
+/// `msg-conv-payload-type` of nvmsgconv
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadType {
+    /// one message per object
+    #[default]
+    Full,
+    /// one message per frame
+    Minimal,
+}
+
+impl std::str::FromStr for PayloadType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" | "0" => Ok(PayloadType::Full),
+            "minimal" | "1" => Ok(PayloadType::Minimal),
+            _ => Err(format!(
+                "unknown payload type {:?}, expect full or minimal",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GeoLocation {
+    pub lat: f64,
+    pub lon: f64,
+    pub alt: f64,
+}
+
+impl GeoLocation {
+    fn from_values(v: Option<&Vec<f64>>) -> Self {
+        let v = v.map(Vec::as_slice).unwrap_or_default();
+        let at = |i: usize| v.get(i).copied().unwrap_or_default();
+        Self {
+            lat: at(0),
+            lon: at(1),
+            alt: at(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Coordinate {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Coordinate {
+    fn from_values(v: Option<&Vec<f64>>) -> Self {
+        let v = v.map(Vec::as_slice).unwrap_or_default();
+        let at = |i: usize| v.get(i).copied().unwrap_or_default();
+        Self {
+            x: at(0),
+            y: at(1),
+            z: at(2),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Sensor {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub sensor_type: String,
+    pub description: String,
+    pub location: GeoLocation,
+    pub coordinate: Coordinate,
+}
+
+/// Sub place of `entrance`, `aisle` or `parkingSpot` types
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubPlace {
+    pub id: String,
+    pub name: String,
+    pub level: String,
+    pub coordinate: Coordinate,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Place {
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub place_type: String,
+    pub location: GeoLocation,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entrance: Option<SubPlace>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aisle: Option<SubPlace>,
+    #[serde(rename = "parkingSpot", skip_serializing_if = "Option::is_none")]
+    pub parking_spot: Option<SubPlace>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AnalyticsModule {
+    pub id: String,
+    pub description: String,
+    pub source: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BBox {
+    pub topleftx: i32,
+    pub toplefty: i32,
+    pub bottomrightx: i32,
+    pub bottomrighty: i32,
+}
+
+/// Attributes of the object keyed by its label such as `"vehicle": {...}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ObjectAttributes {
+    pub confidence: f64,
+    /// labels of secondary classifiers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attributes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Object {
+    pub id: String,
+    pub speed: f64,
+    pub direction: f64,
+    pub orientation: f64,
+    #[serde(flatten)]
+    pub attributes: BTreeMap<String, ObjectAttributes>,
+    pub bbox: BBox,
+    pub location: GeoLocation,
+    pub coordinate: Coordinate,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+}
+
+/// Message of the full schema
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FullPayload {
+    pub messageid: String,
+    pub mdsversion: String,
+    #[serde(rename = "@timestamp")]
+    pub timestamp: String,
+    pub place: Place,
+    pub sensor: Sensor,
+    #[serde(rename = "analyticsModule")]
+    pub analytics_module: AnalyticsModule,
+    pub object: Object,
+    pub event: Event,
+    #[serde(rename = "videoPath")]
+    pub video_path: String,
+}
+
+/// Message of the minimal schema
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinimalPayload {
+    pub version: String,
+    pub id: String,
+    #[serde(rename = "@timestamp")]
+    pub timestamp: String,
+    #[serde(rename = "sensorId")]
+    pub sensor_id: String,
+    pub objects: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Payload {
+    Full(Box<FullPayload>),
+    Minimal(MinimalPayload),
+}
+
+/// Convert frames into nvmsgconv payloads
+#[derive(Debug, Clone)]
+pub struct MsgConv {
+    config: MsgConvConfig,
+    payload_type: PayloadType,
+    event_type: String,
+}
+
+impl MsgConv {
+    pub fn new(config: MsgConvConfig, payload_type: PayloadType) -> Self {
+        Self {
+            config,
+            payload_type,
+            event_type: "moving".to_owned(),
+        }
+    }
+
+    /// `event.type` of full payloads, `moving` by default
+    pub fn with_event_type(mut self, event_type: &str) -> Self {
+        self.event_type = event_type.to_owned();
+        self
+    }
+
+    pub fn payload_type(&self) -> PayloadType {
+        self.payload_type
+    }
+
+    pub fn payloads(&self, f: &FrameObjects) -> Vec<Payload> {
+        match self.payload_type {
+            PayloadType::Full => self
+                .full_payloads(f)
+                .map(|p| Payload::Full(Box::new(p)))
+                .collect(),
+            PayloadType::Minimal => vec![Payload::Minimal(self.minimal_payload(f))],
+        }
+    }
+
+    /// Wall clock time of the frame by NTP or the arrival system time, or now if unavailable
+    ///
+    /// The timestamp selected by the policy may be a pts or a running time.
+    fn timestamp(f: &FrameObjects) -> String {
+        let ts = f.frame().timestamps();
+        let t = ts
+            .ntp
+            .or(ts.arrival_system)
+            .and_then(|t| nanos_to_datetime(t as i64))
+            .unwrap_or_else(Utc::now);
+        t.to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+
+    fn sensor_id(&self, source_id: u32) -> String {
+        MsgConvConfig::section(&self.config.sensors, source_id, |s| s.enable)
+            .and_then(|s| s.id.clone())
+            .unwrap_or_else(|| source_id.to_string())
+    }
+
+    fn sensor(&self, source_id: u32) -> Sensor {
+        let s = match MsgConvConfig::section(&self.config.sensors, source_id, |s| s.enable) {
+            Some(s) => s,
+            None => {
+                return Sensor {
+                    id: source_id.to_string(),
+                    ..Default::default()
+                }
+            }
+        };
+        Sensor {
+            id: self.sensor_id(source_id),
+            sensor_type: s.sensor_type.clone().unwrap_or_default(),
+            description: s.description.clone().unwrap_or_default(),
+            location: GeoLocation::from_values(s.location.as_ref()),
+            coordinate: Coordinate::from_values(s.coordinate.as_ref()),
+        }
+    }
+
+    fn place(&self, source_id: u32) -> Place {
+        let p = match MsgConvConfig::section(&self.config.places, source_id, |s| s.enable) {
+            Some(p) => p,
+            None => return Place::default(),
+        };
+        let place_type = p.place_type.clone().unwrap_or_default();
+        let sub = SubPlace {
+            id: p.sub_field1.clone().unwrap_or_default(),
+            name: p.sub_field2.clone().unwrap_or_default(),
+            level: p.sub_field3.clone().unwrap_or_default(),
+            coordinate: Coordinate::from_values(p.coordinate.as_ref()),
+        };
+        let mut place = Place {
+            id: p.id.clone().unwrap_or_default(),
+            name: p.name.clone().unwrap_or_default(),
+            location: GeoLocation::from_values(p.location.as_ref()),
+            ..Default::default()
+        };
+        // nvmsgconv chooses the sub place by the place type
+        if place_type.contains("entrance") {
+            place.entrance = Some(sub);
+        } else if place_type.contains("aisle") {
+            place.aisle = Some(sub);
+        } else if place_type.contains("parking") {
+            place.parking_spot = Some(sub);
+        }
+        place.place_type = place_type;
+        place
+    }
+
+    fn analytics_module(&self, source_id: u32) -> AnalyticsModule {
+        // a single analytics section is shared by all sources
+        let a = MsgConvConfig::section(&self.config.analytics, source_id, |s| s.enable)
+            .or_else(|| MsgConvConfig::section(&self.config.analytics, 0, |s| s.enable));
+        match a {
+            Some(a) => AnalyticsModule {
+                id: a.id.clone().unwrap_or_default(),
+                description: a.description.clone().unwrap_or_default(),
+                source: a.source.clone().unwrap_or_default(),
+                version: a.version.clone().unwrap_or_default(),
+            },
+            None => AnalyticsModule::default(),
+        }
+    }
+
+    fn object(o: &ObjectMeta) -> Object {
+        let b = o.bbox();
+        let attributes = ObjectAttributes {
+            confidence: o.confidence as f64,
+            attributes: classifier_labels(o).collect(),
+        };
+        Object {
+            id: object_id(o),
+            bbox: BBox {
+                topleftx: b.left as i32,
+                toplefty: b.top as i32,
+                bottomrightx: (b.left + b.width) as i32,
+                bottomrighty: (b.top + b.height) as i32,
+            },
+            attributes: BTreeMap::from([(o.label.to_lowercase(), attributes)]),
+            ..Default::default()
+        }
+    }
+
+    pub fn full_payloads<'a>(
+        &'a self,
+        f: &'a FrameObjects,
+    ) -> impl Iterator<Item = FullPayload> + 'a {
+        let source_id = f.frame().source_id();
+        let timestamp = Self::timestamp(f);
+        let place = self.place(source_id);
+        let sensor = self.sensor(source_id);
+        let analytics_module = self.analytics_module(source_id);
+        f.objects().iter().map(move |o| FullPayload {
+            messageid: uuid::Uuid::new_v4().to_string(),
+            mdsversion: "1.0".to_owned(),
+            timestamp: timestamp.clone(),
+            place: place.clone(),
+            sensor: sensor.clone(),
+            analytics_module: analytics_module.clone(),
+            object: Self::object(o),
+            event: Event {
+                id: uuid::Uuid::new_v4().to_string(),
+                event_type: self.event_type.clone(),
+            },
+            video_path: String::new(),
+        })
+    }
+
+    pub fn minimal_payload(&self, f: &FrameObjects) -> MinimalPayload {
+        let objects = f
+            .objects()
+            .iter()
+            .map(|o| {
+                let b = o.bbox();
+                let mut s = format!(
+                    "{}|{}|{}|{}|{}|{}",
+                    object_id(o),
+                    b.left as i32,
+                    b.top as i32,
+                    (b.left + b.width) as i32,
+                    (b.top + b.height) as i32,
+                    o.label
+                );
+                let attributes = classifier_labels(o).collect::<Vec<_>>();
+                if !attributes.is_empty() {
+                    s.push_str("|#|");
+                    s.push_str(&attributes.join("|"));
+                }
+                s.push_str(&format!("|{}", o.confidence));
+                s
+            })
+            .collect();
+        MinimalPayload {
+            version: "4.0".to_owned(),
+            id: f.frame().frame_num().to_string(),
+            timestamp: Self::timestamp(f),
+            sensor_id: self.sensor_id(f.frame().source_id()),
+            objects,
+        }
+    }
+}
+
+/// Tracking id, or -1 as nvmsgconv if untracked
+fn object_id(o: &ObjectMeta) -> String {
+    if o.object_id == UNTRACKED_OBJECT_ID {
+        "-1".to_owned()
+    } else {
+        o.object_id.to_string()
+    }
+}
+
+fn classifier_labels(o: &ObjectMeta) -> impl Iterator<Item = String> + '_ {
+    o.classifiers
+        .iter()
+        .flat_map(|c| c.labels.iter().map(|l| l.label.clone()))
+}
+
+fn nanos_to_datetime(nanos: i64) -> Option<DateTime<Utc>> {
+    Utc.timestamp_opt(
+        nanos.div_euclid(1_000_000_000),
+        nanos.rem_euclid(1_000_000_000) as u32,
+    )
+    .single()
+}
+
+/// Write payloads of frames as JSON Lines
+pub struct MsgConvExporter<W> {
+    w: W,
+    msgconv: MsgConv,
+}
+
+impl<W: io::Write> MsgConvExporter<W> {
+    pub fn new(w: W, msgconv: MsgConv) -> Self {
+        Self { w, msgconv }
+    }
+}
+
+impl<W: io::Write + Send> Exporter for MsgConvExporter<W> {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        let f = match record {
+            Record::Frame(f) => f,
+            _ => return Ok(()),
+        };
+        for p in self.msgconv.payloads(f) {
+            serde_json::to_writer(&mut self.w, &p)?;
+            writeln!(&mut self.w)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{frame, frame_info, object};
+    use crate::timestamp::FrameTimestamps;
+    use crate::{BufferFrameInfo, ClassifierMeta, LabelInfo, SourceEvent, SourceState};
+
+    const CONFIG: &str = "\
+[sensor0]
+enable=1
+type=Camera
+id=CAMERA_ID
+location=45.293701447;-75.8303914499;48.1557479338
+description=Entrance of Endeavor Garage Right Lane
+coordinate=5.2;10.1;11.2
+
+[sensor1]
+enable=0
+id=DISABLED
+
+[place0]
+id=1
+type=intersection/road
+name=HWY_20_AND_LOCUST__EBA
+location=30.32;-40.55;100.0
+coordinate=1.0;2.0;3.0
+place-sub-field1=C_127_158
+place-sub-field2=Lane 1
+place-sub-field3=P1
+
+[place1]
+type=garage/entrance
+place-sub-field1=walsh
+place-sub-field2=lane1
+place-sub-field3=P2
+
+[analytics0]
+enable=1
+id=XYZ_1
+description=Vehicle Detection and License Plate Recognition
+source=OpenALR
+version=1.0
+";
+
+    fn msgconv(payload_type: PayloadType) -> MsgConv {
+        MsgConv::new(MsgConvConfig::parse(CONFIG).unwrap(), payload_type)
+    }
+
+    /// Frame at 2020-09-13T12:26:40.123Z by NTP time
+    fn timed_frame(source_id: u32, objects: Vec<ObjectMeta>) -> FrameObjects {
+        let timestamps = FrameTimestamps {
+            ntp: Some(1_600_000_000_123_000_000),
+            ..Default::default()
+        };
+        FrameObjects::new(frame_info(source_id, 7, timestamps), objects)
+    }
+
+    /// Tracked car with a color classifier and an untracked person
+    fn objects() -> Vec<ObjectMeta> {
+        let mut car = object(0, 3, [10.6, 20.2, 30.0, 40.5], 0.75);
+        car.label = "Car".to_owned();
+        car.classifiers = vec![ClassifierMeta {
+            unique_component_id: 2,
+            labels: vec![LabelInfo {
+                label_id: 0,
+                class_id: 1,
+                prob: 0.9,
+                label: "red".to_owned(),
+            }],
+        }];
+        let mut person = object(2, UNTRACKED_OBJECT_ID, [100., 200., 50., 100.], 0.5);
+        person.label = "Person".to_owned();
+        vec![car, person]
+    }
+
+    #[test]
+    fn parse_sections() {
+        let c = MsgConvConfig::parse(CONFIG).unwrap();
+        let sensor = &c.sensors[&0];
+        assert_eq!(sensor.enable, Some(true));
+        assert_eq!(sensor.id.as_deref(), Some("CAMERA_ID"));
+        assert_eq!(sensor.coordinate, Some(vec![5.2, 10.1, 11.2]));
+        assert_eq!(c.sensors[&1].enable, Some(false));
+        assert_eq!(c.places[&1].place_type.as_deref(), Some("garage/entrance"));
+        assert_eq!(c.analytics[&0].source.as_deref(), Some("OpenALR"));
+
+        let mut written = vec![];
+        c.write_to(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(MsgConvConfig::parse(&written).unwrap(), c);
+    }
+
+    #[test]
+    fn parse_errors_have_line_numbers() {
+        let line = |s: &str| match MsgConvConfig::parse(s) {
+            Err(Error::Parse { line, .. }) => line,
+            r => panic!("unexpected {:?}", r),
+        };
+        assert_eq!(line("[sensor0]\n\n[camera0]\n"), 3);
+        assert_eq!(line("# comment\nid=0\n"), 2);
+        assert_eq!(line("[sensor0]\nid\n"), 2);
+        assert_eq!(line("[sensor0]\nenable=yes\n"), 2);
+        assert_eq!(line("[sensorX]\n"), 1);
+    }
+
+    #[test]
+    fn full_payload_per_object() {
+        let f = timed_frame(0, objects());
+        let payloads = msgconv(PayloadType::Full)
+            .full_payloads(&f)
+            .collect::<Vec<_>>();
+        assert_eq!(payloads.len(), 2);
+
+        let p = &payloads[0];
+        assert_eq!(p.timestamp, "2020-09-13T12:26:40.123Z");
+        assert_eq!(p.sensor.id, "CAMERA_ID");
+        assert_eq!(p.sensor.sensor_type, "Camera");
+        assert_eq!(p.sensor.location.lat, 45.293701447);
+        assert_eq!(p.sensor.coordinate.z, 11.2);
+        assert_eq!(p.place.id, "1");
+        assert_eq!(p.place.place_type, "intersection/road");
+        assert!(p.place.entrance.is_none() && p.place.aisle.is_none());
+        assert_eq!(p.analytics_module.id, "XYZ_1");
+        assert_eq!(p.event.event_type, "moving");
+        assert_ne!(p.messageid, payloads[1].messageid);
+
+        // bbox corners are truncated as nvmsgconv casts them to int
+        let o = &p.object;
+        assert_eq!(o.id, "3");
+        assert_eq!(
+            (
+                o.bbox.topleftx,
+                o.bbox.toplefty,
+                o.bbox.bottomrightx,
+                o.bbox.bottomrighty
+            ),
+            (10, 20, 40, 60)
+        );
+        let car = &o.attributes["car"];
+        assert_eq!(car.confidence, 0.75);
+        assert_eq!(car.attributes, ["red"]);
+        assert_eq!(payloads[1].object.id, "-1");
+
+        let json = serde_json::to_value(&payloads[1]).unwrap();
+        assert_eq!(json["object"]["person"]["confidence"], 0.5);
+        assert!(json["object"]["person"].get("attributes").is_none());
+        assert_eq!(json["@timestamp"], "2020-09-13T12:26:40.123Z");
+        assert!(json["analyticsModule"].is_object());
+    }
+
+    #[test]
+    fn disabled_or_missing_sections() {
+        let m = msgconv(PayloadType::Full).with_event_type("entry");
+        let f = timed_frame(1, objects());
+        let p = m.full_payloads(&f).next().unwrap();
+        // sensor1 is disabled
+        assert_eq!(p.sensor.id, "1");
+        assert_eq!(p.sensor.sensor_type, "");
+        // the sub place is chosen by the place type
+        let entrance = p.place.entrance.unwrap();
+        assert_eq!(
+            (
+                entrance.id.as_str(),
+                entrance.name.as_str(),
+                entrance.level.as_str()
+            ),
+            ("walsh", "lane1", "P2")
+        );
+        // analytics0 is shared by all sources
+        assert_eq!(p.analytics_module.id, "XYZ_1");
+        assert_eq!(p.event.event_type, "entry");
+
+        let f = timed_frame(5, objects());
+        let p = m.full_payloads(&f).next().unwrap();
+        assert_eq!(p.sensor.id, "5");
+        assert_eq!(p.place.id, "");
+    }
+
+    #[test]
+    fn minimal_payload_per_frame() {
+        let f = timed_frame(0, objects());
+        let payloads = msgconv(PayloadType::Minimal).payloads(&f);
+        assert_eq!(payloads.len(), 1);
+        let p = match &payloads[0] {
+            Payload::Minimal(p) => p,
+            p => panic!("unexpected {:?}", p),
+        };
+        assert_eq!(p.id, "7");
+        assert_eq!(p.sensor_id, "CAMERA_ID");
+        assert_eq!(p.timestamp, "2020-09-13T12:26:40.123Z");
+        assert_eq!(
+            p.objects,
+            [
+                "3|10|20|40|60|Car|#|red|0.75",
+                "-1|100|200|150|300|Person|0.5"
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamp_is_wall_clock_time() {
+        // the policy selected buffer_pts, which is not a unix time
+        let info: BufferFrameInfo = serde_json::from_str(
+            r#"{"source_id":0,"width":640,"height":480,"frame_num":5,"pts":33000000,
+            "infer_ts":0,"timestamp":33000000,"timestamp_source":"buffer_pts",
+            "timestamps":{"buffer_pts":33000000,"arrival_system":1600000000123000000}}"#,
+        )
+        .unwrap();
+        assert_eq!(info.timestamp(), Some(33_000_000));
+        let f = FrameObjects::new(info, vec![]);
+        assert_eq!(MsgConv::timestamp(&f), "2020-09-13T12:26:40.123Z");
+
+        let timestamps = FrameTimestamps {
+            buffer_pts: Some(33_000_000),
+            arrival_running: Some(66_000_000),
+            ..Default::default()
+        };
+        let f = FrameObjects::new(frame_info(0, 5, timestamps), vec![]);
+        let now = Utc::now();
+        let t = DateTime::parse_from_rfc3339(&MsgConv::timestamp(&f)).unwrap();
+        assert!((t.with_timezone(&Utc) - now).num_seconds().abs() < 10);
+    }
+
+    #[test]
+    fn exporter_writes_payload_lines_of_frames() {
+        let records = [
+            Record::Source(SourceEvent::new(0, SourceState::Added, "file:///a.mp4")),
+            Record::Frame(timed_frame(0, objects())),
+            Record::Frame(frame(0, 8, vec![])),
+        ];
+        let mut e = MsgConvExporter::new(vec![], msgconv(PayloadType::Full));
+        for r in records.iter() {
+            e.write_record(r).unwrap();
+        }
+        e.finish().unwrap();
+        let lines = std::str::from_utf8(&e.w)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let p: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(p["object"]["id"], "-1");
+    }
+}
@@ -0,0 +1,1022 @@
+//! Protobuf wire format of [`Record`](crate::Record)
+//!
+//! Messages are derived by hand to mirror `proto/nvdsmeta.proto`, so no protoc is
+//! needed at build time. Keep both in sync and never reuse a tag.
+//! Records are framed by a varint length prefix as `writeDelimitedTo` of protobuf.
+//!
+//! Readers skip records of kinds and enum values added by newer writers, so adding
+//! them is compatible and does not bump [`SCHEMA_VERSION`].
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use chrono::{DateTime, TimeZone, Utc};
+use prost::Message;
+
+use crate::{latency, timestamp, track};
+
+/// Version written to every record, bumped only on incompatible changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Upper limit of the length prefix, larger ones are taken as a broken stream
+pub const MAX_RECORD_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Decode(#[from] prost::DecodeError),
+    #[error("unsupported schema version {0}, expect {SCHEMA_VERSION} or older")]
+    UnsupportedVersion(u32),
+    #[error("missing field {0}")]
+    MissingField(&'static str),
+    #[error("invalid value of {0}")]
+    InvalidValue(&'static str),
+    /// Kind or enum value unknown to this reader, written by a newer writer
+    #[error("unknown {0}")]
+    Unknown(&'static str),
+    #[error("record of {0} bytes exceeds {MAX_RECORD_SIZE} bytes")]
+    TooLarge(usize),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct BBox {
+    #[prost(float, tag = "1")]
+    pub left: f32,
+    #[prost(float, tag = "2")]
+    pub top: f32,
+    #[prost(float, tag = "3")]
+    pub width: f32,
+    #[prost(float, tag = "4")]
+    pub height: f32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LabelInfo {
+    #[prost(uint32, tag = "1")]
+    pub label_id: u32,
+    #[prost(uint32, tag = "2")]
+    pub class_id: u32,
+    #[prost(float, tag = "3")]
+    pub prob: f32,
+    #[prost(string, tag = "4")]
+    pub label: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ClassifierMeta {
+    #[prost(int32, tag = "1")]
+    pub unique_component_id: i32,
+    #[prost(message, repeated, tag = "2")]
+    pub labels: Vec<LabelInfo>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ObjectMeta {
+    #[prost(uint32, tag = "1")]
+    pub detection_index: u32,
+    #[prost(int32, tag = "2")]
+    pub class_id: i32,
+    #[prost(uint64, tag = "3")]
+    pub object_id: u64,
+    #[prost(message, optional, tag = "4")]
+    pub detector_bbox_info: Option<BBox>,
+    #[prost(float, tag = "5")]
+    pub confidence: f32,
+    #[prost(string, tag = "6")]
+    pub label: String,
+    #[prost(message, repeated, tag = "7")]
+    pub classifiers: Vec<ClassifierMeta>,
+    #[prost(bool, tag = "8")]
+    pub backfilled: bool,
+    #[prost(message, optional, tag = "9")]
+    pub tracker_bbox_info: Option<BBox>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TimestampSource {
+    Unspecified = 0,
+    BufPts = 1,
+    BufferPts = 2,
+    Ntp = 3,
+    ArrivalSystem = 4,
+    ArrivalRunning = 5,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FrameTimestamps {
+    #[prost(uint64, optional, tag = "1")]
+    pub buf_pts: Option<u64>,
+    #[prost(uint64, optional, tag = "2")]
+    pub buffer_pts: Option<u64>,
+    #[prost(uint64, optional, tag = "3")]
+    pub ntp: Option<u64>,
+    #[prost(uint64, optional, tag = "4")]
+    pub arrival_system: Option<u64>,
+    #[prost(uint64, optional, tag = "5")]
+    pub arrival_running: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FrameInfo {
+    #[prost(uint32, tag = "1")]
+    pub source_id: u32,
+    #[prost(uint32, tag = "2")]
+    pub width: u32,
+    #[prost(uint32, tag = "3")]
+    pub height: u32,
+    #[prost(int32, tag = "4")]
+    pub frame_num: i32,
+    #[prost(uint64, optional, tag = "5")]
+    pub timestamp: Option<u64>,
+    #[prost(enumeration = "TimestampSource", tag = "6")]
+    pub timestamp_source: i32,
+    #[prost(message, optional, tag = "7")]
+    pub timestamps: Option<FrameTimestamps>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FrameObjects {
+    #[prost(message, optional, tag = "1")]
+    pub frame: Option<FrameInfo>,
+    #[prost(message, repeated, tag = "2")]
+    pub objects: Vec<ObjectMeta>,
+    #[prost(message, repeated, tag = "3")]
+    pub segmentation: Vec<SegmentationStats>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SegmentationStats {
+    #[prost(uint32, tag = "1")]
+    pub width: u32,
+    #[prost(uint32, tag = "2")]
+    pub height: u32,
+    #[prost(uint32, tag = "3")]
+    pub classes: u32,
+    #[prost(uint64, repeated, tag = "4")]
+    pub class_areas: Vec<u64>,
+    #[prost(uint32, repeated, tag = "5")]
+    pub class_regions: Vec<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum SourceState {
+    Unspecified = 0,
+    Added = 1,
+    Removed = 2,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SourceEvent {
+    #[prost(uint32, tag = "1")]
+    pub source_id: u32,
+    #[prost(enumeration = "SourceState", tag = "2")]
+    pub state: i32,
+    #[prost(string, tag = "3")]
+    pub location: String,
+    /// unix time in nanoseconds
+    #[prost(int64, tag = "4")]
+    pub timestamp: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TrackSnapshot {
+    #[prost(int32, tag = "1")]
+    pub frame_num: i32,
+    #[prost(uint64, optional, tag = "2")]
+    pub timestamp: Option<u64>,
+    #[prost(float, tag = "3")]
+    pub confidence: f32,
+    #[prost(message, optional, tag = "4")]
+    pub bbox: Option<BBox>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TrackEvent {
+    #[prost(uint32, tag = "1")]
+    pub source_id: u32,
+    #[prost(uint64, tag = "2")]
+    pub object_id: u64,
+    #[prost(int32, tag = "3")]
+    pub first_frame: i32,
+    #[prost(int32, tag = "4")]
+    pub last_frame: i32,
+    #[prost(uint64, optional, tag = "5")]
+    pub first_timestamp: Option<u64>,
+    #[prost(uint64, optional, tag = "6")]
+    pub last_timestamp: Option<u64>,
+    #[prost(uint32, tag = "7")]
+    pub length: u32,
+    #[prost(int32, tag = "8")]
+    pub class_id: i32,
+    #[prost(string, tag = "9")]
+    pub label: String,
+    #[prost(btree_map = "int32, uint32", tag = "10")]
+    pub class_votes: BTreeMap<i32, u32>,
+    #[prost(message, optional, tag = "11")]
+    pub best: Option<TrackSnapshot>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LatencyStats {
+    #[prost(uint64, tag = "1")]
+    pub count: u64,
+    #[prost(double, tag = "2")]
+    pub mean: f64,
+    #[prost(double, tag = "3")]
+    pub p50: f64,
+    #[prost(double, tag = "4")]
+    pub p95: f64,
+    #[prost(double, tag = "5")]
+    pub p99: f64,
+    #[prost(double, tag = "6")]
+    pub max: f64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct LatencyEvent {
+    #[prost(uint32, tag = "1")]
+    pub source_id: u32,
+    /// unix time in nanoseconds
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+    #[prost(message, optional, tag = "3")]
+    pub frame: Option<LatencyStats>,
+    #[prost(btree_map = "string, message", tag = "4")]
+    pub components: BTreeMap<String, LatencyStats>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AudioFrame {
+    #[prost(uint32, tag = "1")]
+    pub source_id: u32,
+    #[prost(int32, tag = "2")]
+    pub frame_num: i32,
+    /// buf_pts in nanoseconds
+    #[prost(uint64, optional, tag = "3")]
+    pub timestamp: Option<u64>,
+    #[prost(uint32, tag = "4")]
+    pub sample_rate: u32,
+    #[prost(uint32, tag = "5")]
+    pub num_channels: u32,
+    #[prost(int32, tag = "6")]
+    pub num_samples_per_frame: i32,
+    #[prost(int32, tag = "7")]
+    pub class_id: i32,
+    #[prost(float, tag = "8")]
+    pub confidence: f32,
+    #[prost(string, tag = "9")]
+    pub label: String,
+    #[prost(message, repeated, tag = "10")]
+    pub classifiers: Vec<ClassifierMeta>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Record {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+    #[prost(oneof = "record::Kind", tags = "2, 3, 4, 5, 6")]
+    pub kind: Option<record::Kind>,
+}
+
+pub mod record {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Kind {
+        #[prost(message, tag = "2")]
+        Frame(super::FrameObjects),
+        #[prost(message, tag = "3")]
+        Source(super::SourceEvent),
+        #[prost(message, tag = "4")]
+        Track(super::TrackEvent),
+        #[prost(message, tag = "5")]
+        Latency(super::LatencyEvent),
+        #[prost(message, tag = "6")]
+        Audio(super::AudioFrame),
+    }
+}
+
+impl From<&crate::BBoxCorrds> for BBox {
+    fn from(b: &crate::BBoxCorrds) -> Self {
+        Self {
+            left: b.left,
+            top: b.top,
+            width: b.width,
+            height: b.height,
+        }
+    }
+}
+
+impl From<BBox> for crate::BBoxCorrds {
+    fn from(b: BBox) -> Self {
+        Self {
+            left: b.left,
+            top: b.top,
+            width: b.width,
+            height: b.height,
+        }
+    }
+}
+
+impl From<&crate::ClassifierMeta> for ClassifierMeta {
+    fn from(c: &crate::ClassifierMeta) -> Self {
+        Self {
+            unique_component_id: c.unique_component_id,
+            labels: c
+                .labels
+                .iter()
+                .map(|l| LabelInfo {
+                    label_id: l.label_id,
+                    class_id: l.class_id,
+                    prob: l.prob,
+                    label: l.label.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<ClassifierMeta> for crate::ClassifierMeta {
+    fn from(c: ClassifierMeta) -> Self {
+        Self {
+            unique_component_id: c.unique_component_id,
+            labels: c
+                .labels
+                .into_iter()
+                .map(|l| crate::LabelInfo {
+                    label_id: l.label_id,
+                    class_id: l.class_id,
+                    prob: l.prob,
+                    label: l.label,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&crate::ObjectMeta> for ObjectMeta {
+    fn from(o: &crate::ObjectMeta) -> Self {
+        Self {
+            detection_index: o.detection_index,
+            class_id: o.class_id,
+            object_id: o.object_id,
+            detector_bbox_info: Some(BBox::from(&o.detector_bbox_info)),
+            confidence: o.confidence,
+            label: o.label.clone(),
+            classifiers: o.classifiers.iter().map(ClassifierMeta::from).collect(),
+            backfilled: o.backfilled,
+            tracker_bbox_info: o.tracker_bbox_info.as_ref().map(BBox::from),
+        }
+    }
+}
+
+impl TryFrom<ObjectMeta> for crate::ObjectMeta {
+    type Error = Error;
+
+    fn try_from(o: ObjectMeta) -> Result<Self, Self::Error> {
+        Ok(Self {
+            detection_index: o.detection_index,
+            class_id: o.class_id,
+            object_id: o.object_id,
+            detector_bbox_info: o
+                .detector_bbox_info
+                .ok_or(Error::MissingField("detector_bbox_info"))?
+                .into(),
+            tracker_bbox_info: o.tracker_bbox_info.map(Into::into),
+            confidence: o.confidence,
+            label: o.label,
+            classifiers: o.classifiers.into_iter().map(Into::into).collect(),
+            backfilled: o.backfilled,
+        })
+    }
+}
+
+impl From<Option<timestamp::TimestampSource>> for TimestampSource {
+    fn from(s: Option<timestamp::TimestampSource>) -> Self {
+        match s {
+            None => TimestampSource::Unspecified,
+            Some(timestamp::TimestampSource::BufPts) => TimestampSource::BufPts,
+            Some(timestamp::TimestampSource::BufferPts) => TimestampSource::BufferPts,
+            Some(timestamp::TimestampSource::Ntp) => TimestampSource::Ntp,
+            Some(timestamp::TimestampSource::ArrivalSystem) => TimestampSource::ArrivalSystem,
+            Some(timestamp::TimestampSource::ArrivalRunning) => TimestampSource::ArrivalRunning,
+        }
+    }
+}
+
+impl From<TimestampSource> for Option<timestamp::TimestampSource> {
+    fn from(s: TimestampSource) -> Self {
+        match s {
+            TimestampSource::Unspecified => None,
+            TimestampSource::BufPts => Some(timestamp::TimestampSource::BufPts),
+            TimestampSource::BufferPts => Some(timestamp::TimestampSource::BufferPts),
+            TimestampSource::Ntp => Some(timestamp::TimestampSource::Ntp),
+            TimestampSource::ArrivalSystem => Some(timestamp::TimestampSource::ArrivalSystem),
+            TimestampSource::ArrivalRunning => Some(timestamp::TimestampSource::ArrivalRunning),
+        }
+    }
+}
+
+impl From<&crate::FrameObjects> for FrameObjects {
+    fn from(f: &crate::FrameObjects) -> Self {
+        let frame = f.frame();
+        let ts = frame.timestamps();
+        Self {
+            frame: Some(FrameInfo {
+                source_id: frame.source_id(),
+                width: frame.width(),
+                height: frame.height(),
+                frame_num: frame.frame_num(),
+                timestamp: frame.timestamp(),
+                timestamp_source: TimestampSource::from(frame.timestamp_source()) as i32,
+                timestamps: Some(FrameTimestamps {
+                    buf_pts: ts.buf_pts,
+                    buffer_pts: ts.buffer_pts,
+                    ntp: ts.ntp,
+                    arrival_system: ts.arrival_system,
+                    arrival_running: ts.arrival_running,
+                }),
+            }),
+            objects: f.objects().iter().map(ObjectMeta::from).collect(),
+            segmentation: f
+                .segmentation()
+                .iter()
+                .map(|s| SegmentationStats {
+                    width: s.width,
+                    height: s.height,
+                    classes: s.classes,
+                    class_areas: s.class_areas.clone(),
+                    class_regions: s.class_regions.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<FrameObjects> for crate::FrameObjects {
+    type Error = Error;
+
+    fn try_from(f: FrameObjects) -> Result<Self, Self::Error> {
+        let frame = f.frame.ok_or(Error::MissingField("frame"))?;
+        let ts = frame.timestamps.unwrap_or_default();
+        // unknown to this reader, the timestamp is kept without its source
+        let timestamp_source =
+            TimestampSource::from_i32(frame.timestamp_source).unwrap_or_default();
+        let timestamps = timestamp::FrameTimestamps {
+            buf_pts: ts.buf_pts,
+            buffer_pts: ts.buffer_pts,
+            ntp: ts.ntp,
+            arrival_system: ts.arrival_system,
+            arrival_running: ts.arrival_running,
+        };
+        let (pts, infer_ts) = crate::BufferFrameInfo::legacy_timestamps(&timestamps);
+        let frame = crate::BufferFrameInfo {
+            source_id: frame.source_id,
+            width: frame.width,
+            height: frame.height,
+            frame_num: frame.frame_num,
+            pts,
+            infer_ts,
+            timestamp: frame.timestamp,
+            timestamp_source: timestamp_source.into(),
+            timestamps,
+        };
+        let objects = f
+            .objects
+            .into_iter()
+            .map(crate::ObjectMeta::try_from)
+            .collect::<Result<_, _>>()?;
+        let segmentation = f
+            .segmentation
+            .into_iter()
+            .map(|s| crate::SegmentationStats {
+                width: s.width,
+                height: s.height,
+                classes: s.classes,
+                class_areas: s.class_areas,
+                class_regions: s.class_regions,
+            })
+            .collect();
+        Ok(crate::FrameObjects::new(frame, objects).with_segmentation(segmentation))
+    }
+}
+
+fn unix_nanos(t: &DateTime<Utc>) -> i64 {
+    t.timestamp() * 1_000_000_000 + t.timestamp_subsec_nanos() as i64
+}
+
+fn from_unix_nanos(t: i64) -> Result<DateTime<Utc>, Error> {
+    Utc.timestamp_opt(
+        t.div_euclid(1_000_000_000),
+        t.rem_euclid(1_000_000_000) as u32,
+    )
+    .single()
+    .ok_or(Error::InvalidValue("timestamp"))
+}
+
+impl From<&crate::SourceEvent> for SourceEvent {
+    fn from(e: &crate::SourceEvent) -> Self {
+        let state = match e.state {
+            crate::SourceState::Added => SourceState::Added,
+            crate::SourceState::Removed => SourceState::Removed,
+        };
+        Self {
+            source_id: e.source_id,
+            state: state as i32,
+            location: e.location.clone(),
+            timestamp: unix_nanos(&e.timestamp),
+        }
+    }
+}
+
+impl TryFrom<SourceEvent> for crate::SourceEvent {
+    type Error = Error;
+
+    fn try_from(e: SourceEvent) -> Result<Self, Self::Error> {
+        let state = match SourceState::from_i32(e.state) {
+            Some(SourceState::Added) => crate::SourceState::Added,
+            Some(SourceState::Removed) => crate::SourceState::Removed,
+            Some(SourceState::Unspecified) => return Err(Error::InvalidValue("state")),
+            None => return Err(Error::Unknown("state")),
+        };
+        let timestamp = from_unix_nanos(e.timestamp)?;
+        Ok(Self {
+            source_id: e.source_id,
+            state,
+            location: e.location,
+            timestamp,
+        })
+    }
+}
+
+impl From<&track::TrackEvent> for TrackEvent {
+    fn from(t: &track::TrackEvent) -> Self {
+        Self {
+            source_id: t.source_id,
+            object_id: t.object_id,
+            first_frame: t.first_frame,
+            last_frame: t.last_frame,
+            first_timestamp: t.first_timestamp,
+            last_timestamp: t.last_timestamp,
+            length: t.length,
+            class_id: t.class_id,
+            label: t.label.clone(),
+            class_votes: t.class_votes.clone(),
+            best: Some(TrackSnapshot {
+                frame_num: t.best.frame_num,
+                timestamp: t.best.timestamp,
+                confidence: t.best.confidence,
+                bbox: Some(BBox::from(&t.best.bbox)),
+            }),
+        }
+    }
+}
+
+impl TryFrom<TrackEvent> for track::TrackEvent {
+    type Error = Error;
+
+    fn try_from(t: TrackEvent) -> Result<Self, Self::Error> {
+        let best = t.best.ok_or(Error::MissingField("best"))?;
+        Ok(Self {
+            source_id: t.source_id,
+            object_id: t.object_id,
+            first_frame: t.first_frame,
+            last_frame: t.last_frame,
+            first_timestamp: t.first_timestamp,
+            last_timestamp: t.last_timestamp,
+            length: t.length,
+            class_id: t.class_id,
+            label: t.label,
+            class_votes: t.class_votes,
+            best: track::TrackSnapshot {
+                frame_num: best.frame_num,
+                timestamp: best.timestamp,
+                confidence: best.confidence,
+                bbox: best.bbox.ok_or(Error::MissingField("best.bbox"))?.into(),
+            },
+        })
+    }
+}
+
+impl From<&latency::LatencyStats> for LatencyStats {
+    fn from(s: &latency::LatencyStats) -> Self {
+        Self {
+            count: s.count as u64,
+            mean: s.mean,
+            p50: s.p50,
+            p95: s.p95,
+            p99: s.p99,
+            max: s.max,
+        }
+    }
+}
+
+impl From<LatencyStats> for latency::LatencyStats {
+    fn from(s: LatencyStats) -> Self {
+        Self {
+            count: s.count as usize,
+            mean: s.mean,
+            p50: s.p50,
+            p95: s.p95,
+            p99: s.p99,
+            max: s.max,
+        }
+    }
+}
+
+impl From<&latency::LatencyEvent> for LatencyEvent {
+    fn from(e: &latency::LatencyEvent) -> Self {
+        Self {
+            source_id: e.source_id,
+            timestamp: unix_nanos(&e.timestamp),
+            frame: e.frame.as_ref().map(LatencyStats::from),
+            components: e
+                .components
+                .iter()
+                .map(|(name, s)| (name.clone(), s.into()))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<LatencyEvent> for latency::LatencyEvent {
+    type Error = Error;
+
+    fn try_from(e: LatencyEvent) -> Result<Self, Self::Error> {
+        Ok(Self {
+            source_id: e.source_id,
+            timestamp: from_unix_nanos(e.timestamp)?,
+            frame: e.frame.map(Into::into),
+            components: e
+                .components
+                .into_iter()
+                .map(|(name, s)| (name, s.into()))
+                .collect(),
+        })
+    }
+}
+
+impl From<&crate::AudioFrame> for AudioFrame {
+    fn from(a: &crate::AudioFrame) -> Self {
+        Self {
+            source_id: a.source_id,
+            frame_num: a.frame_num,
+            timestamp: a.timestamp,
+            sample_rate: a.sample_rate,
+            num_channels: a.num_channels,
+            num_samples_per_frame: a.num_samples_per_frame,
+            class_id: a.class_id,
+            confidence: a.confidence,
+            label: a.label.clone(),
+            classifiers: a.classifiers.iter().map(ClassifierMeta::from).collect(),
+        }
+    }
+}
+
+impl From<AudioFrame> for crate::AudioFrame {
+    fn from(a: AudioFrame) -> Self {
+        Self {
+            source_id: a.source_id,
+            frame_num: a.frame_num,
+            timestamp: a.timestamp,
+            sample_rate: a.sample_rate,
+            num_channels: a.num_channels,
+            num_samples_per_frame: a.num_samples_per_frame,
+            class_id: a.class_id,
+            confidence: a.confidence,
+            label: a.label,
+            classifiers: a.classifiers.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&crate::Record> for Record {
+    fn from(r: &crate::Record) -> Self {
+        let kind = match r {
+            crate::Record::Frame(f) => record::Kind::Frame(f.into()),
+            crate::Record::Source(e) => record::Kind::Source(e.into()),
+            crate::Record::Track(t) => record::Kind::Track(t.into()),
+            crate::Record::Latency(l) => record::Kind::Latency(l.into()),
+            crate::Record::Audio(a) => record::Kind::Audio(a.into()),
+        };
+        Self {
+            version: SCHEMA_VERSION,
+            kind: Some(kind),
+        }
+    }
+}
+
+impl TryFrom<Record> for crate::Record {
+    type Error = Error;
+
+    fn try_from(r: Record) -> Result<Self, Self::Error> {
+        // records without the version are written by a broken encoder
+        if r.version == 0 || r.version > SCHEMA_VERSION {
+            return Err(Error::UnsupportedVersion(r.version));
+        }
+        // an unknown oneof field is skipped by the decoder and leaves no kind
+        match r.kind.ok_or(Error::Unknown("kind"))? {
+            record::Kind::Frame(f) => Ok(crate::Record::Frame(f.try_into()?)),
+            record::Kind::Source(e) => Ok(crate::Record::Source(e.try_into()?)),
+            record::Kind::Track(t) => Ok(crate::Record::Track(t.try_into()?)),
+            record::Kind::Latency(l) => Ok(crate::Record::Latency(l.try_into()?)),
+            record::Kind::Audio(a) => Ok(crate::Record::Audio(a.into())),
+        }
+    }
+}
+
+/// Write a record with a varint length prefix
+pub fn write_record<W: Write>(w: &mut W, record: &crate::Record) -> io::Result<()> {
+    let buf = Record::from(record).encode_length_delimited_to_vec();
+    w.write_all(&buf)
+}
+
+/// Read length delimited records until EOF
+///
+/// Records unknown to this reader, [`Error::Unknown`], are skipped and counted.
+pub struct RecordReader<R> {
+    r: R,
+    buf: Vec<u8>,
+    skipped: usize,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(r: R) -> Self {
+        Self {
+            r,
+            buf: vec![],
+            skipped: 0,
+        }
+    }
+
+    /// Number of records skipped as unknown
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// `None` on EOF at a record boundary
+    fn read_length(&mut self) -> io::Result<Option<usize>> {
+        let mut len = 0u64;
+        for i in 0..10 {
+            let mut b = [0u8];
+            if self.r.read(&mut b)? == 0 {
+                if i == 0 {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            len |= ((b[0] & 0x7f) as u64) << (7 * i);
+            if b[0] & 0x80 == 0 {
+                return Ok(Some(len as usize));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "length prefix is too long",
+        ))
+    }
+
+    pub fn read_record(&mut self) -> Result<Option<crate::Record>, Error> {
+        loop {
+            let len = match self.read_length()? {
+                Some(len) => len,
+                None => return Ok(None),
+            };
+            if len > MAX_RECORD_SIZE {
+                return Err(Error::TooLarge(len));
+            }
+            self.buf.resize(len, 0);
+            self.r.read_exact(&mut self.buf)?;
+            let record = Record::decode(self.buf.as_slice())?;
+            match record.try_into() {
+                Err(Error::Unknown(_)) => self.skipped += 1,
+                r => return r.map(Some),
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<crate::Record, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{frame_info, object};
+
+    fn records() -> Vec<crate::Record> {
+        let mut o = object(2, 7, [1.0, 2.0, 3.0, 4.0], 0.5);
+        o.detection_index = 3;
+        o.label = "car".to_owned();
+        o.backfilled = true;
+        o.tracker_bbox_info = Some(crate::BBoxCorrds {
+            left: 5.0,
+            top: 6.0,
+            width: 7.0,
+            height: 8.0,
+        });
+        o.classifiers = vec![crate::ClassifierMeta {
+            unique_component_id: 2,
+            labels: vec![crate::LabelInfo {
+                label_id: 0,
+                class_id: 1,
+                prob: 0.75,
+                label: "red".to_owned(),
+            }],
+        }];
+        let timestamps = timestamp::FrameTimestamps {
+            buf_pts: Some(1),
+            buffer_pts: Some(2),
+            ntp: Some(3),
+            arrival_system: None,
+            arrival_running: Some(5),
+        };
+        let frame = crate::FrameObjects::new(frame_info(1, 9, timestamps), vec![o])
+            .with_segmentation(vec![crate::SegmentationStats {
+                width: 4,
+                height: 2,
+                classes: 2,
+                class_areas: vec![6, 2],
+                class_regions: vec![1, 2],
+            }]);
+        let stats = latency::LatencyStats {
+            count: 3,
+            mean: 1.5,
+            p50: 1.0,
+            p95: 2.0,
+            p99: 2.5,
+            max: 3.0,
+        };
+        vec![
+            crate::Record::Frame(frame),
+            crate::Record::Source(crate::SourceEvent::new(
+                1,
+                crate::SourceState::Removed,
+                "file:///a.mp4",
+            )),
+            crate::Record::Track(track::TrackEvent {
+                source_id: 1,
+                object_id: 7,
+                first_frame: 3,
+                last_frame: 9,
+                first_timestamp: Some(30),
+                last_timestamp: None,
+                length: 6,
+                class_id: 2,
+                label: "car".to_owned(),
+                class_votes: BTreeMap::from([(2, 5), (5, 1)]),
+                best: track::TrackSnapshot {
+                    frame_num: 4,
+                    timestamp: Some(40),
+                    confidence: 0.9,
+                    bbox: crate::BBoxCorrds {
+                        left: 1.0,
+                        top: 2.0,
+                        width: 3.0,
+                        height: 4.0,
+                    },
+                },
+            }),
+            crate::Record::Latency(latency::LatencyEvent {
+                source_id: 0,
+                timestamp: Utc.timestamp_opt(1_600_000_000, 123).unwrap(),
+                frame: Some(stats),
+                components: BTreeMap::from([("nvinfer0".to_owned(), stats)]),
+            }),
+            crate::Record::Audio(crate::AudioFrame {
+                source_id: 2,
+                frame_num: 8,
+                timestamp: None,
+                sample_rate: 44100,
+                num_channels: 2,
+                num_samples_per_frame: 1024,
+                class_id: 1,
+                confidence: 0.25,
+                label: "speech".to_owned(),
+                classifiers: vec![],
+            }),
+        ]
+    }
+
+    fn json(r: &crate::Record) -> serde_json::Value {
+        serde_json::to_value(r).unwrap()
+    }
+
+    fn encode(records: &[Record]) -> Vec<u8> {
+        records
+            .iter()
+            .flat_map(|r| r.encode_length_delimited_to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_every_kind() {
+        let records = records();
+        let mut buf = vec![];
+        for r in records.iter() {
+            write_record(&mut buf, r).unwrap();
+        }
+        let mut reader = RecordReader::new(buf.as_slice());
+        let decoded = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded.len(), records.len());
+        for (a, b) in records.iter().zip(decoded.iter()) {
+            assert_eq!(json(a), json(b));
+        }
+        assert_eq!(reader.skipped(), 0);
+    }
+
+    /// Record of a newer writer with a kind of tag 15
+    #[derive(Clone, PartialEq, Message)]
+    struct FutureRecord {
+        #[prost(uint32, tag = "1")]
+        version: u32,
+        #[prost(message, optional, tag = "15")]
+        future: Option<BBox>,
+    }
+
+    #[test]
+    fn unknown_kinds_and_states_are_skipped() {
+        let records = records();
+        let mut state =
+            SourceEvent::from(&crate::SourceEvent::new(0, crate::SourceState::Added, "a"));
+        state.state = 7;
+        let mut buf = encode(&[Record::from(&records[0])]);
+        buf.extend(
+            FutureRecord {
+                version: SCHEMA_VERSION,
+                future: Some(BBox::default()),
+            }
+            .encode_length_delimited_to_vec(),
+        );
+        buf.extend(encode(&[
+            Record {
+                version: SCHEMA_VERSION,
+                kind: Some(record::Kind::Source(state)),
+            },
+            Record::from(&records[4]),
+        ]));
+        let mut reader = RecordReader::new(buf.as_slice());
+        let decoded = reader.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(json(&decoded[0]), json(&records[0]));
+        assert_eq!(json(&decoded[1]), json(&records[4]));
+        assert_eq!(reader.skipped(), 2);
+    }
+
+    #[test]
+    fn unknown_timestamp_source_is_unspecified() {
+        let mut r = Record::from(&records()[0]);
+        match r.kind.as_mut() {
+            Some(record::Kind::Frame(f)) => f.frame.as_mut().unwrap().timestamp_source = 42,
+            _ => unreachable!(),
+        }
+        match crate::Record::try_from(r).unwrap() {
+            crate::Record::Frame(f) => {
+                assert_eq!(f.frame().timestamp(), Some(3));
+                assert_eq!(f.frame().timestamp_source(), None);
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn invalid_records() {
+        let mut r = Record::from(&records()[1]);
+        r.version = SCHEMA_VERSION + 1;
+        assert!(matches!(
+            crate::Record::try_from(r.clone()),
+            Err(Error::UnsupportedVersion(_))
+        ));
+        r.version = SCHEMA_VERSION;
+        if let Some(record::Kind::Source(e)) = r.kind.as_mut() {
+            e.state = SourceState::Unspecified as i32;
+        }
+        assert!(matches!(
+            RecordReader::new(encode(&[r]).as_slice()).read_record(),
+            Err(Error::InvalidValue("state"))
+        ));
+    }
+
+    #[test]
+    fn length_is_capped() {
+        let mut buf = vec![];
+        prost::encoding::encode_varint(MAX_RECORD_SIZE as u64 + 1, &mut buf);
+        assert!(matches!(
+            RecordReader::new(buf.as_slice()).read_record(),
+            Err(Error::TooLarge(n)) if n == MAX_RECORD_SIZE + 1
+        ));
+        // a truncated record is an error, not EOF
+        let buf = encode(&[Record::from(&records()[1])]);
+        assert!(matches!(
+            RecordReader::new(&buf[..buf.len() - 1]).read_record(),
+            Err(Error::Io(_))
+        ));
+        assert!(RecordReader::new(&[][..]).read_record().unwrap().is_none());
+    }
+}
@@ -0,0 +1,735 @@
+//! Publish records to local services over sockets or a message bus
+//!
+//! A [`Publisher`] is an [`Exporter`], so each one runs on its own [`crate::export::ExportWorker`]
+//! with a bounded queue. Connection errors never stop the worker: records are dropped
+//! while disconnected and the connection is retried at the reconnect interval.
+//! ZeroMQ and MQTT are behind the `zeromq` and `mqtt` features.
+use std::fmt;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::exporter::Exporter;
+use crate::Record;
+
+/// Where to publish, such as `unix:///tmp/nvdsmeta.sock` or `mqtt://localhost:1883`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// `unix://<path>`, connect to a Unix domain socket
+    Unix(PathBuf),
+    /// `tcp://<host>:<port>`, connect to a TCP server
+    Tcp(String),
+    /// `zmq+<transport>://<address>`, PUB socket binding `*` addresses and connecting others
+    Zmq(String),
+    /// `mqtt://<host>[:<port>]`
+    Mqtt { host: String, port: u16 },
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scheme, rest) = s
+            .split_once("://")
+            .ok_or_else(|| format!("endpoint {:?} has no scheme", s))?;
+        if rest.is_empty() {
+            return Err(format!("endpoint {:?} has no address", s));
+        }
+        match scheme {
+            "unix" => Ok(Endpoint::Unix(PathBuf::from(rest))),
+            "tcp" => Ok(Endpoint::Tcp(rest.to_owned())),
+            "mqtt" => {
+                let (host, port) = match rest.rsplit_once(':') {
+                    Some((host, port)) => (
+                        host,
+                        port.parse()
+                            .map_err(|_| format!("invalid port of endpoint {:?}", s))?,
+                    ),
+                    None => (rest, 1883),
+                };
+                Ok(Endpoint::Mqtt {
+                    host: host.to_owned(),
+                    port,
+                })
+            }
+            _ => match scheme.strip_prefix("zmq+") {
+                Some(transport) => Ok(Endpoint::Zmq(format!("{}://{}", transport, rest))),
+                None => Err(format!(
+                    "unknown endpoint scheme {:?}, expect unix, tcp, zmq+<transport> or mqtt",
+                    scheme
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Unix(p) => write!(f, "unix://{}", p.display()),
+            Endpoint::Tcp(addr) => write!(f, "tcp://{}", addr),
+            Endpoint::Zmq(addr) => write!(f, "zmq+{}", addr),
+            Endpoint::Mqtt { host, port } => write!(f, "mqtt://{}:{}", host, port),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Endpoint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Encoding of a published message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    /// [`Record`] as JSON, newline delimited on stream sockets
+    #[default]
+    Json,
+    /// [`crate::proto::Record`], length delimited on stream sockets
+    Protobuf,
+}
+
+impl std::str::FromStr for PayloadFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(PayloadFormat::Json),
+            "protobuf" => Ok(PayloadFormat::Protobuf),
+            _ => Err(format!(
+                "unknown payload format {:?}, expect json or protobuf",
+                s
+            )),
+        }
+    }
+}
+
+impl PayloadFormat {
+    pub fn encode(&self, record: &Record) -> io::Result<Vec<u8>> {
+        match self {
+            PayloadFormat::Json => Ok(serde_json::to_vec(record)?),
+            PayloadFormat::Protobuf => Ok(crate::proto::Record::from(record).encode_to_vec()),
+        }
+    }
+}
+
+/// Topic of a record, `{source_id}` is replaced by the source id
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TopicTemplate(String);
+
+impl TopicTemplate {
+    pub fn new(template: &str) -> Self {
+        Self(template.to_owned())
+    }
+
+    pub fn render(&self, record: &Record) -> String {
+        let source_id = match record {
+            Record::Frame(f) => f.frame().source_id(),
+            Record::Source(e) => e.source_id,
+            Record::Track(t) => t.source_id,
+            Record::Latency(l) => l.source_id,
+            Record::Audio(a) => a.source_id,
+        };
+        self.0.replace("{source_id}", &source_id.to_string())
+    }
+}
+
+impl Default for TopicTemplate {
+    fn default() -> Self {
+        Self::new("nvdsmeta/{source_id}")
+    }
+}
+
+impl std::str::FromStr for TopicTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self::new(s))
+    }
+}
+
+/// Connection to publish messages.
+///
+/// `publish` returns [`io::ErrorKind::WouldBlock`] when the message is dropped by a full
+/// queue of the transport, other errors are handled as a lost connection. Stream
+/// transports have no queue and never return `WouldBlock`.
+pub trait Transport: Send {
+    fn connect(&mut self) -> io::Result<()>;
+    fn publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<()>;
+    fn disconnect(&mut self);
+}
+
+/// Unix domain socket or TCP client, the topic is not sent
+///
+/// A write timeout may leave a partial frame on the socket, which the reader cannot
+/// resync from, so it is reported as [`io::ErrorKind::TimedOut`] to reconnect.
+pub struct StreamTransport {
+    endpoint: Endpoint,
+    format: PayloadFormat,
+    stream: Option<Box<dyn Write + Send>>,
+    buf: Vec<u8>,
+}
+
+impl StreamTransport {
+    /// Do not block the export thread forever on a stalled reader
+    const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub fn new(endpoint: Endpoint, format: PayloadFormat) -> Self {
+        Self {
+            endpoint,
+            format,
+            stream: None,
+            buf: vec![],
+        }
+    }
+}
+
+impl Transport for StreamTransport {
+    fn connect(&mut self) -> io::Result<()> {
+        let stream: Box<dyn Write + Send> = match &self.endpoint {
+            Endpoint::Unix(p) => {
+                let s = UnixStream::connect(p)?;
+                s.set_write_timeout(Some(Self::WRITE_TIMEOUT))?;
+                Box::new(s)
+            }
+            Endpoint::Tcp(addr) => {
+                let s = TcpStream::connect(addr)?;
+                s.set_write_timeout(Some(Self::WRITE_TIMEOUT))?;
+                s.set_nodelay(true)?;
+                Box::new(s)
+            }
+            e => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not a stream socket", e),
+                ))
+            }
+        };
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn publish(&mut self, _topic: &str, payload: &[u8]) -> io::Result<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        // frame and payload in one write not to interleave partial frames on timeout
+        self.buf.clear();
+        match self.format {
+            PayloadFormat::Json => {
+                self.buf.extend_from_slice(payload);
+                self.buf.push(b'\n');
+            }
+            PayloadFormat::Protobuf => {
+                prost::encode_length_delimiter(payload.len(), &mut self.buf)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                self.buf.extend_from_slice(payload);
+            }
+        }
+        stream.write_all(&self.buf).map_err(|e| match e.kind() {
+            // the write timeout of the socket
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                io::Error::new(io::ErrorKind::TimedOut, "write timed out")
+            }
+            _ => e,
+        })
+    }
+
+    fn disconnect(&mut self) {
+        self.stream = None;
+    }
+}
+
+/// ZeroMQ PUB socket sending `[topic, payload]` multipart messages
+#[cfg(feature = "zeromq")]
+pub struct ZmqTransport {
+    context: zmq::Context,
+    address: String,
+    high_water_mark: i32,
+    socket: Option<zmq::Socket>,
+}
+
+#[cfg(feature = "zeromq")]
+impl ZmqTransport {
+    pub fn new(address: &str, high_water_mark: usize) -> Self {
+        Self {
+            context: zmq::Context::new(),
+            address: address.to_owned(),
+            high_water_mark: high_water_mark.min(i32::MAX as usize) as i32,
+            socket: None,
+        }
+    }
+}
+
+#[cfg(feature = "zeromq")]
+impl Transport for ZmqTransport {
+    fn connect(&mut self) -> io::Result<()> {
+        let to_io = |e: zmq::Error| io::Error::new(io::ErrorKind::Other, e);
+        let socket = self.context.socket(zmq::PUB).map_err(to_io)?;
+        socket.set_sndhwm(self.high_water_mark).map_err(to_io)?;
+        // zmq reconnects connected sockets by itself
+        if self.address.contains("://*") {
+            socket.bind(&self.address).map_err(to_io)?;
+        } else {
+            socket.connect(&self.address).map_err(to_io)?;
+        }
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    fn publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<()> {
+        let socket = self
+            .socket
+            .as_ref()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        match socket.send_multipart([topic.as_bytes(), payload], zmq::DONTWAIT) {
+            Ok(()) => Ok(()),
+            Err(zmq::Error::EAGAIN) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.socket = None;
+    }
+}
+
+/// MQTT client publishing with QoS 0, the event loop thread reconnects by itself
+///
+/// `disconnect` waits for the event loop to send the queued messages, up to
+/// [`MqttTransport::DISCONNECT_TIMEOUT`].
+#[cfg(feature = "mqtt")]
+pub struct MqttTransport {
+    host: String,
+    port: u16,
+    capacity: usize,
+    reconnect_interval: Duration,
+    client: Option<rumqttc::Client>,
+    /// the event loop thread and a channel closed when it exits
+    event_loop: Option<(std::thread::JoinHandle<()>, std::sync::mpsc::Receiver<()>)>,
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttTransport {
+    /// Do not block the export thread forever on an unreachable broker
+    pub const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+    pub fn new(host: &str, port: u16, capacity: usize, reconnect_interval: Duration) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            capacity,
+            reconnect_interval,
+            client: None,
+            event_loop: None,
+        }
+    }
+}
+
+#[cfg(feature = "mqtt")]
+impl Transport for MqttTransport {
+    fn connect(&mut self) -> io::Result<()> {
+        let client_id = format!("nvdsmeta-{}", std::process::id());
+        let mut opts = rumqttc::MqttOptions::new(client_id, &self.host, self.port);
+        opts.set_keep_alive(Duration::from_secs(5));
+        let (client, mut connection) = rumqttc::Client::new(opts, self.capacity);
+        let interval = self.reconnect_interval;
+        let endpoint = format!("mqtt://{}:{}", self.host, self.port);
+        let (done, exited) = std::sync::mpsc::channel::<()>();
+        let handle = std::thread::Builder::new()
+            .name("mqtt".to_owned())
+            .spawn(move || {
+                // dropped when the loop exits
+                let _done = done;
+                for notification in connection.iter() {
+                    match notification {
+                        // requested after the queued messages, which are sent by now
+                        Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Disconnect)) => break,
+                        Ok(_) => (),
+                        Err(rumqttc::ConnectionError::RequestsDone) => break,
+                        Err(e) => {
+                            log::warn!("{}: {}, reconnecting in {:?}", endpoint, e, interval);
+                            std::thread::sleep(interval);
+                        }
+                    }
+                }
+            })?;
+        self.client = Some(client);
+        self.event_loop = Some((handle, exited));
+        Ok(())
+    }
+
+    fn publish(&mut self, topic: &str, payload: &[u8]) -> io::Result<()> {
+        let client = self
+            .client
+            .as_mut()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        // requests are queued while the event loop reconnects, fails only when full
+        client
+            .try_publish(topic, rumqttc::QoS::AtMostOnce, false, payload.to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::WouldBlock, e))
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(mut client) = self.client.take() {
+            // a full queue fails the request, the event loop then stops when the queue
+            // is drained since the client is dropped
+            let _ = client.try_disconnect();
+        }
+        let (handle, exited) = match self.event_loop.take() {
+            Some(event_loop) => event_loop,
+            None => return,
+        };
+        match exited.recv_timeout(Self::DISCONNECT_TIMEOUT) {
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => log::warn!(
+                "mqtt://{}:{}: queued messages are not sent in {:?}",
+                self.host,
+                self.port,
+                Self::DISCONNECT_TIMEOUT
+            ),
+            // the thread is exiting
+            _ => {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+/// Create the transport of the endpoint
+pub fn transport(
+    endpoint: &Endpoint,
+    format: PayloadFormat,
+    capacity: usize,
+    reconnect_interval: Duration,
+) -> io::Result<Box<dyn Transport>> {
+    // unused without the features
+    let _ = (capacity, reconnect_interval);
+    match endpoint {
+        Endpoint::Unix(_) | Endpoint::Tcp(_) => {
+            Ok(Box::new(StreamTransport::new(endpoint.clone(), format)))
+        }
+        #[cfg(feature = "zeromq")]
+        Endpoint::Zmq(address) => Ok(Box::new(ZmqTransport::new(address, capacity))),
+        #[cfg(feature = "mqtt")]
+        Endpoint::Mqtt { host, port } => Ok(Box::new(MqttTransport::new(
+            host,
+            *port,
+            capacity,
+            reconnect_interval,
+        ))),
+        #[allow(unreachable_patterns)]
+        e => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} needs to build with the zeromq or mqtt feature", e),
+        )),
+    }
+}
+
+/// Exporter publishing each record to a topic by `source_id`
+pub struct Publisher {
+    endpoint: Endpoint,
+    transport: Box<dyn Transport>,
+    format: PayloadFormat,
+    topic: TopicTemplate,
+    reconnect_interval: Duration,
+    connected: bool,
+    retry_at: Instant,
+    dropped: u64,
+}
+
+impl Publisher {
+    pub fn new(
+        endpoint: Endpoint,
+        transport: Box<dyn Transport>,
+        format: PayloadFormat,
+        topic: TopicTemplate,
+        reconnect_interval: Duration,
+    ) -> Self {
+        Self {
+            endpoint,
+            transport,
+            format,
+            topic,
+            reconnect_interval,
+            connected: false,
+            retry_at: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    fn ensure_connected(&mut self) -> bool {
+        if self.connected {
+            return true;
+        }
+        if Instant::now() < self.retry_at {
+            return false;
+        }
+        match self.transport.connect() {
+            Ok(()) => {
+                log::info!("connected to {}", self.endpoint);
+                self.connected = true;
+            }
+            Err(e) => {
+                log::warn!(
+                    "failed to connect to {}: {}, retry in {:?}",
+                    self.endpoint,
+                    e,
+                    self.reconnect_interval
+                );
+                self.retry_at = Instant::now() + self.reconnect_interval;
+            }
+        }
+        self.connected
+    }
+}
+
+impl Exporter for Publisher {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        if !self.ensure_connected() {
+            self.dropped += 1;
+            return Ok(());
+        }
+        let payload = self.format.encode(record)?;
+        match self.transport.publish(&self.topic.render(record), &payload) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.dropped += 1,
+            Err(e) => {
+                log::warn!("lost connection to {}: {}", self.endpoint, e);
+                self.transport.disconnect();
+                self.connected = false;
+                self.dropped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records dropped while disconnected or by a full queue of the transport
+    fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.transport.disconnect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{frame, object};
+    use crate::{SourceEvent, SourceState};
+    use std::collections::VecDeque;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex};
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record::Frame(frame(0, 1, vec![object(2, 7, [10., 20., 30., 40.], 0.5)])),
+            Record::Source(SourceEvent::new(1, SourceState::Added, "file:///a.mp4")),
+        ]
+    }
+
+    fn publisher(endpoint: Endpoint, format: PayloadFormat) -> Publisher {
+        let transport = StreamTransport::new(endpoint.clone(), format);
+        Publisher::new(
+            endpoint,
+            Box::new(transport),
+            format,
+            TopicTemplate::default(),
+            Duration::from_secs(60),
+        )
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "nvdsmeta-publish-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn parse_endpoints() {
+        let parse = |s: &str| s.parse::<Endpoint>();
+        assert_eq!(
+            parse("unix:///tmp/a.sock").unwrap(),
+            Endpoint::Unix(PathBuf::from("/tmp/a.sock"))
+        );
+        assert_eq!(
+            parse("tcp://127.0.0.1:5000").unwrap(),
+            Endpoint::Tcp("127.0.0.1:5000".to_owned())
+        );
+        assert_eq!(
+            parse("zmq+tcp://*:5556").unwrap(),
+            Endpoint::Zmq("tcp://*:5556".to_owned())
+        );
+        assert_eq!(
+            parse("mqtt://broker").unwrap(),
+            Endpoint::Mqtt {
+                host: "broker".to_owned(),
+                port: 1883
+            }
+        );
+        assert!(parse("mqtt://broker:x").is_err());
+        assert!(parse("unix://").is_err());
+        assert!(parse("/tmp/a.sock").is_err());
+        assert!(parse("udp://127.0.0.1:5000").is_err());
+    }
+
+    #[test]
+    fn json_lines_over_unix_socket() {
+        let path = socket_path("json");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut p = publisher(Endpoint::Unix(path.clone()), PayloadFormat::Json);
+        let records = records();
+        for r in records.iter() {
+            p.write_record(r).unwrap();
+        }
+        p.finish().unwrap();
+        assert_eq!(p.dropped(), 0);
+
+        let mut received = String::new();
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.read_to_string(&mut received).unwrap();
+        let lines = received
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .collect::<Vec<_>>();
+        let expected = records
+            .iter()
+            .map(|r| serde_json::to_value(r).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, expected);
+        assert!(received.ends_with('\n'));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn length_delimited_protobuf_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = Endpoint::Tcp(listener.local_addr().unwrap().to_string());
+        let mut p = publisher(endpoint, PayloadFormat::Protobuf);
+        let records = records();
+        for r in records.iter() {
+            p.write_record(r).unwrap();
+        }
+        p.finish().unwrap();
+
+        let mut received = vec![];
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.read_to_end(&mut received).unwrap();
+        let mut buf = received.as_slice();
+        let mut decoded = vec![];
+        while !buf.is_empty() {
+            decoded.push(crate::proto::Record::decode_length_delimited(&mut buf).unwrap());
+        }
+        let expected = records
+            .iter()
+            .map(crate::proto::Record::from)
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn stalled_reader_times_out() {
+        let path = socket_path("stalled");
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut t = StreamTransport::new(Endpoint::Unix(path.clone()), PayloadFormat::Json);
+        t.connect().unwrap();
+        // accepted but never read, so the socket buffer fills up
+        let (_stream, _) = listener.accept().unwrap();
+        let payload = vec![b'x'; 16 << 20];
+        let e = t.publish("", &payload).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Transport returning scripted results and logging calls
+    #[derive(Default)]
+    struct MockTransport {
+        results: VecDeque<io::Result<()>>,
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Transport for MockTransport {
+        fn connect(&mut self) -> io::Result<()> {
+            self.calls.lock().unwrap().push("connect");
+            Ok(())
+        }
+        fn publish(&mut self, _topic: &str, _payload: &[u8]) -> io::Result<()> {
+            self.calls.lock().unwrap().push("publish");
+            self.results.pop_front().unwrap_or(Ok(()))
+        }
+        fn disconnect(&mut self) {
+            self.calls.lock().unwrap().push("disconnect");
+        }
+    }
+
+    #[test]
+    fn queue_full_keeps_connection_and_other_errors_reconnect() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let transport = MockTransport {
+            results: VecDeque::from(vec![
+                Err(io::ErrorKind::WouldBlock.into()),
+                Ok(()),
+                Err(io::ErrorKind::TimedOut.into()),
+                Ok(()),
+            ]),
+            calls: calls.clone(),
+        };
+        let mut p = Publisher::new(
+            Endpoint::Tcp("127.0.0.1:1".to_owned()),
+            Box::new(transport),
+            PayloadFormat::Json,
+            TopicTemplate::default(),
+            Duration::ZERO,
+        );
+        let record = &records()[1];
+        for _ in 0..4 {
+            p.write_record(record).unwrap();
+        }
+        assert_eq!(p.dropped(), 2);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "connect",
+                "publish",
+                "publish",
+                "publish",
+                "disconnect",
+                "connect",
+                "publish"
+            ]
+        );
+    }
+
+    #[test]
+    fn worker_reports_records_dropped_while_disconnected() {
+        use crate::export::{DropPolicy, ExportWorker};
+
+        let path = socket_path("missing");
+        let p = publisher(Endpoint::Unix(path), PayloadFormat::Json);
+        let (sender, worker) = ExportWorker::spawn(Box::new(p), 8, DropPolicy::Block);
+        let metrics = worker.metrics().clone();
+        for r in records() {
+            sender.send(r).unwrap();
+        }
+        worker.finish().unwrap();
+        assert_eq!(metrics.written(), 0);
+        assert_eq!(metrics.dropped(), 2);
+    }
+}
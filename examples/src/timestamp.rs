@@ -0,0 +1,272 @@
+//! Timestamps of a frame and the policy to select the canonical one
+//!
+//! DeepStream writes 0 to `ntp_timestamp` when NTP is not available, and the PTS
+//! of the batched buffer differs from `buf_pts` of each frame, so every candidate
+//! is kept separately and the record timestamp is selected by [`TimestampPolicy`].
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nvdsmeta_sys::NvDsFrameMeta;
+use serde::{Deserialize, Serialize};
+
+/// Source of a timestamp in nanoseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    /// PTS of the frame given by the source, `NvDsFrameMeta::buf_pts`
+    BufPts,
+    /// PTS of the batched buffer from nvstreammux
+    BufferPts,
+    /// NTP time of the frame, see [`NtpMode`]
+    Ntp,
+    /// System time when the record arrived at appsink
+    ArrivalSystem,
+    /// Running time of the pipeline when the record arrived at appsink
+    ArrivalRunning,
+}
+
+impl std::str::FromStr for TimestampSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buf_pts" => Ok(TimestampSource::BufPts),
+            "buffer_pts" => Ok(TimestampSource::BufferPts),
+            "ntp" => Ok(TimestampSource::Ntp),
+            "arrival_system" => Ok(TimestampSource::ArrivalSystem),
+            "arrival_running" => Ok(TimestampSource::ArrivalRunning),
+            _ => Err(format!(
+                "unknown timestamp {:?}, expect buf_pts, buffer_pts, ntp, arrival_system or arrival_running",
+                s
+            )),
+        }
+    }
+}
+
+/// How nvstreammux fills `ntp_timestamp`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NtpMode {
+    /// Ignore `ntp_timestamp`
+    Disabled,
+    /// System time when the frame arrived at nvstreammux (`attach-sys-ts=true`)
+    #[default]
+    System,
+    /// NTP time calculated from RTCP sender reports of RTSP sources (`attach-sys-ts=false`)
+    Rtcp,
+}
+
+impl std::str::FromStr for NtpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disabled" => Ok(NtpMode::Disabled),
+            "system" => Ok(NtpMode::System),
+            "rtcp" => Ok(NtpMode::Rtcp),
+            _ => Err(format!(
+                "unknown ntp mode {:?}, expect disabled, system or rtcp",
+                s
+            )),
+        }
+    }
+}
+
+/// Candidates of the canonical timestamp in priority order, such as `ntp,buf_pts`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampPolicy(Vec<TimestampSource>);
+
+impl TimestampPolicy {
+    pub fn new(sources: Vec<TimestampSource>) -> Self {
+        Self(sources)
+    }
+
+    /// The first available timestamp and its source
+    pub fn select(&self, ts: &FrameTimestamps) -> Option<(u64, TimestampSource)> {
+        self.0.iter().find_map(|s| ts.get(*s).map(|t| (t, *s)))
+    }
+}
+
+impl Default for TimestampPolicy {
+    fn default() -> Self {
+        Self(vec![TimestampSource::Ntp, TimestampSource::ArrivalSystem])
+    }
+}
+
+impl std::str::FromStr for TimestampPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sources = s
+            .split(',')
+            .map(|x| x.trim().parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        if sources.is_empty() {
+            return Err("timestamp policy is empty".to_owned());
+        }
+        Ok(Self(sources))
+    }
+}
+
+impl fmt::Display for TimestampPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self
+            .0
+            .iter()
+            .map(|s| match s {
+                TimestampSource::BufPts => "buf_pts",
+                TimestampSource::BufferPts => "buffer_pts",
+                TimestampSource::Ntp => "ntp",
+                TimestampSource::ArrivalSystem => "arrival_system",
+                TimestampSource::ArrivalRunning => "arrival_running",
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl<'de> Deserialize<'de> for TimestampPolicy {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Times when the batched buffer arrived at appsink
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Arrival {
+    pub system: Option<u64>,
+    pub running_time: Option<u64>,
+}
+
+impl Arrival {
+    /// Arrival at now with the running time of the element
+    pub fn now(running_time: Option<u64>) -> Self {
+        let system = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_nanos() as u64);
+        Self {
+            system,
+            running_time,
+        }
+    }
+}
+
+/// All timestamps of a frame in nanoseconds, `None` if unavailable
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FrameTimestamps {
+    pub buf_pts: Option<u64>,
+    pub buffer_pts: Option<u64>,
+    pub ntp: Option<u64>,
+    pub arrival_system: Option<u64>,
+    pub arrival_running: Option<u64>,
+}
+
+impl FrameTimestamps {
+    pub fn new(
+        meta: &NvDsFrameMeta,
+        buffer_pts: Option<u64>,
+        arrival: &Arrival,
+        ntp_mode: NtpMode,
+    ) -> Self {
+        // 0 is written when neither system time nor RTCP is attached
+        let ntp = match (ntp_mode, meta.ntp_timestamp()) {
+            (NtpMode::Disabled, _) | (_, 0) => None,
+            (_, ntp) => Some(ntp),
+        };
+        Self {
+            buf_pts: meta.buf_pts().map(|t| t.nseconds()),
+            buffer_pts,
+            ntp,
+            arrival_system: arrival.system,
+            arrival_running: arrival.running_time,
+        }
+    }
+
+    pub fn get(&self, source: TimestampSource) -> Option<u64> {
+        match source {
+            TimestampSource::BufPts => self.buf_pts,
+            TimestampSource::BufferPts => self.buffer_pts,
+            TimestampSource::Ntp => self.ntp,
+            TimestampSource::ArrivalSystem => self.arrival_system,
+            TimestampSource::ArrivalRunning => self.arrival_running,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamps() -> FrameTimestamps {
+        FrameTimestamps {
+            buf_pts: Some(1),
+            buffer_pts: None,
+            ntp: None,
+            arrival_system: Some(4),
+            arrival_running: Some(5),
+        }
+    }
+
+    #[test]
+    fn policy_selects_first_available() {
+        let ts = timestamps();
+        let policy = TimestampPolicy::default();
+        assert_eq!(
+            policy.select(&ts),
+            Some((4, TimestampSource::ArrivalSystem))
+        );
+        let policy: TimestampPolicy = "buffer_pts, ntp ,buf_pts".parse().unwrap();
+        assert_eq!(policy.select(&ts), Some((1, TimestampSource::BufPts)));
+        let policy: TimestampPolicy = "ntp,buffer_pts".parse().unwrap();
+        assert_eq!(policy.select(&ts), None);
+    }
+
+    #[test]
+    fn policy_parse_and_display() {
+        let s = "buf_pts,buffer_pts,ntp,arrival_system,arrival_running";
+        let policy: TimestampPolicy = s.parse().unwrap();
+        assert_eq!(policy.to_string(), s);
+        assert!("ntp,pts".parse::<TimestampPolicy>().is_err());
+        assert!("".parse::<TimestampPolicy>().is_err());
+
+        #[derive(Deserialize)]
+        struct Config {
+            policy: TimestampPolicy,
+            ntp: NtpMode,
+        }
+        let c: Config = toml::from_str("policy = \"ntp,arrival_running\"\nntp = \"rtcp\"").unwrap();
+        assert_eq!(
+            c.policy,
+            TimestampPolicy::new(vec![TimestampSource::Ntp, TimestampSource::ArrivalRunning])
+        );
+        assert_eq!(c.ntp, NtpMode::Rtcp);
+        assert!(toml::from_str::<Config>("policy = \"ntp\"\nntp = \"gps\"").is_err());
+    }
+
+    #[test]
+    fn get_by_source() {
+        let ts = timestamps();
+        assert_eq!(ts.get(TimestampSource::BufPts), Some(1));
+        assert_eq!(ts.get(TimestampSource::BufferPts), None);
+        assert_eq!(ts.get(TimestampSource::Ntp), None);
+        assert_eq!(ts.get(TimestampSource::ArrivalSystem), Some(4));
+        assert_eq!(ts.get(TimestampSource::ArrivalRunning), Some(5));
+        assert_eq!(
+            serde_json::to_string(&TimestampSource::ArrivalRunning).unwrap(),
+            "\"arrival_running\""
+        );
+    }
+
+    #[test]
+    fn arrival_now_is_system_time() {
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+        let arrival = Arrival::now(Some(7));
+        assert!(arrival.system.unwrap() >= before);
+        assert_eq!(arrival.running_time, Some(7));
+    }
+}
@@ -0,0 +1,333 @@
+//! Aggregate per-frame objects into one event per tracked object lifetime
+//!
+//! Objects are grouped by `(source_id, object_id)`. A track ends when it is not seen
+//! for `timeout_frames` frames of its source, when the source is removed, or on flush.
+//! Untracked objects are skipped.
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use nvdsmeta_sys::UNTRACKED_OBJECT_ID;
+use serde::{Deserialize, Serialize};
+
+use crate::exporter::Exporter;
+use crate::{BBoxCorrds, FrameObjects, ObjectMeta, Record, SourceState};
+
+/// Object of the frame with the best confidence of the track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackSnapshot {
+    pub frame_num: i32,
+    pub timestamp: Option<u64>,
+    pub confidence: f32,
+    pub bbox: BBoxCorrds,
+}
+
+/// Lifetime of a tracked object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackEvent {
+    pub source_id: u32,
+    pub object_id: u64,
+    pub first_frame: i32,
+    pub last_frame: i32,
+    pub first_timestamp: Option<u64>,
+    pub last_timestamp: Option<u64>,
+    /// number of frames the object is seen
+    pub length: u32,
+    /// class with the most votes
+    pub class_id: i32,
+    pub label: String,
+    /// number of frames by class id
+    pub class_votes: BTreeMap<i32, u32>,
+    pub best: TrackSnapshot,
+}
+
+impl TrackEvent {
+    fn new(source_id: u32, frame_num: i32, timestamp: Option<u64>, o: &ObjectMeta) -> Self {
+        Self {
+            source_id,
+            object_id: o.object_id,
+            first_frame: frame_num,
+            last_frame: frame_num,
+            first_timestamp: timestamp,
+            last_timestamp: timestamp,
+            length: 1,
+            class_id: o.class_id,
+            label: o.label.clone(),
+            class_votes: BTreeMap::from([(o.class_id, 1)]),
+            best: TrackSnapshot {
+                frame_num,
+                timestamp,
+                confidence: o.confidence,
+                bbox: o.bbox().clone(),
+            },
+        }
+    }
+
+    fn update(&mut self, frame_num: i32, timestamp: Option<u64>, o: &ObjectMeta) {
+        self.last_frame = frame_num;
+        self.last_timestamp = timestamp.or(self.last_timestamp);
+        self.length += 1;
+        let votes = self.class_votes.entry(o.class_id).or_default();
+        *votes += 1;
+        // ties keep the earlier class
+        if *votes > self.class_votes[&self.class_id] {
+            self.class_id = o.class_id;
+            self.label = o.label.clone();
+        }
+        if o.confidence > self.best.confidence {
+            self.best = TrackSnapshot {
+                frame_num,
+                timestamp,
+                confidence: o.confidence,
+                bbox: o.bbox().clone(),
+            };
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TrackAggregator {
+    timeout_frames: u32,
+    tracks: HashMap<(u32, u64), TrackEvent>,
+}
+
+impl TrackAggregator {
+    pub fn new(timeout_frames: u32) -> Self {
+        Self {
+            timeout_frames,
+            tracks: HashMap::new(),
+        }
+    }
+
+    /// Update tracks by the frame and return tracks timed out on its source
+    pub fn push(&mut self, f: &FrameObjects) -> Vec<TrackEvent> {
+        let frame = f.frame();
+        let source_id = frame.source_id();
+        for o in f.objects() {
+            if o.object_id == UNTRACKED_OBJECT_ID {
+                continue;
+            }
+            self.tracks
+                .entry((source_id, o.object_id))
+                .and_modify(|t| t.update(frame.frame_num(), frame.timestamp(), o))
+                .or_insert_with(|| {
+                    TrackEvent::new(source_id, frame.frame_num(), frame.timestamp(), o)
+                });
+        }
+        let timeout = self.timeout_frames as i64;
+        self.take(|t| {
+            t.source_id == source_id && frame.frame_num() as i64 - t.last_frame as i64 > timeout
+        })
+    }
+
+    /// End all tracks of the source
+    pub fn remove_source(&mut self, source_id: u32) -> Vec<TrackEvent> {
+        self.take(|t| t.source_id == source_id)
+    }
+
+    /// End all tracks
+    pub fn flush(&mut self) -> Vec<TrackEvent> {
+        self.take(|_| true)
+    }
+
+    fn take(&mut self, pred: impl Fn(&TrackEvent) -> bool) -> Vec<TrackEvent> {
+        let keys = self
+            .tracks
+            .iter()
+            .filter(|(_, t)| pred(t))
+            .map(|(k, _)| *k)
+            .collect::<Vec<_>>();
+        let mut ended = keys
+            .into_iter()
+            .filter_map(|k| self.tracks.remove(&k))
+            .collect::<Vec<_>>();
+        ended.sort_by_key(|t| (t.source_id, t.first_frame, t.object_id));
+        ended
+    }
+}
+
+/// Exporter writing [`Record::Track`] by the inner exporter instead of frames
+pub struct TrackExporter {
+    inner: Box<dyn Exporter>,
+    aggregator: TrackAggregator,
+}
+
+impl TrackExporter {
+    pub fn new(inner: Box<dyn Exporter>, timeout_frames: u32) -> Self {
+        Self {
+            inner,
+            aggregator: TrackAggregator::new(timeout_frames),
+        }
+    }
+
+    fn write_tracks(&mut self, tracks: Vec<TrackEvent>) -> io::Result<()> {
+        for t in tracks {
+            self.inner.write_record(&Record::Track(t))?;
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for TrackExporter {
+    fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        match record {
+            Record::Frame(f) => {
+                let ended = self.aggregator.push(f);
+                self.write_tracks(ended)
+            }
+            Record::Source(e) => {
+                if e.state == SourceState::Removed {
+                    let ended = self.aggregator.remove_source(e.source_id);
+                    self.write_tracks(ended)?;
+                }
+                self.inner.write_record(record)
+            }
+            Record::Track(_) | Record::Latency(_) | Record::Audio(_) => {
+                self.inner.write_record(record)
+            }
+        }
+    }
+
+    fn dropped(&self) -> u64 {
+        self.inner.dropped()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let ended = self.aggregator.flush();
+        self.write_tracks(ended)?;
+        self.inner.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{frame, frame_info, object, Collect};
+    use crate::timestamp::FrameTimestamps;
+    use crate::SourceEvent;
+
+    fn labeled(class_id: i32, object_id: u64, label: &str, confidence: f32) -> ObjectMeta {
+        let mut o = object(class_id, object_id, [0., 0., 10., 10.], confidence);
+        o.label = label.to_owned();
+        o
+    }
+
+    fn ids(tracks: &[TrackEvent]) -> Vec<(u32, u64)> {
+        tracks.iter().map(|t| (t.source_id, t.object_id)).collect()
+    }
+
+    #[test]
+    fn tracks_end_after_timeout_of_their_source() {
+        let mut a = TrackAggregator::new(2);
+        let car = || labeled(0, 1, "car", 0.5);
+        assert!(a.push(&frame(0, 0, vec![car()])).is_empty());
+        assert!(a.push(&frame(1, 0, vec![car()])).is_empty());
+        assert!(a.push(&frame(0, 1, vec![car()])).is_empty());
+        assert!(a.push(&frame(0, 2, vec![])).is_empty());
+        // 2 frames without the object is not over the timeout
+        assert!(a.push(&frame(0, 3, vec![])).is_empty());
+        let ended = a.push(&frame(0, 4, vec![]));
+        assert_eq!(ids(&ended), [(0, 1)]);
+        let t = &ended[0];
+        assert_eq!((t.first_frame, t.last_frame, t.length), (0, 1, 2));
+        // the same object id of another source is a different track
+        assert!(a.push(&frame(1, 2, vec![])).is_empty());
+        assert_eq!(ids(&a.push(&frame(1, 3, vec![]))), [(1, 1)]);
+        assert!(a.flush().is_empty());
+    }
+
+    #[test]
+    fn untracked_objects_are_skipped() {
+        let mut a = TrackAggregator::new(0);
+        a.push(&frame(
+            0,
+            0,
+            vec![labeled(0, UNTRACKED_OBJECT_ID, "car", 0.5)],
+        ));
+        assert!(a.flush().is_empty());
+    }
+
+    #[test]
+    fn class_votes_keep_the_earlier_class_on_ties() {
+        let mut a = TrackAggregator::new(30);
+        a.push(&frame(0, 0, vec![labeled(2, 1, "car", 0.5)]));
+        a.push(&frame(0, 1, vec![labeled(5, 1, "truck", 0.5)]));
+        let t = a.flush().remove(0);
+        assert_eq!((t.class_id, t.label.as_str()), (2, "car"));
+        assert_eq!(t.class_votes, BTreeMap::from([(2, 1), (5, 1)]));
+
+        let mut a = TrackAggregator::new(30);
+        a.push(&frame(0, 0, vec![labeled(2, 1, "car", 0.5)]));
+        a.push(&frame(0, 1, vec![labeled(5, 1, "truck", 0.5)]));
+        a.push(&frame(0, 2, vec![labeled(5, 1, "truck", 0.5)]));
+        let t = a.flush().remove(0);
+        assert_eq!((t.class_id, t.label.as_str()), (5, "truck"));
+        assert_eq!(t.class_votes, BTreeMap::from([(2, 1), (5, 2)]));
+    }
+
+    #[test]
+    fn best_snapshot_and_timestamps() {
+        let timed = |frame_num: i32, ntp: Option<u64>, confidence: f32| {
+            let timestamps = FrameTimestamps {
+                ntp,
+                ..Default::default()
+            };
+            FrameObjects::new(
+                frame_info(0, frame_num, timestamps),
+                vec![labeled(0, 1, "car", confidence)],
+            )
+        };
+        let mut a = TrackAggregator::new(30);
+        a.push(&timed(0, Some(100), 0.5));
+        a.push(&timed(1, Some(200), 0.9));
+        // equal confidence keeps the earlier frame
+        a.push(&timed(2, Some(300), 0.9));
+        // frames without a timestamp keep the last one
+        a.push(&timed(3, None, 0.1));
+        let t = a.flush().remove(0);
+        assert_eq!(
+            (t.first_timestamp, t.last_timestamp),
+            (Some(100), Some(300))
+        );
+        assert_eq!((t.last_frame, t.length), (3, 4));
+        assert_eq!((t.best.frame_num, t.best.timestamp), (1, Some(200)));
+        assert_eq!(t.best.confidence, 0.9);
+    }
+
+    #[test]
+    fn exporter_ends_tracks_of_removed_sources() {
+        let (inner, written) = Collect::boxed();
+        let mut e = TrackExporter::new(inner, 30);
+        let records = [
+            Record::Frame(frame(0, 0, vec![labeled(0, 1, "car", 0.5)])),
+            Record::Frame(frame(1, 0, vec![labeled(0, 2, "car", 0.5)])),
+            Record::Frame(frame(0, 1, vec![labeled(0, 3, "car", 0.5)])),
+            Record::Source(SourceEvent::new(0, SourceState::Removed, "file:///a.mp4")),
+            Record::Source(SourceEvent::new(2, SourceState::Added, "file:///b.mp4")),
+        ];
+        for r in records.iter() {
+            e.write_record(r).unwrap();
+        }
+        let summary = |records: &[Record]| {
+            records
+                .iter()
+                .map(|r| match r {
+                    Record::Track(t) => format!("track {}/{}", t.source_id, t.object_id),
+                    Record::Source(s) => format!("source {} {:?}", s.source_id, s.state),
+                    _ => "frame".to_owned(),
+                })
+                .collect::<Vec<_>>()
+        };
+        // tracks of the removed source are written before its event, frames are not
+        assert_eq!(
+            summary(&written.lock().unwrap()),
+            [
+                "track 0/1",
+                "track 0/3",
+                "source 0 Removed",
+                "source 2 Added"
+            ]
+        );
+        e.finish().unwrap();
+        assert_eq!(summary(&written.lock().unwrap()[4..]), ["track 1/2"]);
+    }
+}
@@ -1,4 +1,5 @@
 fn main() {
     println!("cargo:rustc-link-search=native=/opt/nvidia/deepstream/deepstream/lib/");
     println!("cargo:rustc-link-lib=dylib=nvdsgst_meta");
+    println!("cargo:rustc-link-lib=dylib=nvds_meta");
 }
@@ -0,0 +1,274 @@
+//! Audio frame meta of batches made by nvstreammux from audio sources
+//!
+//! `NvDsAudioFrameMeta` of nvds_audio_meta.h, which is not included in the bindings.
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint};
+
+use gst::{glib, ClockTime};
+
+use crate::{imp, nvlist, NvDsClassifierMeta, NvDsUserMeta};
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+struct RawAudioFrameMeta {
+    base_meta: imp::NvDsBaseMeta,
+    pad_index: c_uint,
+    batch_id: c_uint,
+    frame_num: c_int,
+    buf_pts: u64,
+    ntp_timestamp: u64,
+    source_id: c_uint,
+    num_samples_per_frame: c_int,
+    sample_rate: c_uint,
+    num_channels: c_uint,
+    format: c_uint,
+    layout: c_uint,
+    infer_done: glib::ffi::gboolean,
+    class_id: c_int,
+    confidence: f32,
+    class_label: [c_char; imp::MAX_LABEL_SIZE as usize],
+    classifier_meta_list: *mut imp::NvDsClassifierMetaList,
+    frame_user_meta_list: *mut imp::NvDsUserMetaList,
+    misc_frame_info: [i64; imp::MAX_USER_FIELDS as usize],
+    reserved: [i64; imp::MAX_RESERVED_FIELDS as usize],
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct NvDsAudioFrameMeta(RawAudioFrameMeta);
+
+impl NvDsAudioFrameMeta {
+    #[inline]
+    pub fn pad_index(&self) -> u32 {
+        self.0.pad_index
+    }
+    #[inline]
+    pub fn batch_id(&self) -> u32 {
+        self.0.batch_id
+    }
+    #[inline]
+    pub fn source_id(&self) -> u32 {
+        self.0.source_id
+    }
+    #[inline]
+    pub fn frame_num(&self) -> i32 {
+        self.0.frame_num
+    }
+    /// `None` if the source did not set PTS of the frame
+    #[inline]
+    pub fn buf_pts(&self) -> Option<ClockTime> {
+        if self.0.buf_pts == gst::ffi::GST_CLOCK_TIME_NONE {
+            None
+        } else {
+            Some(ClockTime::from_nseconds(self.0.buf_pts))
+        }
+    }
+    #[inline]
+    pub fn ntp_timestamp(&self) -> u64 {
+        self.0.ntp_timestamp
+    }
+    #[inline]
+    pub fn num_samples_per_frame(&self) -> i32 {
+        self.0.num_samples_per_frame
+    }
+    #[inline]
+    pub fn sample_rate(&self) -> u32 {
+        self.0.sample_rate
+    }
+    #[inline]
+    pub fn num_channels(&self) -> u32 {
+        self.0.num_channels
+    }
+    /// nvinferaudio processed the frame
+    #[inline]
+    pub fn infer_done(&self) -> bool {
+        self.0.infer_done != glib::ffi::GFALSE
+    }
+    /// class with the best confidence of the audio classifier
+    #[inline]
+    pub fn class_id(&self) -> i32 {
+        self.0.class_id
+    }
+    #[inline]
+    pub fn confidence(&self) -> f32 {
+        self.0.confidence
+    }
+    #[inline]
+    pub fn class_label(&self) -> &CStr {
+        let label = &self.0.class_label;
+        if label.contains(&0) {
+            unsafe { CStr::from_ptr(label.as_ptr()) }
+        } else {
+            Default::default()
+        }
+    }
+    /// results of audio classifiers operated on the frame
+    pub fn classifier_meta_list(&self) -> nvlist::GListIter<'_, NvDsClassifierMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(
+                self.0.classifier_meta_list as *const glib::ffi::GList,
+            )
+        }
+    }
+    pub fn user_meta_list(&self) -> nvlist::GListIter<'_, NvDsUserMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(
+                self.0.frame_user_meta_list as *const glib::ffi::GList,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, FrameMeta, NvDsBatchMeta};
+
+    /// Batch whose `frame_meta_list` holds the given entries in order
+    struct MockBatch {
+        batch: Box<imp::NvDsBatchMeta>,
+        _list: Vec<glib::ffi::GList>,
+        video: Vec<Box<imp::NvDsFrameMeta>>,
+        _audio: Vec<Box<RawAudioFrameMeta>>,
+        _other: Vec<Box<imp::NvDsBaseMeta>>,
+    }
+
+    enum Entry {
+        Video(u32, i32),
+        Audio(u32, i32),
+        /// meta of another type, such as one added by a custom element
+        Other,
+    }
+
+    impl MockBatch {
+        fn new(meta_type: imp::NvDsMetaType, entries: &[Entry]) -> Self {
+            let (mut video, mut audio, mut other) = (vec![], vec![], vec![]);
+            let mut data = vec![];
+            for e in entries {
+                let p = unsafe {
+                    match *e {
+                        Entry::Video(source_id, frame_num) => {
+                            let mut f: Box<imp::NvDsFrameMeta> = Box::new(std::mem::zeroed());
+                            f.base_meta.meta_type = imp::NvDsMetaType_NVDS_FRAME_META;
+                            f.source_id = source_id;
+                            f.frame_num = frame_num;
+                            video.push(f);
+                            &mut **video.last_mut().unwrap() as *mut _ as glib::ffi::gpointer
+                        }
+                        Entry::Audio(source_id, frame_num) => {
+                            let mut f: Box<RawAudioFrameMeta> = Box::new(std::mem::zeroed());
+                            f.base_meta.meta_type = imp::NvDsMetaType_NVDS_AUDIO_FRAME_META;
+                            f.source_id = source_id;
+                            f.frame_num = frame_num;
+                            f.buf_pts = gst::ffi::GST_CLOCK_TIME_NONE;
+                            audio.push(f);
+                            &mut **audio.last_mut().unwrap() as *mut _ as glib::ffi::gpointer
+                        }
+                        Entry::Other => {
+                            let mut m: Box<imp::NvDsBaseMeta> = Box::new(std::mem::zeroed());
+                            m.meta_type = imp::NvDsMetaType_NVDS_USER_META;
+                            other.push(m);
+                            &mut **other.last_mut().unwrap() as *mut _ as glib::ffi::gpointer
+                        }
+                    }
+                };
+                data.push(p);
+            }
+            let mut list = data
+                .into_iter()
+                .map(|data| glib::ffi::GList {
+                    data,
+                    next: std::ptr::null_mut(),
+                    prev: std::ptr::null_mut(),
+                })
+                .collect::<Vec<_>>();
+            for i in 1..list.len() {
+                let next = &mut list[i] as *mut glib::ffi::GList;
+                list[i - 1].next = next;
+            }
+            let mut batch: Box<imp::NvDsBatchMeta> = Box::new(unsafe { std::mem::zeroed() });
+            batch.base_meta.meta_type = meta_type;
+            batch.num_frames_in_batch = entries.len() as c_uint;
+            batch.frame_meta_list = list
+                .first_mut()
+                .map_or(std::ptr::null_mut(), |l| l as *mut glib::ffi::GList as _);
+            Self {
+                batch,
+                _list: list,
+                video,
+                _audio: audio,
+                _other: other,
+            }
+        }
+
+        fn batch_meta(&mut self) -> &mut NvDsBatchMeta {
+            unsafe { &mut *(&mut *self.batch as *mut imp::NvDsBatchMeta as *mut NvDsBatchMeta) }
+        }
+    }
+
+    #[test]
+    fn frames_are_dispatched_by_meta_type() {
+        let mut batch = MockBatch::new(
+            imp::NvDsMetaType_NVDS_BATCH_META,
+            &[
+                Entry::Audio(0, 5),
+                Entry::Video(0, 7),
+                Entry::Other,
+                Entry::Video(1, 8),
+                Entry::Audio(2, 9),
+            ],
+        );
+        let batch_meta = batch.batch_meta();
+        assert!(!batch_meta.is_audio());
+        let frames = batch_meta
+            .frames()
+            .map(|f| match f {
+                FrameMeta::Video(f) => ("video", f.source_id(), f.frame_num()),
+                FrameMeta::Audio(f) => ("audio", f.source_id(), f.frame_num()),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            frames,
+            [
+                ("audio", 0, 5),
+                ("video", 0, 7),
+                ("video", 1, 8),
+                ("audio", 2, 9)
+            ]
+        );
+        let video = batch_meta
+            .frame_meta_list()
+            .map(|f| f.frame_num())
+            .collect::<Vec<_>>();
+        assert_eq!(video, [7, 8]);
+        let audio = batch_meta
+            .audio_frame_meta_list()
+            .map(|f| (f.frame_num(), f.buf_pts(), f.class_label().to_bytes().len()))
+            .collect::<Vec<_>>();
+        assert_eq!(audio, [(5, None, 0), (9, None, 0)]);
+    }
+
+    #[test]
+    fn user_meta_is_attached_to_video_frames_only() {
+        let mut batch = MockBatch::new(
+            imp::NvDsMetaType_NVDS_BATCH_META,
+            &[Entry::Audio(0, 5), Entry::Other, Entry::Video(0, 7)],
+        );
+        let expected = &mut *batch.video[0] as *mut imp::NvDsFrameMeta;
+        // the audio frame of source 0 comes first but is skipped
+        assert_eq!(batch.batch_meta().video_frame_ptr(0).unwrap(), expected);
+
+        let mut batch = MockBatch::new(
+            imp::NvDsMetaType_NVDS_AUDIO_BATCH_META,
+            &[Entry::Audio(0, 5), Entry::Audio(1, 5)],
+        );
+        let batch_meta = batch.batch_meta();
+        assert!(batch_meta.is_audio());
+        assert_eq!(batch_meta.frame_meta_list().count(), 0);
+        assert!(matches!(
+            batch_meta.video_frame_ptr(1),
+            Err(Error::FrameNotFound(1))
+        ));
+    }
+}
@@ -0,0 +1,23 @@
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("buffer has no NvDsMeta")]
+    NoMeta,
+    #[error("NvDsMeta is not a batch meta: meta_type {0}")]
+    NotBatchMeta(crate::imp::NvDsMetaType),
+    #[error("no frame of source {0} in the batch")]
+    FrameNotFound(u32),
+    #[error("failed to acquire a meta from the pool")]
+    AcquireMeta,
+    #[error("no output layer at index {0}")]
+    NoLayer(usize),
+    #[error("no output layer named {0:?}")]
+    LayerNotFound(String),
+    #[error("layer {0:?} has no host buffer")]
+    NoHostBuffer(String),
+    #[error("layer {0:?} has unsupported data type {1}")]
+    UnsupportedDataType(String, u32),
+    #[error("host buffer of layer {0:?} is not aligned to its data type")]
+    UnalignedBuffer(String),
+    #[error("field {0} contains a nul byte")]
+    NulString(&'static str),
+}
@@ -0,0 +1,932 @@
+//! Event message meta consumed by nvmsgconv
+//!
+//! `NvDsEventMsgMeta` and its extension objects of nvdsmeta_schema.h, which are not
+//! included in the bindings. Strings and extension objects are allocated by glib and
+//! deep copied or freed by the `copy_func` and `release_func` of the user meta.
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gst::glib;
+
+use crate::{imp, Error, NvBbox_Coords, NvDsBatchMeta, NvDsObjectMeta, NvDsUserMeta};
+
+#[cfg(not(test))]
+use glib::ffi::g_free;
+#[cfg(test)]
+use tests::g_free;
+
+/// `NvDsEventType`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventType {
+    Entry,
+    Exit,
+    Moving,
+    Stopped,
+    Empty,
+    Parked,
+    Reset,
+    Custom,
+}
+
+impl EventType {
+    fn to_raw(self) -> c_uint {
+        match self {
+            EventType::Entry => 0,
+            EventType::Exit => 1,
+            EventType::Moving => 2,
+            EventType::Stopped => 3,
+            EventType::Empty => 4,
+            EventType::Parked => 5,
+            EventType::Reset => 6,
+            EventType::Custom => 0x101,
+        }
+    }
+}
+
+/// `NvDsObjectType`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectType {
+    Vehicle,
+    Person,
+    Face,
+    Bag,
+    Bicycle,
+    RoadSign,
+    Custom,
+    Unknown,
+}
+
+impl ObjectType {
+    fn to_raw(self) -> c_uint {
+        match self {
+            ObjectType::Vehicle => OBJECT_TYPE_VEHICLE,
+            ObjectType::Person => OBJECT_TYPE_PERSON,
+            ObjectType::Face => 2,
+            ObjectType::Bag => 3,
+            ObjectType::Bicycle => 4,
+            ObjectType::RoadSign => 5,
+            ObjectType::Custom => 0x101,
+            ObjectType::Unknown => 0x102,
+        }
+    }
+}
+
+const OBJECT_TYPE_VEHICLE: c_uint = 0;
+const OBJECT_TYPE_PERSON: c_uint = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct NvDsRect {
+    top: f32,
+    left: f32,
+    width: f32,
+    height: f32,
+}
+
+/// `NvDsGeoLocation` (lat, lon, alt) and `NvDsCoordinate` (x, y, z)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct NvDsTriple {
+    a: f64,
+    b: f64,
+    c: f64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+struct NvDsObjectSignature {
+    signature: *mut f64,
+    size: c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+struct NvDsEmbedding {
+    embedding_vector: *mut f32,
+    embedding_length: c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct RawVehicleObject {
+    type_: *mut c_char,
+    make: *mut c_char,
+    model: *mut c_char,
+    color: *mut c_char,
+    region: *mut c_char,
+    license: *mut c_char,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct RawPersonObject {
+    gender: *mut c_char,
+    hair: *mut c_char,
+    cap: *mut c_char,
+    apparel: *mut c_char,
+    age: c_uint,
+}
+
+/// `NVDS_EVENT_MSG_META` attached to a frame
+///
+/// `embedding` was added in DeepStream 6.2 and is left empty, so the meta is also
+/// read correctly by earlier versions.
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsEventMsgMeta {
+    type_: c_uint,
+    obj_type: c_uint,
+    bbox: NvDsRect,
+    location: NvDsTriple,
+    coordinate: NvDsTriple,
+    obj_signature: NvDsObjectSignature,
+    obj_class_id: c_int,
+    sensor_id: c_int,
+    module_id: c_int,
+    place_id: c_int,
+    component_id: c_int,
+    frame_id: c_int,
+    confidence: f64,
+    tracking_id: c_int,
+    ts: *mut c_char,
+    object_id: *mut c_char,
+    sensor_str: *mut c_char,
+    other_attrs: *mut c_char,
+    video_path: *mut c_char,
+    ext_msg: *mut c_void,
+    ext_msg_size: c_uint,
+    embedding: NvDsEmbedding,
+}
+
+/// `None` if null
+unsafe fn opt_str<'a>(p: *const c_char) -> Option<&'a CStr> {
+    if p.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(p))
+    }
+}
+
+impl NvDsEventMsgMeta {
+    /// One of `NvDsEventType`
+    #[inline]
+    pub fn event_type(&self) -> u32 {
+        self.type_
+    }
+    /// One of `NvDsObjectType`
+    #[inline]
+    pub fn object_type(&self) -> u32 {
+        self.obj_type
+    }
+    #[inline]
+    pub fn bbox(&self) -> NvBbox_Coords {
+        NvBbox_Coords {
+            left: self.bbox.left,
+            top: self.bbox.top,
+            width: self.bbox.width,
+            height: self.bbox.height,
+        }
+    }
+    #[inline]
+    pub fn class_id(&self) -> i32 {
+        self.obj_class_id
+    }
+    #[inline]
+    pub fn sensor_id(&self) -> i32 {
+        self.sensor_id
+    }
+    #[inline]
+    pub fn frame_id(&self) -> i32 {
+        self.frame_id
+    }
+    #[inline]
+    pub fn confidence(&self) -> f64 {
+        self.confidence
+    }
+    #[inline]
+    pub fn tracking_id(&self) -> i32 {
+        self.tracking_id
+    }
+    /// ISO 8601 timestamp
+    #[inline]
+    pub fn ts(&self) -> Option<&CStr> {
+        unsafe { opt_str(self.ts) }
+    }
+    #[inline]
+    pub fn object_id(&self) -> Option<&CStr> {
+        unsafe { opt_str(self.object_id) }
+    }
+    #[inline]
+    pub fn sensor_str(&self) -> Option<&CStr> {
+        unsafe { opt_str(self.sensor_str) }
+    }
+}
+
+impl NvDsUserMeta {
+    pub fn event_msg_meta(&self) -> Option<&NvDsEventMsgMeta> {
+        if self.meta_type() == imp::NvDsMetaType_NVDS_EVENT_MSG_META
+            && !self.0.user_meta_data.is_null()
+        {
+            unsafe { Some(&*(self.0.user_meta_data as *const NvDsEventMsgMeta)) }
+        } else {
+            None
+        }
+    }
+}
+
+/// `NvDsVehicleObject`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VehicleObject {
+    /// `type`
+    pub kind: String,
+    pub make: String,
+    pub model: String,
+    pub color: String,
+    pub region: String,
+    pub license: String,
+}
+
+/// `NvDsPersonObject`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PersonObject {
+    pub gender: String,
+    pub hair: String,
+    pub cap: String,
+    pub apparel: String,
+    pub age: u32,
+}
+
+/// Extension object of the event, which also decides the object type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventMsgExt {
+    Vehicle(VehicleObject),
+    Person(PersonObject),
+}
+
+/// `YYYY-MM-DDTHH:MM:SS.mmmZ` of the time
+fn rfc3339_millis(t: SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs();
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // civil from days of http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        d.subsec_millis()
+    )
+}
+
+/// Builder of `NvDsEventMsgMeta` attached by [`NvDsBatchMeta::add_event_msg_meta`]
+///
+/// Empty strings are left null.
+#[derive(Debug, Clone)]
+pub struct EventMsgBuilder {
+    event_type: EventType,
+    object_type: ObjectType,
+    bbox: NvBbox_Coords,
+    location: (f64, f64, f64),
+    coordinate: (f64, f64, f64),
+    class_id: i32,
+    sensor_id: i32,
+    module_id: i32,
+    place_id: i32,
+    component_id: i32,
+    frame_id: i32,
+    confidence: f64,
+    tracking_id: i32,
+    ts: String,
+    object_id: String,
+    sensor_str: String,
+    other_attrs: String,
+    video_path: String,
+    ext: Option<EventMsgExt>,
+}
+
+impl EventMsgBuilder {
+    pub fn new(event_type: EventType, object_type: ObjectType) -> Self {
+        Self {
+            event_type,
+            object_type,
+            bbox: NvBbox_Coords {
+                left: 0.0,
+                top: 0.0,
+                width: 0.0,
+                height: 0.0,
+            },
+            location: (0.0, 0.0, 0.0),
+            coordinate: (0.0, 0.0, 0.0),
+            class_id: 0,
+            sensor_id: 0,
+            module_id: 0,
+            place_id: 0,
+            component_id: 0,
+            frame_id: 0,
+            confidence: 0.0,
+            tracking_id: 0,
+            ts: String::new(),
+            object_id: String::new(),
+            sensor_str: String::new(),
+            other_attrs: String::new(),
+            video_path: String::new(),
+            ext: None,
+        }
+    }
+
+    /// Take bbox, class id, confidence and tracking id of the object
+    ///
+    /// `trackingId` is a C int, so the tracking id is -1 as untracked objects when the
+    /// object id does not fit in it.
+    pub fn object(mut self, o: &NvDsObjectMeta) -> Self {
+        self.bbox = *o.detector_bbox();
+        self.class_id = o.class_id();
+        self.confidence = o.confidence() as f64;
+        self.tracking_id = i32::try_from(o.object_id()).unwrap_or(-1);
+        self
+    }
+    pub fn bbox(mut self, bbox: NvBbox_Coords) -> Self {
+        self.bbox = bbox;
+        self
+    }
+    pub fn location(mut self, lat: f64, lon: f64, alt: f64) -> Self {
+        self.location = (lat, lon, alt);
+        self
+    }
+    pub fn coordinate(mut self, x: f64, y: f64, z: f64) -> Self {
+        self.coordinate = (x, y, z);
+        self
+    }
+    pub fn class_id(mut self, class_id: i32) -> Self {
+        self.class_id = class_id;
+        self
+    }
+    pub fn confidence(mut self, confidence: f64) -> Self {
+        self.confidence = confidence;
+        self
+    }
+    pub fn tracking_id(mut self, tracking_id: i32) -> Self {
+        self.tracking_id = tracking_id;
+        self
+    }
+    pub fn frame_id(mut self, frame_id: i32) -> Self {
+        self.frame_id = frame_id;
+        self
+    }
+    /// ISO 8601 timestamp such as `2023-01-01T00:00:00.000Z`
+    pub fn ts(mut self, ts: &str) -> Self {
+        self.ts = ts.to_owned();
+        self
+    }
+    /// Timestamp of the time formatted as RFC 3339 UTC in milliseconds, times before the
+    /// unix epoch are the epoch
+    pub fn time(self, t: SystemTime) -> Self {
+        let ts = rfc3339_millis(t);
+        self.ts(&ts)
+    }
+    pub fn object_id(mut self, object_id: &str) -> Self {
+        self.object_id = object_id.to_owned();
+        self
+    }
+    /// `sensorId` and `sensorStr` matching `[sensorN]` of the msgconv config
+    pub fn sensor(mut self, sensor_id: i32, sensor_str: &str) -> Self {
+        self.sensor_id = sensor_id;
+        self.sensor_str = sensor_str.to_owned();
+        self
+    }
+    /// `placeId` matching `[placeN]` of the msgconv config
+    pub fn place_id(mut self, place_id: i32) -> Self {
+        self.place_id = place_id;
+        self
+    }
+    /// `moduleId` matching `[analyticsN]` of the msgconv config
+    pub fn module_id(mut self, module_id: i32) -> Self {
+        self.module_id = module_id;
+        self
+    }
+    pub fn component_id(mut self, component_id: i32) -> Self {
+        self.component_id = component_id;
+        self
+    }
+    pub fn other_attrs(mut self, other_attrs: &str) -> Self {
+        self.other_attrs = other_attrs.to_owned();
+        self
+    }
+    pub fn video_path(mut self, video_path: &str) -> Self {
+        self.video_path = video_path.to_owned();
+        self
+    }
+    /// Set the extension object and its object type
+    pub fn ext(mut self, ext: EventMsgExt) -> Self {
+        self.object_type = match ext {
+            EventMsgExt::Vehicle(_) => ObjectType::Vehicle,
+            EventMsgExt::Person(_) => ObjectType::Person,
+        };
+        self.ext = Some(ext);
+        self
+    }
+
+    /// Allocate the meta by glib, strings are checked before any allocation
+    fn to_raw(&self) -> Result<*mut NvDsEventMsgMeta, Error> {
+        let ts = cstring(&self.ts, "ts")?;
+        let object_id = cstring(&self.object_id, "object_id")?;
+        let sensor_str = cstring(&self.sensor_str, "sensor_str")?;
+        let other_attrs = cstring(&self.other_attrs, "other_attrs")?;
+        let video_path = cstring(&self.video_path, "video_path")?;
+        let ext = match self.ext.as_ref() {
+            Some(EventMsgExt::Vehicle(v)) => Some(ExtStrings::Vehicle([
+                cstring(&v.kind, "type")?,
+                cstring(&v.make, "make")?,
+                cstring(&v.model, "model")?,
+                cstring(&v.color, "color")?,
+                cstring(&v.region, "region")?,
+                cstring(&v.license, "license")?,
+            ])),
+            Some(EventMsgExt::Person(p)) => Some(ExtStrings::Person(
+                [
+                    cstring(&p.gender, "gender")?,
+                    cstring(&p.hair, "hair")?,
+                    cstring(&p.cap, "cap")?,
+                    cstring(&p.apparel, "apparel")?,
+                ],
+                p.age,
+            )),
+            None => None,
+        };
+        unsafe {
+            let p = g_new0::<NvDsEventMsgMeta>();
+            let m = &mut *p;
+            m.type_ = self.event_type.to_raw();
+            m.obj_type = self.object_type.to_raw();
+            m.bbox = NvDsRect {
+                top: self.bbox.top,
+                left: self.bbox.left,
+                width: self.bbox.width,
+                height: self.bbox.height,
+            };
+            m.location = NvDsTriple {
+                a: self.location.0,
+                b: self.location.1,
+                c: self.location.2,
+            };
+            m.coordinate = NvDsTriple {
+                a: self.coordinate.0,
+                b: self.coordinate.1,
+                c: self.coordinate.2,
+            };
+            m.obj_class_id = self.class_id;
+            m.sensor_id = self.sensor_id;
+            m.module_id = self.module_id;
+            m.place_id = self.place_id;
+            m.component_id = self.component_id;
+            m.frame_id = self.frame_id;
+            m.confidence = self.confidence;
+            m.tracking_id = self.tracking_id;
+            m.ts = strdup(&ts);
+            m.object_id = strdup(&object_id);
+            m.sensor_str = strdup(&sensor_str);
+            m.other_attrs = strdup(&other_attrs);
+            m.video_path = strdup(&video_path);
+            match ext {
+                Some(ExtStrings::Vehicle(s)) => {
+                    let v = g_new0::<RawVehicleObject>();
+                    (*v).type_ = strdup(&s[0]);
+                    (*v).make = strdup(&s[1]);
+                    (*v).model = strdup(&s[2]);
+                    (*v).color = strdup(&s[3]);
+                    (*v).region = strdup(&s[4]);
+                    (*v).license = strdup(&s[5]);
+                    m.ext_msg = v as *mut c_void;
+                    m.ext_msg_size = std::mem::size_of::<RawVehicleObject>() as c_uint;
+                }
+                Some(ExtStrings::Person(s, age)) => {
+                    let v = g_new0::<RawPersonObject>();
+                    (*v).gender = strdup(&s[0]);
+                    (*v).hair = strdup(&s[1]);
+                    (*v).cap = strdup(&s[2]);
+                    (*v).apparel = strdup(&s[3]);
+                    (*v).age = age;
+                    m.ext_msg = v as *mut c_void;
+                    m.ext_msg_size = std::mem::size_of::<RawPersonObject>() as c_uint;
+                }
+                None => {}
+            }
+            Ok(p)
+        }
+    }
+}
+
+enum ExtStrings {
+    Vehicle([Option<CString>; 6]),
+    Person([Option<CString>; 4], u32),
+}
+
+/// `None` for an empty string
+fn cstring(s: &str, field: &'static str) -> Result<Option<CString>, Error> {
+    if s.is_empty() {
+        Ok(None)
+    } else {
+        CString::new(s)
+            .map(Some)
+            .map_err(|_| Error::NulString(field))
+    }
+}
+
+unsafe fn strdup(s: &Option<CString>) -> *mut c_char {
+    match s {
+        Some(s) => glib::ffi::g_strdup(s.as_ptr()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+unsafe fn g_new0<T>() -> *mut T {
+    glib::ffi::g_malloc0(std::mem::size_of::<T>()) as *mut T
+}
+
+/// Deep copy of the meta, the extension object is copied by the object type
+unsafe fn copy_meta(src: &NvDsEventMsgMeta) -> *mut NvDsEventMsgMeta {
+    let p = g_new0::<NvDsEventMsgMeta>();
+    std::ptr::copy_nonoverlapping(src, p, 1);
+    let m = &mut *p;
+    m.ts = glib::ffi::g_strdup(src.ts);
+    m.object_id = glib::ffi::g_strdup(src.object_id);
+    m.sensor_str = glib::ffi::g_strdup(src.sensor_str);
+    m.other_attrs = glib::ffi::g_strdup(src.other_attrs);
+    m.video_path = glib::ffi::g_strdup(src.video_path);
+    m.obj_signature.signature = std::ptr::null_mut();
+    m.obj_signature.size = 0;
+    m.embedding.embedding_vector = std::ptr::null_mut();
+    m.embedding.embedding_length = 0;
+    m.ext_msg = std::ptr::null_mut();
+    m.ext_msg_size = 0;
+    if src.ext_msg.is_null() {
+        return p;
+    }
+    match src.obj_type {
+        OBJECT_TYPE_VEHICLE => {
+            let s = &*(src.ext_msg as *const RawVehicleObject);
+            let v = g_new0::<RawVehicleObject>();
+            (*v).type_ = glib::ffi::g_strdup(s.type_);
+            (*v).make = glib::ffi::g_strdup(s.make);
+            (*v).model = glib::ffi::g_strdup(s.model);
+            (*v).color = glib::ffi::g_strdup(s.color);
+            (*v).region = glib::ffi::g_strdup(s.region);
+            (*v).license = glib::ffi::g_strdup(s.license);
+            m.ext_msg = v as *mut c_void;
+        }
+        OBJECT_TYPE_PERSON => {
+            let s = &*(src.ext_msg as *const RawPersonObject);
+            let v = g_new0::<RawPersonObject>();
+            (*v).gender = glib::ffi::g_strdup(s.gender);
+            (*v).hair = glib::ffi::g_strdup(s.hair);
+            (*v).cap = glib::ffi::g_strdup(s.cap);
+            (*v).apparel = glib::ffi::g_strdup(s.apparel);
+            (*v).age = s.age;
+            m.ext_msg = v as *mut c_void;
+        }
+        // only vehicle and person extensions are made by the builder
+        _ => return p,
+    }
+    m.ext_msg_size = src.ext_msg_size;
+    p
+}
+
+unsafe fn free_meta(p: *mut NvDsEventMsgMeta) {
+    let m = &mut *p;
+    g_free(m.ts as glib::ffi::gpointer);
+    g_free(m.object_id as glib::ffi::gpointer);
+    g_free(m.sensor_str as glib::ffi::gpointer);
+    g_free(m.other_attrs as glib::ffi::gpointer);
+    g_free(m.video_path as glib::ffi::gpointer);
+    if !m.ext_msg.is_null() {
+        match m.obj_type {
+            OBJECT_TYPE_VEHICLE => {
+                let v = &*(m.ext_msg as *const RawVehicleObject);
+                for s in [v.type_, v.make, v.model, v.color, v.region, v.license] {
+                    g_free(s as glib::ffi::gpointer);
+                }
+            }
+            OBJECT_TYPE_PERSON => {
+                let v = &*(m.ext_msg as *const RawPersonObject);
+                for s in [v.gender, v.hair, v.cap, v.apparel] {
+                    g_free(s as glib::ffi::gpointer);
+                }
+            }
+            _ => {}
+        }
+        g_free(m.ext_msg);
+    }
+    g_free(p as glib::ffi::gpointer);
+}
+
+/// `copy_func` of the user meta, called when the buffer is copied
+unsafe extern "C" fn copy_event_msg(
+    data: glib::ffi::gpointer,
+    _user_data: glib::ffi::gpointer,
+) -> glib::ffi::gpointer {
+    let user_meta = data as *mut imp::NvDsUserMeta;
+    let src = (*user_meta).user_meta_data as *const NvDsEventMsgMeta;
+    if src.is_null() {
+        return std::ptr::null_mut();
+    }
+    copy_meta(&*src) as glib::ffi::gpointer
+}
+
+/// `release_func` of the user meta, called when the meta returns to the pool
+unsafe extern "C" fn release_event_msg(data: glib::ffi::gpointer, _user_data: glib::ffi::gpointer) {
+    let user_meta = data as *mut imp::NvDsUserMeta;
+    let p = (*user_meta).user_meta_data as *mut NvDsEventMsgMeta;
+    if !p.is_null() {
+        free_meta(p);
+    }
+    (*user_meta).user_meta_data = std::ptr::null_mut();
+}
+
+impl NvDsBatchMeta {
+    /// Attach the event to the frame of the source as `NVDS_EVENT_MSG_META`
+    pub fn add_event_msg_meta(
+        &mut self,
+        source_id: u32,
+        event: &EventMsgBuilder,
+    ) -> Result<(), Error> {
+        let frame = self.video_frame_ptr(source_id)?;
+        let msg = event.to_raw()?;
+        unsafe {
+            let user_meta = imp::nvds_acquire_user_meta_from_pool(&mut self.0);
+            if user_meta.is_null() {
+                free_meta(msg);
+                return Err(Error::AcquireMeta);
+            }
+            (*user_meta).user_meta_data = msg as *mut c_void;
+            (*user_meta).base_meta.meta_type = imp::NvDsMetaType_NVDS_EVENT_MSG_META;
+            (*user_meta).base_meta.copy_func = Some(copy_event_msg);
+            (*user_meta).base_meta.release_func = Some(release_event_msg);
+            imp::nvds_add_user_meta_to_frame(frame, user_meta);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static FREED: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// `g_free` recording the pointers freed by [`free_meta`]
+    pub(super) unsafe fn g_free(p: glib::ffi::gpointer) {
+        if !p.is_null() {
+            FREED.with(|f| f.borrow_mut().push(p as usize));
+        }
+        glib::ffi::g_free(p);
+    }
+
+    fn take_freed() -> Vec<usize> {
+        let mut freed = FREED.with(|f| std::mem::take(&mut *f.borrow_mut()));
+        freed.sort_unstable();
+        freed
+    }
+
+    /// Every allocation of the meta: the meta, its strings, the extension and its strings
+    unsafe fn allocations(p: *const NvDsEventMsgMeta) -> Vec<usize> {
+        let m = &*p;
+        let mut ptrs = vec![
+            p as usize,
+            m.ts as usize,
+            m.object_id as usize,
+            m.sensor_str as usize,
+            m.other_attrs as usize,
+            m.video_path as usize,
+        ];
+        if !m.ext_msg.is_null() {
+            ptrs.push(m.ext_msg as usize);
+            match m.obj_type {
+                OBJECT_TYPE_VEHICLE => {
+                    let v = &*(m.ext_msg as *const RawVehicleObject);
+                    ptrs.extend(
+                        [v.type_, v.make, v.model, v.color, v.region, v.license]
+                            .map(|s| s as usize),
+                    );
+                }
+                OBJECT_TYPE_PERSON => {
+                    let v = &*(m.ext_msg as *const RawPersonObject);
+                    ptrs.extend([v.gender, v.hair, v.cap, v.apparel].map(|s| s as usize));
+                }
+                _ => {}
+            }
+        }
+        ptrs.retain(|&p| p != 0);
+        ptrs.sort_unstable();
+        ptrs
+    }
+
+    unsafe fn str_at<'a>(p: *const c_char) -> &'a str {
+        CStr::from_ptr(p).to_str().unwrap()
+    }
+
+    /// Copy the meta of the builder by the trampolines, then release both
+    unsafe fn copy_then_release(
+        builder: EventMsgBuilder,
+        check_ext: impl Fn(*const c_void, *const c_void),
+    ) {
+        take_freed();
+        let mut src: imp::NvDsUserMeta = std::mem::zeroed();
+        src.user_meta_data = builder.to_raw().unwrap() as *mut c_void;
+        let data = &mut src as *mut imp::NvDsUserMeta as glib::ffi::gpointer;
+        let original = src.user_meta_data as *const NvDsEventMsgMeta;
+
+        let copy = copy_event_msg(data, std::ptr::null_mut()) as *mut NvDsEventMsgMeta;
+        assert!(!copy.is_null());
+        let (o, c) = (&*original, &*copy);
+        assert_ne!(copy as *const NvDsEventMsgMeta, original);
+        for (a, b) in [
+            (o.ts, c.ts),
+            (o.object_id, c.object_id),
+            (o.sensor_str, c.sensor_str),
+            (o.other_attrs, c.other_attrs),
+            (o.video_path, c.video_path),
+        ] {
+            assert_ne!(a, b);
+            assert_eq!(str_at(a), str_at(b));
+        }
+        assert_eq!(c.event_type(), o.event_type());
+        assert_eq!(c.object_type(), o.object_type());
+        assert_eq!(c.tracking_id(), 7);
+        assert_eq!(c.ext_msg_size, o.ext_msg_size);
+        assert_ne!(c.ext_msg, o.ext_msg);
+        check_ext(o.ext_msg, c.ext_msg);
+
+        // the copy owns its own allocations, so both sets are freed once each
+        let mut expected = allocations(original);
+        expected.extend(allocations(copy));
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(expected.len(), 2 * allocations(copy).len());
+
+        release_event_msg(data, std::ptr::null_mut());
+        assert!(src.user_meta_data.is_null());
+        // a released meta is copied as null and released again without a double free
+        assert!(copy_event_msg(data, std::ptr::null_mut()).is_null());
+        release_event_msg(data, std::ptr::null_mut());
+
+        let mut copied: imp::NvDsUserMeta = std::mem::zeroed();
+        copied.user_meta_data = copy as *mut c_void;
+        release_event_msg(
+            &mut copied as *mut imp::NvDsUserMeta as glib::ffi::gpointer,
+            std::ptr::null_mut(),
+        );
+        assert!(copied.user_meta_data.is_null());
+        assert_eq!(take_freed(), expected);
+    }
+
+    fn builder() -> EventMsgBuilder {
+        EventMsgBuilder::new(EventType::Moving, ObjectType::Unknown)
+            .tracking_id(7)
+            .ts("2023-01-01T00:00:00.000Z")
+            .object_id("7")
+            .sensor(1, "CAMERA_ID")
+            .other_attrs("attr")
+            .video_path("/tmp/a.mp4")
+    }
+
+    #[test]
+    fn copy_then_release_vehicle() {
+        let vehicle = VehicleObject {
+            kind: "sedan".to_owned(),
+            make: "Bugatti".to_owned(),
+            model: "M".to_owned(),
+            color: "blue".to_owned(),
+            region: "CA".to_owned(),
+            license: "XX1234".to_owned(),
+        };
+        unsafe {
+            copy_then_release(builder().ext(EventMsgExt::Vehicle(vehicle)), |o, c| {
+                let (o, c) = (
+                    &*(o as *const RawVehicleObject),
+                    &*(c as *const RawVehicleObject),
+                );
+                for (a, b) in [
+                    (o.type_, c.type_),
+                    (o.make, c.make),
+                    (o.model, c.model),
+                    (o.color, c.color),
+                    (o.region, c.region),
+                    (o.license, c.license),
+                ] {
+                    assert_ne!(a, b);
+                    assert_eq!(str_at(a), str_at(b));
+                }
+                assert_eq!(str_at(c.license), "XX1234");
+            });
+        }
+    }
+
+    #[test]
+    fn copy_then_release_person() {
+        let person = PersonObject {
+            gender: "female".to_owned(),
+            hair: "black".to_owned(),
+            cap: "none".to_owned(),
+            apparel: "formal".to_owned(),
+            age: 45,
+        };
+        unsafe {
+            copy_then_release(builder().ext(EventMsgExt::Person(person)), |o, c| {
+                let (o, c) = (
+                    &*(o as *const RawPersonObject),
+                    &*(c as *const RawPersonObject),
+                );
+                for (a, b) in [
+                    (o.gender, c.gender),
+                    (o.hair, c.hair),
+                    (o.cap, c.cap),
+                    (o.apparel, c.apparel),
+                ] {
+                    assert_ne!(a, b);
+                    assert_eq!(str_at(a), str_at(b));
+                }
+                assert_eq!(c.age, 45);
+            });
+        }
+    }
+
+    #[test]
+    fn time_is_formatted_in_millis() {
+        let ts = |t: SystemTime| {
+            EventMsgBuilder::new(EventType::Entry, ObjectType::Face)
+                .time(t)
+                .ts
+        };
+        let after = |millis: u64| ts(UNIX_EPOCH + std::time::Duration::from_millis(millis));
+        assert_eq!(after(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(after(1_600_000_000_123), "2020-09-13T12:26:40.123Z");
+        // leap day and the end of a year
+        assert_eq!(after(951_782_400_005), "2000-02-29T00:00:00.005Z");
+        assert_eq!(after(1_704_067_199_999), "2023-12-31T23:59:59.999Z");
+        assert_eq!(
+            ts(UNIX_EPOCH - std::time::Duration::from_secs(1)),
+            "1970-01-01T00:00:00.000Z"
+        );
+    }
+
+    #[test]
+    fn tracking_id_out_of_c_int_is_untracked() {
+        let tracking_id = |object_id: u64| {
+            let mut o: NvDsObjectMeta = unsafe { std::mem::zeroed() };
+            o.0.object_id = object_id;
+            EventMsgBuilder::new(EventType::Moving, ObjectType::Vehicle)
+                .object(&o)
+                .tracking_id
+        };
+        assert_eq!(tracking_id(7), 7);
+        assert_eq!(tracking_id(i32::MAX as u64), i32::MAX);
+        assert_eq!(tracking_id(crate::UNTRACKED_OBJECT_ID), -1);
+        assert_eq!(tracking_id(1 << 40), -1);
+    }
+
+    #[test]
+    fn empty_strings_are_null() {
+        unsafe {
+            let p = EventMsgBuilder::new(EventType::Entry, ObjectType::Face)
+                .ts("2023-01-01T00:00:00.000Z")
+                .to_raw()
+                .unwrap();
+            assert!((*p).object_id().is_none());
+            assert!((*p).ext_msg.is_null());
+            let copy = copy_meta(&*p);
+            assert!((*copy).object_id().is_none());
+            assert_eq!(
+                (*copy).ts().unwrap().to_str(),
+                Ok("2023-01-01T00:00:00.000Z")
+            );
+            take_freed();
+            free_meta(copy);
+            free_meta(p);
+            // the meta and the timestamp of each
+            assert_eq!(take_freed().len(), 4);
+        }
+        assert!(matches!(
+            builder().object_id("a\0b").to_raw(),
+            Err(Error::NulString("object_id"))
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+//! Tensor output meta attached by nvinfer with `output-tensor-meta=1`
+//!
+//! `NvDsInferTensorMeta` of gstnvdsinfer.h and `NvDsInferLayerInfo` of nvdsinfer.h,
+//! which are not included in the bindings. Layers carry `NvDsInferDataType` of
+//! nvdsinfer.h, which differs from `NvDsDataType` of nvdsmeta.h.
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+pub use half::f16;
+
+use crate::Error;
+
+const NVDSINFER_MAX_DIMS: usize = 8;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct NvDsInferDims {
+    num_dims: c_uint,
+    d: [c_uint; NVDSINFER_MAX_DIMS],
+    num_elements: c_uint,
+}
+
+/// Data type of a layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NvDsInferDataType {
+    Float,
+    Half,
+    Int8,
+    Int32,
+}
+
+impl NvDsInferDataType {
+    fn from_raw(v: c_uint) -> Option<Self> {
+        match v {
+            0 => Some(Self::Float),
+            1 => Some(Self::Half),
+            2 => Some(Self::Int8),
+            3 => Some(Self::Int32),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Float | Self::Int32 => 4,
+            Self::Half => 2,
+            Self::Int8 => 1,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsInferLayerInfo {
+    data_type: c_uint,
+    infer_dims: NvDsInferDims,
+    binding_index: c_int,
+    layer_name: *const c_char,
+    buffer: *mut c_void,
+    is_input: c_int,
+}
+
+impl NvDsInferLayerInfo {
+    #[inline]
+    pub fn data_type(&self) -> Option<NvDsInferDataType> {
+        NvDsInferDataType::from_raw(self.data_type)
+    }
+    /// Dimensions without the batch dimension
+    #[inline]
+    pub fn dims(&self) -> &[u32] {
+        let n = (self.infer_dims.num_dims as usize).min(NVDSINFER_MAX_DIMS);
+        &self.infer_dims.d[..n]
+    }
+    #[inline]
+    pub fn num_elements(&self) -> usize {
+        self.infer_dims.num_elements as usize
+    }
+    #[inline]
+    pub fn binding_index(&self) -> i32 {
+        self.binding_index
+    }
+    #[inline]
+    pub fn layer_name(&self) -> &CStr {
+        if self.layer_name.is_null() {
+            Default::default()
+        } else {
+            unsafe { CStr::from_ptr(self.layer_name) }
+        }
+    }
+    #[inline]
+    pub fn is_input(&self) -> bool {
+        self.is_input != 0
+    }
+}
+
+/// Row-major view of a layer output
+#[derive(Debug, Clone, Copy)]
+pub struct TensorView<'a, T> {
+    data: &'a [T],
+    dims: &'a [u32],
+}
+
+impl<'a, T> TensorView<'a, T> {
+    #[inline]
+    pub fn shape(&self) -> &'a [u32] {
+        self.dims
+    }
+    #[inline]
+    pub fn as_slice(&self) -> &'a [T] {
+        self.data
+    }
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Element at the multi-dimensional index
+    pub fn get(&self, index: &[usize]) -> Option<&'a T> {
+        if index.len() != self.dims.len() {
+            return None;
+        }
+        let mut offset = 0;
+        for (&i, &d) in index.iter().zip(self.dims) {
+            if i >= d as usize {
+                return None;
+            }
+            offset = offset * d as usize + i;
+        }
+        self.data.get(offset)
+    }
+
+    /// Sub-tensors along the first dimension, e.g. rows of a `[N, C]` output
+    pub fn outer_iter(&self) -> impl Iterator<Item = TensorView<'a, T>> + 'a {
+        let (dims, inner) = match self.dims.split_first() {
+            Some((_, inner)) => (inner, inner.iter().map(|&d| d as usize).product::<usize>()),
+            None => (self.dims, 1),
+        };
+        self.data
+            .chunks_exact(inner.max(1))
+            .map(move |data| TensorView { data, dims })
+    }
+}
+
+impl<'a> TensorView<'a, f16> {
+    /// Convert FP16 elements to f32
+    pub fn to_f32_vec(&self) -> Vec<f32> {
+        self.data.iter().map(|v| v.to_f32()).collect()
+    }
+}
+
+/// Layer output by the data type
+#[derive(Debug, Clone, Copy)]
+pub enum Tensor<'a> {
+    Fp32(TensorView<'a, f32>),
+    Fp16(TensorView<'a, f16>),
+    Int8(TensorView<'a, i8>),
+    Int32(TensorView<'a, i32>),
+}
+
+impl<'a> Tensor<'a> {
+    pub fn shape(&self) -> &'a [u32] {
+        match self {
+            Self::Fp32(t) => t.shape(),
+            Self::Fp16(t) => t.shape(),
+            Self::Int8(t) => t.shape(),
+            Self::Int32(t) => t.shape(),
+        }
+    }
+
+    /// Elements converted to f32 regardless of the data type
+    pub fn to_f32_vec(&self) -> Vec<f32> {
+        match self {
+            Self::Fp32(t) => t.as_slice().to_vec(),
+            Self::Fp16(t) => t.to_f32_vec(),
+            Self::Int8(t) => t.as_slice().iter().map(|&v| v as f32).collect(),
+            Self::Int32(t) => t.as_slice().iter().map(|&v| v as f32).collect(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct NvDsInferNetworkInfo {
+    pub width: c_uint,
+    pub height: c_uint,
+    pub channels: c_uint,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsInferTensorMeta {
+    unique_id: c_uint,
+    num_output_layers: c_uint,
+    output_layers_info: *mut NvDsInferLayerInfo,
+    out_buf_ptrs_host: *mut *mut c_void,
+    out_buf_ptrs_dev: *mut *mut c_void,
+    gpu_id: c_int,
+    priv_data: *mut c_void,
+    network_info: NvDsInferNetworkInfo,
+    maintain_aspect_ratio: c_int,
+}
+
+impl NvDsInferTensorMeta {
+    /// gie-unique-id of the nvinfer which attached the meta
+    #[inline]
+    pub fn unique_id(&self) -> u32 {
+        self.unique_id
+    }
+    #[inline]
+    pub fn gpu_id(&self) -> i32 {
+        self.gpu_id
+    }
+    #[inline]
+    pub fn network_info(&self) -> &NvDsInferNetworkInfo {
+        &self.network_info
+    }
+    #[inline]
+    pub fn maintain_aspect_ratio(&self) -> bool {
+        self.maintain_aspect_ratio != 0
+    }
+    pub fn output_layers(&self) -> &[NvDsInferLayerInfo] {
+        if self.output_layers_info.is_null() {
+            &[]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(self.output_layers_info, self.num_output_layers as usize)
+            }
+        }
+    }
+    pub fn layer(&self, name: &str) -> Option<&NvDsInferLayerInfo> {
+        self.layer_index(name).map(|i| &self.output_layers()[i])
+    }
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.output_layers()
+            .iter()
+            .position(|l| l.layer_name().to_bytes() == name.as_bytes())
+    }
+
+    /// Typed view of the host copy of the `i`th output layer
+    ///
+    /// `buffer` of the layer info may be a device pointer, so the view reads
+    /// `out_buf_ptrs_host[i]` which nvinfer fills for each output layer.
+    pub fn layer_tensor(&self, i: usize) -> Result<Tensor<'_>, Error> {
+        let layer = self.output_layers().get(i).ok_or(Error::NoLayer(i))?;
+        let name = || layer.layer_name().to_string_lossy().into_owned();
+        let ptr = if self.out_buf_ptrs_host.is_null() {
+            std::ptr::null_mut()
+        } else {
+            unsafe { *self.out_buf_ptrs_host.add(i) }
+        };
+        if ptr.is_null() {
+            return Err(Error::NoHostBuffer(name()));
+        }
+        let data_type = layer
+            .data_type()
+            .ok_or_else(|| Error::UnsupportedDataType(name(), layer.data_type))?;
+        let dims = layer.dims();
+        let len = layer.num_elements();
+        // SAFETY: nvinfer allocates num_elements of the data type for each host buffer
+        // and keeps it until the meta is released.
+        let tensor = unsafe {
+            match data_type {
+                NvDsInferDataType::Float => view(ptr, dims, len).map(Tensor::Fp32),
+                NvDsInferDataType::Half => view(ptr, dims, len).map(Tensor::Fp16),
+                NvDsInferDataType::Int8 => view(ptr, dims, len).map(Tensor::Int8),
+                NvDsInferDataType::Int32 => view(ptr, dims, len).map(Tensor::Int32),
+            }
+        };
+        tensor.ok_or_else(|| Error::UnalignedBuffer(name()))
+    }
+
+    /// [`NvDsInferTensorMeta::layer_tensor`] of the layer named `name`
+    pub fn tensor(&self, name: &str) -> Result<Tensor<'_>, Error> {
+        let i = self
+            .layer_index(name)
+            .ok_or_else(|| Error::LayerNotFound(name.to_owned()))?;
+        self.layer_tensor(i)
+    }
+}
+
+/// `None` if `ptr` is not aligned to `T`
+unsafe fn view<'a, T>(ptr: *mut c_void, dims: &'a [u32], len: usize) -> Option<TensorView<'a, T>> {
+    let ptr = ptr as *const T;
+    if ptr.align_offset(std::mem::align_of::<T>()) != 0 {
+        return None;
+    }
+    Some(TensorView {
+        data: std::slice::from_raw_parts(ptr, len),
+        dims,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::ptr::null_mut;
+
+    fn layer(name: &CStr, data_type: c_uint, dims: &[u32]) -> NvDsInferLayerInfo {
+        let mut d = [0; NVDSINFER_MAX_DIMS];
+        d[..dims.len()].copy_from_slice(dims);
+        NvDsInferLayerInfo {
+            data_type,
+            infer_dims: NvDsInferDims {
+                num_dims: dims.len() as c_uint,
+                d,
+                num_elements: dims.iter().product(),
+            },
+            binding_index: 0,
+            layer_name: name.as_ptr(),
+            // a device pointer in nvinfer, never read
+            buffer: 1 as *mut c_void,
+            is_input: 0,
+        }
+    }
+
+    fn tensor_meta(
+        layers: &mut [NvDsInferLayerInfo],
+        host: &mut [*mut c_void],
+    ) -> NvDsInferTensorMeta {
+        NvDsInferTensorMeta {
+            unique_id: 1,
+            num_output_layers: layers.len() as c_uint,
+            output_layers_info: layers.as_mut_ptr(),
+            out_buf_ptrs_host: host.as_mut_ptr(),
+            out_buf_ptrs_dev: null_mut(),
+            gpu_id: 0,
+            priv_data: null_mut(),
+            network_info: NvDsInferNetworkInfo {
+                width: 640,
+                height: 480,
+                channels: 3,
+            },
+            maintain_aspect_ratio: 1,
+        }
+    }
+
+    #[test]
+    fn views_host_buffers_of_a_synthetic_batch() {
+        let names = ["boxes", "scores", "mask", "count"].map(|n| CString::new(n).unwrap());
+        // 2 boxes of [x, y, w, h], 2 fp16 scores, 2x3 int8 mask and an int32 count
+        let mut boxes = vec![0.0f32, 1.0, 2.0, 3.0, 10.0, 11.0, 12.0, 13.0];
+        let mut scores = vec![f16::from_f32(0.5), f16::from_f32(0.25)];
+        let mut mask = vec![0i8, 1, -1, 2, -2, 3];
+        let mut count = vec![2i32];
+        let mut layers = [
+            layer(&names[0], 0, &[2, 4]),
+            layer(&names[1], 1, &[2]),
+            layer(&names[2], 2, &[2, 3]),
+            layer(&names[3], 3, &[1]),
+        ];
+        let mut host = [
+            boxes.as_mut_ptr() as *mut c_void,
+            scores.as_mut_ptr() as *mut c_void,
+            mask.as_mut_ptr() as *mut c_void,
+            count.as_mut_ptr() as *mut c_void,
+        ];
+        let meta = tensor_meta(&mut layers, &mut host);
+        assert!(meta.maintain_aspect_ratio());
+        assert_eq!(meta.output_layers().len(), 4);
+
+        let boxes = match meta.layer_tensor(0).unwrap() {
+            Tensor::Fp32(t) => t,
+            t => panic!("unexpected {:?}", t),
+        };
+        assert_eq!(boxes.shape(), [2, 4]);
+        assert_eq!(boxes.get(&[1, 2]), Some(&12.0));
+        assert_eq!(boxes.get(&[2, 0]), None);
+        assert_eq!(boxes.get(&[1]), None);
+        let rows = boxes
+            .outer_iter()
+            .map(|r| r.as_slice().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, [[0.0, 1.0, 2.0, 3.0], [10.0, 11.0, 12.0, 13.0]]);
+
+        let scores = meta.tensor("scores").unwrap();
+        assert!(matches!(scores, Tensor::Fp16(_)));
+        assert_eq!(scores.to_f32_vec(), [0.5, 0.25]);
+        let mask = meta.tensor("mask").unwrap();
+        assert_eq!(mask.shape(), [2, 3]);
+        assert_eq!(mask.to_f32_vec(), [0.0, 1.0, -1.0, 2.0, -2.0, 3.0]);
+        match meta.layer_tensor(3).unwrap() {
+            Tensor::Int32(t) => assert_eq!(t.as_slice(), [2]),
+            t => panic!("unexpected {:?}", t),
+        }
+    }
+
+    #[test]
+    fn invalid_layers() {
+        let names = ["missing", "unknown", "unaligned"].map(|n| CString::new(n).unwrap());
+        let mut data = vec![0.0f32; 3];
+        let mut layers = [
+            layer(&names[0], 0, &[1]),
+            layer(&names[1], 7, &[1]),
+            layer(&names[2], 0, &[1]),
+        ];
+        let mut host = [null_mut(), data.as_mut_ptr() as *mut c_void, unsafe {
+            (data.as_mut_ptr() as *mut u8).add(1)
+        }
+            as *mut c_void];
+        let meta = tensor_meta(&mut layers, &mut host);
+        assert!(matches!(meta.layer_tensor(0), Err(Error::NoHostBuffer(n)) if n == "missing"));
+        assert!(matches!(
+            meta.layer_tensor(1),
+            Err(Error::UnsupportedDataType(n, 7)) if n == "unknown"
+        ));
+        assert!(matches!(meta.layer_tensor(2), Err(Error::UnalignedBuffer(n)) if n == "unaligned"));
+        assert!(matches!(meta.layer_tensor(3), Err(Error::NoLayer(3))));
+        assert!(matches!(meta.tensor("boxes"), Err(Error::LayerNotFound(n)) if n == "boxes"));
+
+        let mut no_host = tensor_meta(&mut layers, &mut host);
+        no_host.out_buf_ptrs_host = null_mut();
+        no_host.maintain_aspect_ratio = 0;
+        assert!(!no_host.maintain_aspect_ratio());
+        assert!(matches!(
+            no_host.layer_tensor(1),
+            Err(Error::NoHostBuffer(_))
+        ));
+    }
+}
@@ -0,0 +1,432 @@
+//! Latency measurement of nvds_latency_meta.h
+//!
+//! DeepStream records timestamps only if `NVDS_ENABLE_LATENCY_MEASUREMENT=1` and, for
+//! each component, `NVDS_ENABLE_COMPONENT_LATENCY_MEASUREMENT=1` are set before the
+//! pipeline starts. Timestamps are system time in milliseconds.
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint};
+
+use gst::glib;
+
+use crate::{imp, Error, NvDsBatchMeta, NvDsMeta};
+
+const MAX_COMPONENT_LEN: usize = 64;
+
+#[link(name = "nvdsgst_meta")]
+extern "C" {
+    fn nvds_measure_buffer_latency(
+        buf: *mut gst::ffi::GstBuffer,
+        latency_info: *mut NvDsFrameLatencyInfo,
+    ) -> c_uint;
+    fn nvds_get_enable_latency_measurement() -> glib::ffi::gboolean;
+    fn nvds_get_enable_component_latency_measurement() -> glib::ffi::gboolean;
+}
+
+/// `NVDS_ENABLE_LATENCY_MEASUREMENT` is set
+pub fn latency_measurement_enabled() -> bool {
+    unsafe { nvds_get_enable_latency_measurement() != glib::ffi::GFALSE }
+}
+
+/// `NVDS_ENABLE_COMPONENT_LATENCY_MEASUREMENT` is set
+pub fn component_latency_measurement_enabled() -> bool {
+    unsafe { nvds_get_enable_component_latency_measurement() != glib::ffi::GFALSE }
+}
+
+/// Latency of a frame from the decoder input to the measurement
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NvDsFrameLatencyInfo {
+    pub source_id: c_uint,
+    pub frame_num: c_uint,
+    pub comp_in_timestamp: f64,
+    pub latency: f64,
+}
+
+/// Input and output timestamps of a component for a frame
+#[repr(C)]
+#[derive(Debug)]
+pub struct NvDsMetaCompLatency {
+    component_name: [c_char; MAX_COMPONENT_LEN],
+    in_system_timestamp: f64,
+    out_system_timestamp: f64,
+    source_id: c_uint,
+    frame_num: c_uint,
+    pad_index: c_uint,
+}
+
+impl NvDsMetaCompLatency {
+    #[inline]
+    pub fn component_name(&self) -> &CStr {
+        let name = &self.component_name;
+        if name.contains(&0) {
+            unsafe { CStr::from_ptr(name.as_ptr()) }
+        } else {
+            Default::default()
+        }
+    }
+    #[inline]
+    pub fn in_system_timestamp(&self) -> f64 {
+        self.in_system_timestamp
+    }
+    #[inline]
+    pub fn out_system_timestamp(&self) -> f64 {
+        self.out_system_timestamp
+    }
+    /// Milliseconds spent in the component
+    #[inline]
+    pub fn latency(&self) -> f64 {
+        self.out_system_timestamp - self.in_system_timestamp
+    }
+    #[inline]
+    pub fn source_id(&self) -> u32 {
+        self.source_id
+    }
+    #[inline]
+    pub fn frame_num(&self) -> u32 {
+        self.frame_num
+    }
+    #[inline]
+    pub fn pad_index(&self) -> u32 {
+        self.pad_index
+    }
+}
+
+impl NvDsBatchMeta {
+    /// Component latencies of all frames in `NVDS_LATENCY_MEASUREMENT_META`
+    ///
+    /// Each meta holds one entry per frame of the batch.
+    pub fn component_latencies(&self) -> impl Iterator<Item = &NvDsMetaCompLatency> {
+        let frames = self.num_frames_in_batch() as usize;
+        self.user_meta_list()
+            .filter(|m| m.meta_type() == imp::NvDsMetaType_NVDS_LATENCY_MEASUREMENT_META)
+            .filter(|m| !m.0.user_meta_data.is_null())
+            .flat_map(move |m| unsafe {
+                std::slice::from_raw_parts(m.0.user_meta_data as *const NvDsMetaCompLatency, frames)
+            })
+    }
+}
+
+/// Measure latencies of frames in the buffer by `nvds_measure_buffer_latency`
+///
+/// Returns no frames if the latency measurement is not enabled.
+pub fn measure_buffer_latency(buffer: &gst::BufferRef) -> Result<Vec<NvDsFrameLatencyInfo>, Error> {
+    if !latency_measurement_enabled() {
+        return Ok(vec![]);
+    }
+    let meta = NvDsMeta::from_buffer(buffer)?;
+    let max_frames = meta.batch_meta()?.max_frames_in_batch() as usize;
+    let mut info = vec![NvDsFrameLatencyInfo::default(); max_frames];
+    // nvds_measure_buffer_latency only reads the meta of the buffer
+    let n = unsafe {
+        nvds_measure_buffer_latency(
+            buffer.as_ptr() as *mut gst::ffi::GstBuffer,
+            info.as_mut_ptr(),
+        )
+    };
+    info.truncate((n as usize).min(max_frames));
+    Ok(info)
+}
+
+/// Percentiles of latencies in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub mean: f64,
+    pub p50: f64,
+    pub p95: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl LatencyStats {
+    /// Nearest-rank percentiles of the samples, `None` if empty
+    pub fn from_samples(samples: &mut [f64]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(f64::total_cmp);
+        let n = samples.len();
+        let rank = |p: f64| samples[((p * n as f64).ceil() as usize).clamp(1, n) - 1];
+        Some(Self {
+            count: n,
+            mean: samples.iter().sum::<f64>() / n as f64,
+            p50: rank(0.50),
+            p95: rank(0.95),
+            p99: rank(0.99),
+            max: samples[n - 1],
+        })
+    }
+}
+
+/// Latencies of a source over an interval
+#[derive(Debug, Clone, Default)]
+pub struct SourceLatency {
+    /// from the decoder input to the measurement point
+    pub frame: Option<LatencyStats>,
+    /// by component name
+    pub components: BTreeMap<String, LatencyStats>,
+}
+
+/// Collect latencies of buffers by source and component
+///
+/// Call [`LatencyReporter::push`] from a pad probe downstream of the measured elements
+/// and [`LatencyReporter::report`] at each reporting interval.
+#[derive(Debug, Default)]
+pub struct LatencyReporter {
+    frames: BTreeMap<u32, Vec<f64>>,
+    components: BTreeMap<(u32, String), Vec<f64>>,
+}
+
+impl LatencyReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, buffer: &gst::BufferRef) -> Result<(), Error> {
+        self.push_frames(&measure_buffer_latency(buffer)?);
+        if component_latency_measurement_enabled() {
+            let meta = NvDsMeta::from_buffer(buffer)?;
+            self.push_components(meta.batch_meta()?.component_latencies());
+        }
+        Ok(())
+    }
+
+    fn push_frames(&mut self, frames: &[NvDsFrameLatencyInfo]) {
+        for info in frames {
+            self.frames
+                .entry(info.source_id)
+                .or_default()
+                .push(info.latency);
+        }
+    }
+
+    fn push_components<'a>(&mut self, components: impl Iterator<Item = &'a NvDsMetaCompLatency>) {
+        for c in components {
+            let name = c.component_name().to_string_lossy().into_owned();
+            self.components
+                .entry((c.source_id(), name))
+                .or_default()
+                .push(c.latency());
+        }
+    }
+
+    /// Statistics by source id since the last report
+    pub fn report(&mut self) -> BTreeMap<u32, SourceLatency> {
+        let mut report = BTreeMap::<u32, SourceLatency>::new();
+        for (source_id, mut samples) in std::mem::take(&mut self.frames) {
+            report.entry(source_id).or_default().frame = LatencyStats::from_samples(&mut samples);
+        }
+        for ((source_id, name), mut samples) in std::mem::take(&mut self.components) {
+            if let Some(stats) = LatencyStats::from_samples(&mut samples) {
+                report
+                    .entry(source_id)
+                    .or_default()
+                    .components
+                    .insert(name, stats);
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Metadata which nvdsgst_meta attaches to a batch going through components
+    ///
+    /// Each component adds a `NVDS_LATENCY_MEASUREMENT_META` with one entry per frame,
+    /// and components run one after another starting at the decoder input time.
+    struct MockBatch {
+        batch: Box<imp::NvDsBatchMeta>,
+        _user_metas: Vec<imp::NvDsUserMeta>,
+        _list: Vec<glib::ffi::GList>,
+        _entries: Vec<Vec<NvDsMetaCompLatency>>,
+        frames: Vec<NvDsFrameLatencyInfo>,
+    }
+
+    impl MockBatch {
+        /// `frames` of `(source_id, frame_num, decoder input time)` through components
+        /// of `(name, milliseconds by frame)`
+        fn new(frames: &[(u32, u32, f64)], components: &[(&str, &[f64])]) -> Self {
+            let mut now = frames.iter().map(|f| f.2).collect::<Vec<_>>();
+            let mut entries = vec![];
+            for (name, durations) in components {
+                let mut component_name = [0; MAX_COMPONENT_LEN];
+                for (d, &c) in component_name.iter_mut().zip(name.as_bytes()) {
+                    *d = c as c_char;
+                }
+                let metas = frames
+                    .iter()
+                    .zip(now.iter_mut())
+                    .zip(durations.iter())
+                    .enumerate()
+                    .map(|(pad_index, ((&(source_id, frame_num, _), now), d))| {
+                        let in_system_timestamp = *now;
+                        *now += d;
+                        NvDsMetaCompLatency {
+                            component_name,
+                            in_system_timestamp,
+                            out_system_timestamp: *now,
+                            source_id,
+                            frame_num,
+                            pad_index: pad_index as c_uint,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                entries.push(metas);
+            }
+            // nvds_measure_buffer_latency measures from the decoder input to now
+            let frames = frames
+                .iter()
+                .zip(now.iter())
+                .map(
+                    |(&(source_id, frame_num, start), now)| NvDsFrameLatencyInfo {
+                        source_id,
+                        frame_num,
+                        comp_in_timestamp: start,
+                        latency: now - start,
+                    },
+                )
+                .collect();
+            let mut user_metas = entries
+                .iter_mut()
+                .map(|e| unsafe {
+                    let mut m: imp::NvDsUserMeta = std::mem::zeroed();
+                    m.base_meta.meta_type = imp::NvDsMetaType_NVDS_LATENCY_MEASUREMENT_META;
+                    m.user_meta_data = e.as_mut_ptr() as glib::ffi::gpointer;
+                    m
+                })
+                .collect::<Vec<_>>();
+            let mut list = user_metas
+                .iter_mut()
+                .map(|m| glib::ffi::GList {
+                    data: m as *mut imp::NvDsUserMeta as glib::ffi::gpointer,
+                    next: std::ptr::null_mut(),
+                    prev: std::ptr::null_mut(),
+                })
+                .collect::<Vec<_>>();
+            for i in 1..list.len() {
+                let next = &mut list[i] as *mut glib::ffi::GList;
+                list[i - 1].next = next;
+            }
+            let mut batch: Box<imp::NvDsBatchMeta> = Box::new(unsafe { std::mem::zeroed() });
+            batch.num_frames_in_batch = entries.first().map_or(0, |e| e.len() as c_uint);
+            batch.max_frames_in_batch = batch.num_frames_in_batch;
+            batch.batch_user_meta_list = list
+                .first_mut()
+                .map_or(std::ptr::null_mut(), |l| l as *mut glib::ffi::GList as _);
+            Self {
+                batch,
+                _user_metas: user_metas,
+                _list: list,
+                _entries: entries,
+                frames,
+            }
+        }
+
+        fn batch_meta(&self) -> &NvDsBatchMeta {
+            unsafe { &*(&*self.batch as *const imp::NvDsBatchMeta as *const NvDsBatchMeta) }
+        }
+
+        fn push_to(&self, reporter: &mut LatencyReporter) {
+            reporter.push_frames(&self.frames);
+            reporter.push_components(self.batch_meta().component_latencies());
+        }
+    }
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let mut samples = (1..=10).rev().map(|v| v as f64 * 10.0).collect::<Vec<_>>();
+        let stats = LatencyStats::from_samples(&mut samples).unwrap();
+        // ranks ceil(0.5 * 10) = 5, ceil(0.95 * 10) = 10 and ceil(0.99 * 10) = 10
+        assert_eq!(
+            stats,
+            LatencyStats {
+                count: 10,
+                mean: 55.0,
+                p50: 50.0,
+                p95: 100.0,
+                p99: 100.0,
+                max: 100.0,
+            }
+        );
+        let one = LatencyStats::from_samples(&mut [3.0]).unwrap();
+        assert_eq!((one.p50, one.p99, one.max), (3.0, 3.0, 3.0));
+        assert!(LatencyStats::from_samples(&mut []).is_none());
+    }
+
+    #[test]
+    fn component_latencies_of_a_batch() {
+        let batch = MockBatch::new(
+            &[(0, 10, 1000.0), (1, 20, 1002.0)],
+            &[("nvv4l2decoder0", &[5.0, 6.0]), ("nvinfer0", &[20.0, 22.0])],
+        );
+        let latencies = batch
+            .batch_meta()
+            .component_latencies()
+            .map(|c| {
+                (
+                    c.component_name().to_str().unwrap(),
+                    c.source_id(),
+                    c.frame_num(),
+                    c.pad_index(),
+                    c.in_system_timestamp(),
+                    c.latency(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            latencies,
+            [
+                ("nvv4l2decoder0", 0, 10, 0, 1000.0, 5.0),
+                ("nvv4l2decoder0", 1, 20, 1, 1002.0, 6.0),
+                ("nvinfer0", 0, 10, 0, 1005.0, 20.0),
+                ("nvinfer0", 1, 20, 1, 1008.0, 22.0),
+            ]
+        );
+        assert_eq!(batch.frames[1].latency, 28.0);
+    }
+
+    #[test]
+    fn report_by_source_and_component() {
+        let mut reporter = LatencyReporter::new();
+        // source 0 runs nvinfer in 10, 20, 30 and 40 ms, source 1 in 50 ms once
+        for (i, d) in [10.0, 20.0, 30.0, 40.0].into_iter().enumerate() {
+            let start = 1000.0 * i as f64;
+            let frames = if i == 0 {
+                vec![(0, 0, start), (1, 0, start)]
+            } else {
+                vec![(0, i as u32, start)]
+            };
+            let infer = [d, 50.0];
+            let components: &[(&str, &[f64])] = &[
+                ("decoder", &[2.0, 2.0]),
+                ("nvinfer0", &infer[..frames.len()]),
+            ];
+            MockBatch::new(&frames, components).push_to(&mut reporter);
+        }
+        let report = reporter.report();
+        assert_eq!(report.keys().copied().collect::<Vec<_>>(), [0, 1]);
+
+        let s0 = &report[&0];
+        let frame = s0.frame.unwrap();
+        assert_eq!((frame.count, frame.mean), (4, 27.0));
+        assert_eq!((frame.p50, frame.p95, frame.max), (22.0, 42.0, 42.0));
+        assert_eq!(
+            s0.components.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["decoder", "nvinfer0"]
+        );
+        let infer = s0.components["nvinfer0"];
+        assert_eq!((infer.count, infer.mean, infer.p50), (4, 25.0, 20.0));
+        assert_eq!(s0.components["decoder"].max, 2.0);
+
+        let s1 = &report[&1];
+        assert_eq!(s1.frame.unwrap().max, 52.0);
+        assert_eq!(s1.components["nvinfer0"].count, 1);
+
+        // samples are cleared by the report
+        assert!(reporter.report().is_empty());
+    }
+}
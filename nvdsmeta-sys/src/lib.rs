@@ -1,8 +1,36 @@
 use gst::{glib, prelude::*, ClockTime};
 use std::{ffi::CStr, fmt};
 
+mod audio;
+mod error;
+mod event_msg;
 mod imp;
+mod infer;
+pub mod latency;
 pub mod nvlist;
+mod optical_flow;
+mod payload;
+mod preprocess;
+mod segmentation;
+mod tracker;
+
+pub use audio::NvDsAudioFrameMeta;
+pub use error::Error;
+pub use event_msg::{
+    EventMsgBuilder, EventMsgExt, EventType, NvDsEventMsgMeta, ObjectType, PersonObject,
+    VehicleObject,
+};
+pub use infer::{
+    f16, NvDsInferDataType, NvDsInferLayerInfo, NvDsInferNetworkInfo, NvDsInferTensorMeta, Tensor,
+    TensorView,
+};
+pub use optical_flow::{MotionGrid, NvDsOpticalFlowMeta, NvOFFlowVector};
+pub use payload::NvDsPayload;
+pub use preprocess::{NvDsPreProcessBatchMeta, NvDsPreProcessTensorMeta, NvDsRoiMeta};
+pub use segmentation::{NvDsInferSegmentationMeta, BACKGROUND_CLASS};
+pub use tracker::{
+    NvDsPastFrameObj, NvDsPastFrameObjBatch, NvDsPastFrameObjList, NvDsPastFrameObjStream,
+};
 
 #[link(name = "nvdsgst_meta")]
 extern "C" {
@@ -27,6 +55,12 @@ mod nvgst {
         gst_to_nvds_meta_release_func: glib::ffi::gpointer,
     }
 }
+/// DeepStream metadata attached to a buffer
+///
+/// Every view obtained from it borrows the [`gst::MetaRef`] and therefore the buffer,
+/// so references to batch, frame or object metadata cannot outlive the buffer, and
+/// mutable access requires a writable buffer borrowed exclusively, so it cannot coexist
+/// with shared views. `tests/compile_fail` checks both.
 #[repr(transparent)]
 pub struct NvDsMeta(nvgst::NvDsMeta);
 
@@ -47,6 +81,32 @@ impl NvDsMeta {
             None
         }
     }
+
+    /// Get NvDsMeta attached to the buffer
+    pub fn from_buffer(buffer: &gst::BufferRef) -> Result<gst::MetaRef<'_, Self>, Error> {
+        buffer.meta::<Self>().ok_or(Error::NoMeta)
+    }
+
+    /// Same as [`NvDsMeta::get_batch_meta`] but reports the actual meta type
+    pub fn batch_meta(&self) -> Result<&NvDsBatchMeta, Error> {
+        self.get_batch_meta()
+            .ok_or_else(|| Error::NotBatchMeta(self.meta_type()))
+    }
+
+    /// Get NvDsMeta of a writable buffer to add metadata
+    pub fn from_buffer_mut(
+        buffer: &mut gst::BufferRef,
+    ) -> Result<gst::MetaRefMut<'_, Self, gst::meta::Standalone>, Error> {
+        buffer.meta_mut::<Self>().ok_or(Error::NoMeta)
+    }
+
+    pub fn batch_meta_mut(&mut self) -> Result<&mut NvDsBatchMeta, Error> {
+        if self.meta_type() == nvgst::NvDsMetaType_NVDS_GST_BATCH_META {
+            unsafe { Ok(&mut *(self.0.meta_data as *mut NvDsBatchMeta)) }
+        } else {
+            Err(Error::NotBatchMeta(self.meta_type()))
+        }
+    }
 }
 
 unsafe impl MetaAPI for NvDsMeta {
@@ -66,11 +126,22 @@ impl fmt::Debug for NvDsMeta {
 }
 
 pub use imp::NvBbox_Coords;
+
+/// `object_id` of objects not tracked by nvtracker
+///
+/// bindgen generates `imp::UNTRACKED_OBJECT_ID` as `-1i32`, so define it as `u64` here.
+pub const UNTRACKED_OBJECT_ID: u64 = u64::MAX;
+
 #[repr(transparent)]
 #[derive(Debug)]
 pub struct NvDsObjectMeta(imp::NvDsObjectMeta);
 
 impl NvDsObjectMeta {
+    /// unique id of the component (gie-unique-id) which detected the object
+    #[inline]
+    pub fn unique_component_id(&self) -> i32 {
+        self.0.unique_component_id
+    }
     #[inline]
     pub fn class_id(&self) -> i32 {
         self.0.class_id
@@ -99,6 +170,116 @@ impl NvDsObjectMeta {
     pub fn tracker_bbox(&self) -> &NvBbox_Coords {
         &self.0.tracker_bbox_info.org_bbox_coords
     }
+    /// results of secondary classifiers operated on the object
+    pub fn classifier_meta_list(&self) -> nvlist::GListIter<'_, NvDsClassifierMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(
+                self.0.classifier_meta_list as *const glib::ffi::GList,
+            )
+        }
+    }
+    pub fn user_meta_list(&self) -> nvlist::GListIter<'_, NvDsUserMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(self.0.obj_user_meta_list as *const glib::ffi::GList)
+        }
+    }
+    /// Tensor output of the object by secondary nvinfer
+    pub fn tensor_meta(&self) -> impl Iterator<Item = &NvDsInferTensorMeta> {
+        self.user_meta_list().filter_map(NvDsUserMeta::tensor_meta)
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct NvDsClassifierMeta(imp::NvDsClassifierMeta);
+
+impl NvDsClassifierMeta {
+    #[inline]
+    pub fn num_labels(&self) -> u32 {
+        self.0.num_labels
+    }
+    /// gie-unique-id of the classifier
+    #[inline]
+    pub fn unique_component_id(&self) -> i32 {
+        self.0.unique_component_id
+    }
+    #[inline]
+    pub fn classifier_type(&self) -> Option<&CStr> {
+        if self.0.classifier_type.is_null() {
+            None
+        } else {
+            unsafe { Some(CStr::from_ptr(self.0.classifier_type)) }
+        }
+    }
+    pub fn label_info_list(&self) -> nvlist::GListIter<'_, NvDsLabelInfo> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(self.0.label_info_list as *const glib::ffi::GList)
+        }
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct NvDsLabelInfo(imp::NvDsLabelInfo);
+
+impl NvDsLabelInfo {
+    #[inline]
+    pub fn num_classes(&self) -> u32 {
+        self.0.num_classes
+    }
+    /// label of the best result
+    #[inline]
+    pub fn result_label(&self) -> &CStr {
+        // long label exceeding MAX_LABEL_SIZE is stored in pResult_label
+        if self.0.pResult_label.is_null() {
+            unsafe { CStr::from_ptr(&self.0.result_label as *const std::os::raw::c_char) }
+        } else {
+            unsafe { CStr::from_ptr(self.0.pResult_label) }
+        }
+    }
+    #[inline]
+    pub fn result_class_id(&self) -> u32 {
+        self.0.result_class_id
+    }
+    #[inline]
+    pub fn label_id(&self) -> u32 {
+        self.0.label_id
+    }
+    #[inline]
+    pub fn result_prob(&self) -> f32 {
+        self.0.result_prob
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct NvDsUserMeta(imp::NvDsUserMeta);
+
+impl NvDsUserMeta {
+    #[inline]
+    pub fn meta_type(&self) -> imp::NvDsMetaType {
+        self.0.base_meta.meta_type
+    }
+    /// `NVDSINFER_TENSOR_OUTPUT_META` attached by nvinfer with `output-tensor-meta=1`
+    pub fn tensor_meta(&self) -> Option<&NvDsInferTensorMeta> {
+        if self.meta_type() == imp::NvDsMetaType_NVDSINFER_TENSOR_OUTPUT_META
+            && !self.0.user_meta_data.is_null()
+        {
+            unsafe { Some(&*(self.0.user_meta_data as *const NvDsInferTensorMeta)) }
+        } else {
+            None
+        }
+    }
+    /// `NVDSINFER_SEGMENTATION_META` attached by nvinfer of segmentation networks
+    pub fn segmentation_meta(&self) -> Option<&NvDsInferSegmentationMeta> {
+        if self.meta_type() == imp::NvDsMetaType_NVDSINFER_SEGMENTATION_META
+            && !self.0.user_meta_data.is_null()
+        {
+            unsafe { Some(&*(self.0.user_meta_data as *const NvDsInferSegmentationMeta)) }
+        } else {
+            None
+        }
+    }
 }
 
 #[repr(transparent)]
@@ -106,8 +287,26 @@ impl NvDsObjectMeta {
 pub struct NvDsFrameMeta(imp::NvDsFrameMeta);
 
 impl NvDsFrameMeta {
-    pub fn object_meta_list(&self) -> nvlist::GListIter<NvDsObjectMeta> {
-        nvlist::GListIter::from_glib_full(self.0.obj_meta_list as *mut glib::ffi::GList)
+    pub fn object_meta_list(&self) -> nvlist::GListIter<'_, NvDsObjectMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(self.0.obj_meta_list as *const glib::ffi::GList)
+        }
+    }
+    pub fn user_meta_list(&self) -> nvlist::GListIter<'_, NvDsUserMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(
+                self.0.frame_user_meta_list as *const glib::ffi::GList,
+            )
+        }
+    }
+    /// Tensor output of the frame by full-frame nvinfer
+    pub fn tensor_meta(&self) -> impl Iterator<Item = &NvDsInferTensorMeta> {
+        self.user_meta_list().filter_map(NvDsUserMeta::tensor_meta)
+    }
+    /// Segmentation output of the frame by full-frame nvinfer
+    pub fn segmentation_meta(&self) -> impl Iterator<Item = &NvDsInferSegmentationMeta> {
+        self.user_meta_list()
+            .filter_map(NvDsUserMeta::segmentation_meta)
     }
     #[inline]
     pub fn source_id(&self) -> u32 {
@@ -117,9 +316,14 @@ impl NvDsFrameMeta {
     pub fn frame_num(&self) -> i32 {
         self.0.frame_num
     }
+    /// `None` if the source did not set PTS of the frame
     #[inline]
-    pub fn buf_pts(&self) -> ClockTime {
-        ClockTime::from_nseconds(self.0.buf_pts)
+    pub fn buf_pts(&self) -> Option<ClockTime> {
+        if self.0.buf_pts == gst::ffi::GST_CLOCK_TIME_NONE {
+            None
+        } else {
+            Some(ClockTime::from_nseconds(self.0.buf_pts))
+        }
     }
     #[inline]
     pub fn ntp_timestamp(&self) -> u64 {
@@ -139,6 +343,28 @@ impl NvDsFrameMeta {
     }
 }
 
+/// Entry of `frame_meta_list` dispatched by `base_meta.meta_type`
+#[derive(Debug, Clone, Copy)]
+pub enum FrameMeta<'a> {
+    Video(&'a NvDsFrameMeta),
+    Audio(&'a NvDsAudioFrameMeta),
+}
+
+impl<'a> FrameMeta<'a> {
+    pub fn video(self) -> Option<&'a NvDsFrameMeta> {
+        match self {
+            FrameMeta::Video(f) => Some(f),
+            FrameMeta::Audio(_) => None,
+        }
+    }
+    pub fn audio(self) -> Option<&'a NvDsAudioFrameMeta> {
+        match self {
+            FrameMeta::Video(_) => None,
+            FrameMeta::Audio(f) => Some(f),
+        }
+    }
+}
+
 #[repr(transparent)]
 pub struct NvDsBatchMeta(imp::NvDsBatchMeta);
 
@@ -149,8 +375,63 @@ impl NvDsBatchMeta {
     pub fn num_frames_in_batch(&self) -> u32 {
         self.0.num_frames_in_batch
     }
-    pub fn frame_meta_list(&self) -> nvlist::GListIter<NvDsFrameMeta> {
-        nvlist::GListIter::from_glib_full(self.0.frame_meta_list as *mut glib::ffi::GList)
+    /// The batch is made of audio frames
+    #[inline]
+    pub fn is_audio(&self) -> bool {
+        self.0.base_meta.meta_type == imp::NvDsMetaType_NVDS_AUDIO_BATCH_META
+    }
+    /// Video and audio frames of the batch, entries of other meta types are skipped
+    pub fn frames(&self) -> impl Iterator<Item = FrameMeta<'_>> {
+        let list: nvlist::GListIter<'_, imp::NvDsBaseMeta> = unsafe {
+            nvlist::GListIter::from_glib_none(self.0.frame_meta_list as *const glib::ffi::GList)
+        };
+        // every frame meta starts with NvDsBaseMeta
+        list.filter_map(|base| {
+            let ptr = base as *const imp::NvDsBaseMeta;
+            unsafe {
+                match base.meta_type {
+                    imp::NvDsMetaType_NVDS_FRAME_META => {
+                        Some(FrameMeta::Video(&*(ptr as *const NvDsFrameMeta)))
+                    }
+                    imp::NvDsMetaType_NVDS_AUDIO_FRAME_META => {
+                        Some(FrameMeta::Audio(&*(ptr as *const NvDsAudioFrameMeta)))
+                    }
+                    _ => None,
+                }
+            }
+        })
+    }
+    /// Video frames of the batch
+    pub fn frame_meta_list(&self) -> impl Iterator<Item = &NvDsFrameMeta> {
+        self.frames().filter_map(FrameMeta::video)
+    }
+    /// Audio frames of the batch
+    pub fn audio_frame_meta_list(&self) -> impl Iterator<Item = &NvDsAudioFrameMeta> {
+        self.frames().filter_map(FrameMeta::audio)
+    }
+    pub fn user_meta_list(&self) -> nvlist::GListIter<'_, NvDsUserMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(
+                self.0.batch_user_meta_list as *const glib::ffi::GList,
+            )
+        }
+    }
+    /// Video frame of the source to attach user meta to
+    fn video_frame_ptr(&mut self, source_id: u32) -> Result<*mut imp::NvDsFrameMeta, Error> {
+        unsafe {
+            let mut list = self.0.frame_meta_list;
+            while !list.is_null() {
+                let frame = (*list).data as *mut imp::NvDsFrameMeta;
+                // audio batches hold NvDsAudioFrameMeta
+                if (*frame).base_meta.meta_type == imp::NvDsMetaType_NVDS_FRAME_META
+                    && (*frame).source_id == source_id
+                {
+                    return Ok(frame);
+                }
+                list = (*list).next;
+            }
+        }
+        Err(Error::FrameNotFound(source_id))
     }
 }
 
@@ -1,37 +1,41 @@
+//! Iterators over GList owned by DeepStream metadata
+//!
+//! Lists are only read, never modified, and items are borrowed for the lifetime
+//! of the metadata which owns the list.
 use gst::glib;
 use std::marker::PhantomData;
 
-pub struct GList {
+pub struct GList<'a> {
     ptr: Option<std::ptr::NonNull<glib::ffi::GList>>,
+    phantom: PhantomData<&'a glib::ffi::GList>,
 }
 
-impl Iterator for GList {
+impl<'a> Iterator for GList<'a> {
     type Item = std::ptr::NonNull<glib::ffi::GList>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.ptr {
-            None => None,
-            Some(cur) => unsafe {
-                self.ptr = std::ptr::NonNull::new(cur.as_ref().next);
-                if let Some(mut next) = self.ptr {
-                    next.as_mut().prev = std::ptr::null_mut();
-                }
-                Some(cur)
-            },
+        let cur = self.ptr?;
+        unsafe {
+            self.ptr = std::ptr::NonNull::new(cur.as_ref().next);
         }
+        Some(cur)
     }
 }
 
-impl GList {
-    pub fn from_glib_full(list: *mut glib::ffi::GList) -> GList {
+impl<'a> GList<'a> {
+    /// # Safety
+    ///
+    /// `list` must be null or a valid GList which is not modified during `'a`.
+    pub unsafe fn from_glib_none(list: *const glib::ffi::GList) -> GList<'a> {
         GList {
-            ptr: std::ptr::NonNull::new(list),
+            ptr: std::ptr::NonNull::new(list as *mut _),
+            phantom: PhantomData,
         }
     }
 }
 
 pub struct GListIter<'a, T> {
-    ptr: Option<std::ptr::NonNull<glib::ffi::GList>>,
+    list: GList<'a>,
     phantom: PhantomData<&'a T>,
 }
 
@@ -39,24 +43,20 @@ impl<'a, T> Iterator for GListIter<'a, T> {
     type Item = &'a T;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.ptr {
-            None => None,
-            Some(cur) => unsafe {
-                self.ptr = std::ptr::NonNull::new(cur.as_ref().next);
-                if let Some(mut next) = self.ptr {
-                    next.as_mut().prev = std::ptr::null_mut();
-                }
-                let item = &*(cur.as_ref().data as *const T);
-                Some(item)
-            },
-        }
+        let cur = self.list.next()?;
+        unsafe { Some(&*(cur.as_ref().data as *const T)) }
     }
 }
 
 impl<'a, T> GListIter<'a, T> {
-    pub(crate) fn from_glib_full(list: *mut glib::ffi::GList) -> GListIter<'a, T> {
+    /// # Safety
+    ///
+    /// `list` must be null or a valid GList of `T` and both the list and the items
+    /// must outlive `'a` without modification. Callers bind `'a` to the borrow of the
+    /// metadata owning the list.
+    pub(crate) unsafe fn from_glib_none(list: *const glib::ffi::GList) -> GListIter<'a, T> {
         GListIter {
-            ptr: std::ptr::NonNull::new(list),
+            list: GList::from_glib_none(list),
             phantom: PhantomData,
         }
     }
@@ -0,0 +1,308 @@
+//! Motion vectors attached by nvof as `NVDS_OPTICAL_FLOW_META`
+//!
+//! `NvDsOpticalFlowMeta` of nvds_opticalflow_meta.h, which is not included in the
+//! bindings. Each vector covers a block of the frame given to nvof, which is the
+//! nvstreammux resolution, so grids are mapped to frames by their width and height.
+use std::os::raw::{c_uint, c_void};
+
+use crate::{imp, NvBbox_Coords, NvDsFrameMeta, NvDsUserMeta};
+
+/// Motion of a block in S10.5 fixed point
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NvOFFlowVector {
+    pub flowx: i16,
+    pub flowy: i16,
+}
+
+impl NvOFFlowVector {
+    /// Horizontal motion in pixels
+    #[inline]
+    pub fn dx(&self) -> f32 {
+        self.flowx as f32 / 32.0
+    }
+    /// Vertical motion in pixels
+    #[inline]
+    pub fn dy(&self) -> f32 {
+        self.flowy as f32 / 32.0
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsOpticalFlowMeta {
+    rows: c_uint,
+    cols: c_uint,
+    mv_size: c_uint,
+    frame_num: u64,
+    data: *mut c_void,
+    priv_data: *mut c_void,
+    reserved: *mut c_void,
+}
+
+impl NvDsOpticalFlowMeta {
+    #[inline]
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+    #[inline]
+    pub fn cols(&self) -> u32 {
+        self.cols
+    }
+    /// Size of the motion vector buffer in bytes
+    #[inline]
+    pub fn mv_size(&self) -> u32 {
+        self.mv_size
+    }
+    #[inline]
+    pub fn frame_num(&self) -> u64 {
+        self.frame_num
+    }
+
+    /// Motion vectors, `None` if the buffer is missing or smaller than the grid
+    pub fn motion_vectors(&self) -> Option<MotionGrid<'_>> {
+        let len = self.rows as usize * self.cols as usize;
+        if self.data.is_null() || (self.mv_size as usize) < len * 4 {
+            return None;
+        }
+        let vectors =
+            unsafe { std::slice::from_raw_parts(self.data as *const NvOFFlowVector, len) };
+        MotionGrid::new(self.rows, self.cols, vectors)
+    }
+}
+
+impl NvDsUserMeta {
+    pub fn optical_flow_meta(&self) -> Option<&NvDsOpticalFlowMeta> {
+        if self.meta_type() == imp::NvDsMetaType_NVDS_OPTICAL_FLOW_META
+            && !self.0.user_meta_data.is_null()
+        {
+            unsafe { Some(&*(self.0.user_meta_data as *const NvDsOpticalFlowMeta)) }
+        } else {
+            None
+        }
+    }
+}
+
+impl NvDsFrameMeta {
+    /// Optical flow of the frame by nvof
+    pub fn optical_flow_meta(&self) -> Option<&NvDsOpticalFlowMeta> {
+        self.user_meta_list()
+            .find_map(NvDsUserMeta::optical_flow_meta)
+    }
+}
+
+/// Motion vectors in row-major order
+#[derive(Debug, Clone, Copy)]
+pub struct MotionGrid<'a> {
+    rows: u32,
+    cols: u32,
+    vectors: &'a [NvOFFlowVector],
+}
+
+impl<'a> MotionGrid<'a> {
+    /// `None` if `vectors` does not hold `rows * cols` entries
+    pub fn new(rows: u32, cols: u32, vectors: &'a [NvOFFlowVector]) -> Option<Self> {
+        if vectors.len() == rows as usize * cols as usize {
+            Some(Self {
+                rows,
+                cols,
+                vectors,
+            })
+        } else {
+            None
+        }
+    }
+    #[inline]
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+    #[inline]
+    pub fn cols(&self) -> u32 {
+        self.cols
+    }
+    #[inline]
+    pub fn vectors(&self) -> &'a [NvOFFlowVector] {
+        self.vectors
+    }
+    #[inline]
+    pub fn get(&self, row: u32, col: u32) -> Option<NvOFFlowVector> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+        self.vectors
+            .get(row as usize * self.cols as usize + col as usize)
+            .copied()
+    }
+    /// Rows of [`MotionGrid::vectors`]
+    pub fn grid_rows(&self) -> std::slice::ChunksExact<'a, NvOFFlowVector> {
+        self.vectors.chunks_exact((self.cols as usize).max(1))
+    }
+
+    /// Average motion in pixels of blocks whose centers are inside the bbox
+    ///
+    /// `width` and `height` are of the frame the bbox belongs to. `None` if no block
+    /// center is inside.
+    pub fn average_motion(
+        &self,
+        bbox: &NvBbox_Coords,
+        width: u32,
+        height: u32,
+    ) -> Option<(f32, f32)> {
+        if self.rows == 0 || self.cols == 0 || width == 0 || height == 0 {
+            return None;
+        }
+        let block_w = width as f32 / self.cols as f32;
+        let block_h = height as f32 / self.rows as f32;
+        // blocks with centers in [left, right) and [top, bottom)
+        let first = |start: f32, block: f32| ((start / block - 0.5).ceil().max(0.0)) as u32;
+        let last =
+            |end: f32, block: f32, n: u32| ((end / block - 0.5).ceil().max(0.0) as u32).min(n);
+        let (col0, col1) = (
+            first(bbox.left, block_w),
+            last(bbox.left + bbox.width, block_w, self.cols),
+        );
+        let (row0, row1) = (
+            first(bbox.top, block_h),
+            last(bbox.top + bbox.height, block_h, self.rows),
+        );
+        let (mut sx, mut sy, mut n) = (0.0, 0.0, 0usize);
+        for row in self.grid_rows().take(row1 as usize).skip(row0 as usize) {
+            for v in row.iter().take(col1 as usize).skip(col0 as usize) {
+                sx += v.dx();
+                sy += v.dy();
+                n += 1;
+            }
+        }
+        if n == 0 {
+            None
+        } else {
+            Some((sx / n as f32, sy / n as f32))
+        }
+    }
+
+    /// Length in pixels of [`MotionGrid::average_motion`], small for static objects
+    pub fn average_speed(&self, bbox: &NvBbox_Coords, width: u32, height: u32) -> Option<f32> {
+        self.average_motion(bbox, width, height)
+            .map(|(dx, dy)| dx.hypot(dy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(left: f32, top: f32, width: f32, height: f32) -> NvBbox_Coords {
+        NvBbox_Coords {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    /// 2 x 4 blocks of 16 x 16 pixels on a 64 x 32 frame, block centers are at
+    /// x = 8, 24, 40, 56 and y = 8, 24. Column c moves c + 1 pixels right and row r
+    /// moves r / 2 pixels up.
+    fn vectors() -> Vec<NvOFFlowVector> {
+        (0..2)
+            .flat_map(|row| {
+                (0..4).map(move |col| NvOFFlowVector {
+                    flowx: 32 * (col + 1),
+                    flowy: -16 * row,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixed_point_vectors() {
+        let v = NvOFFlowVector {
+            flowx: -48,
+            flowy: 1,
+        };
+        assert_eq!(v.dx(), -1.5);
+        assert_eq!(v.dy(), 1.0 / 32.0);
+    }
+
+    #[test]
+    fn grid_layout() {
+        let vectors = vectors();
+        assert!(MotionGrid::new(2, 3, &vectors).is_none());
+        let grid = MotionGrid::new(2, 4, &vectors).unwrap();
+        assert_eq!(
+            grid.get(1, 2),
+            Some(NvOFFlowVector {
+                flowx: 96,
+                flowy: -16
+            })
+        );
+        assert_eq!(grid.get(2, 0), None);
+        assert_eq!(grid.get(0, 4), None);
+        assert_eq!(grid.grid_rows().count(), 2);
+    }
+
+    #[test]
+    fn average_motion_of_block_centers() {
+        let vectors = vectors();
+        let grid = MotionGrid::new(2, 4, &vectors).unwrap();
+        let motion = |b: NvBbox_Coords| grid.average_motion(&b, 64, 32);
+        // dx (1 + 2 + 3 + 4) / 4, dy (0 - 0.5) / 2
+        assert_eq!(motion(bbox(0.0, 0.0, 64.0, 32.0)), Some((2.5, -0.25)));
+        // the center at the left and top edges is inside, at the right and bottom is not
+        assert_eq!(motion(bbox(8.0, 8.0, 16.0, 16.0)), Some((1.0, 0.0)));
+        // x in [20, 50) has the centers of columns 1 and 2, y in [10, 30) of row 1
+        assert_eq!(motion(bbox(20.0, 10.0, 30.0, 20.0)), Some((2.5, -0.5)));
+        // a bbox over the frame edges is clipped to column 3 and both rows
+        assert_eq!(motion(bbox(50.0, -10.0, 100.0, 100.0)), Some((4.0, -0.25)));
+        // no center in x [9, 24)
+        assert_eq!(motion(bbox(9.0, 0.0, 15.0, 32.0)), None);
+        assert_eq!(motion(bbox(0.0, 0.0, 0.0, 0.0)), None);
+        assert_eq!(
+            grid.average_motion(&bbox(0.0, 0.0, 64.0, 32.0), 0, 32),
+            None
+        );
+        let empty = MotionGrid::new(0, 4, &[]).unwrap();
+        assert_eq!(
+            empty.average_motion(&bbox(0.0, 0.0, 64.0, 32.0), 64, 32),
+            None
+        );
+    }
+
+    #[test]
+    fn average_speed_is_the_length() {
+        let vectors = [NvOFFlowVector {
+            flowx: 3 * 32,
+            flowy: -4 * 32,
+        }; 4];
+        let grid = MotionGrid::new(2, 2, &vectors).unwrap();
+        assert_eq!(
+            grid.average_speed(&bbox(0.0, 0.0, 32.0, 32.0), 32, 32),
+            Some(5.0)
+        );
+        assert_eq!(grid.average_speed(&bbox(40.0, 0.0, 8.0, 8.0), 32, 32), None);
+    }
+
+    #[test]
+    fn motion_vectors_of_meta() {
+        let mut vectors = vectors();
+        let mut meta = NvDsOpticalFlowMeta {
+            rows: 2,
+            cols: 4,
+            mv_size: 8 * 4,
+            frame_num: 3,
+            data: vectors.as_mut_ptr() as *mut c_void,
+            priv_data: std::ptr::null_mut(),
+            reserved: std::ptr::null_mut(),
+        };
+        let grid = meta.motion_vectors().unwrap();
+        assert_eq!((grid.rows(), grid.cols()), (2, 4));
+        assert_eq!(grid.vectors(), &vectors[..]);
+        // a buffer smaller than the grid
+        meta.mv_size = 7 * 4;
+        assert!(meta.motion_vectors().is_none());
+        meta.mv_size = 8 * 4;
+        meta.data = std::ptr::null_mut();
+        assert!(meta.motion_vectors().is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Payload meta consumed by nvmsgbroker
+use std::os::raw::c_void;
+
+use gst::glib;
+
+use crate::{imp, Error, NvDsBatchMeta};
+
+/// `NvDsPayload` of nvdsmeta_schema.h, which is not included in the bindings
+#[repr(C)]
+#[derive(Debug)]
+pub struct NvDsPayload {
+    pub payload: *mut c_void,
+    pub payload_size: u32,
+    pub component_id: u32,
+}
+
+impl NvDsPayload {
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.payload.is_null() {
+            &[]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(self.payload as *const u8, self.payload_size as usize)
+            }
+        }
+    }
+}
+
+/// Allocate a payload by glib to be freed by [`release_payload`]
+unsafe fn new_payload(data: &[u8], component_id: u32) -> *mut NvDsPayload {
+    let buf = glib::ffi::g_malloc(data.len());
+    std::ptr::copy_nonoverlapping(data.as_ptr(), buf as *mut u8, data.len());
+    let p = glib::ffi::g_malloc0(std::mem::size_of::<NvDsPayload>()) as *mut NvDsPayload;
+    (*p).payload = buf;
+    (*p).payload_size = data.len() as u32;
+    (*p).component_id = component_id;
+    p
+}
+
+/// `copy_func` of the user meta, called when the buffer is copied
+unsafe extern "C" fn copy_payload(
+    data: glib::ffi::gpointer,
+    _user_data: glib::ffi::gpointer,
+) -> glib::ffi::gpointer {
+    let user_meta = data as *mut imp::NvDsUserMeta;
+    let src = (*user_meta).user_meta_data as *const NvDsPayload;
+    if src.is_null() {
+        return std::ptr::null_mut();
+    }
+    new_payload((*src).as_bytes(), (*src).component_id) as glib::ffi::gpointer
+}
+
+/// `release_func` of the user meta, called when the meta returns to the pool
+unsafe extern "C" fn release_payload(data: glib::ffi::gpointer, _user_data: glib::ffi::gpointer) {
+    let user_meta = data as *mut imp::NvDsUserMeta;
+    let p = (*user_meta).user_meta_data as *mut NvDsPayload;
+    if !p.is_null() {
+        glib::ffi::g_free((*p).payload);
+        glib::ffi::g_free(p as glib::ffi::gpointer);
+    }
+    (*user_meta).user_meta_data = std::ptr::null_mut();
+}
+
+impl NvDsBatchMeta {
+    /// Attach a copy of `payload` to the frame of the source as `NVDS_PAYLOAD_META`
+    pub fn add_payload_meta(
+        &mut self,
+        source_id: u32,
+        payload: &[u8],
+        component_id: u32,
+    ) -> Result<(), Error> {
+        let frame = self.video_frame_ptr(source_id)?;
+        unsafe {
+            let user_meta = imp::nvds_acquire_user_meta_from_pool(&mut self.0);
+            if user_meta.is_null() {
+                return Err(Error::AcquireMeta);
+            }
+            (*user_meta).user_meta_data = new_payload(payload, component_id) as *mut c_void;
+            (*user_meta).base_meta.meta_type = imp::NvDsMetaType_NVDS_PAYLOAD_META;
+            (*user_meta).base_meta.copy_func = Some(copy_payload);
+            (*user_meta).base_meta.release_func = Some(release_payload);
+            imp::nvds_add_user_meta_to_frame(frame, user_meta);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_then_release() {
+        unsafe {
+            let mut src: imp::NvDsUserMeta = std::mem::zeroed();
+            src.user_meta_data = new_payload(b"{\"id\":1}", 3) as *mut c_void;
+            let data = &mut src as *mut imp::NvDsUserMeta as glib::ffi::gpointer;
+
+            let copy = copy_payload(data, std::ptr::null_mut()) as *mut NvDsPayload;
+            assert!(!copy.is_null());
+            assert_ne!(copy as *mut c_void, src.user_meta_data);
+            let original = &*(src.user_meta_data as *const NvDsPayload);
+            assert_ne!((*copy).payload, original.payload);
+            assert_eq!((*copy).as_bytes(), b"{\"id\":1}");
+            assert_eq!((*copy).component_id, 3);
+
+            release_payload(data, std::ptr::null_mut());
+            assert!(src.user_meta_data.is_null());
+            // a released meta is copied as null and released again without a double free
+            assert!(copy_payload(data, std::ptr::null_mut()).is_null());
+            release_payload(data, std::ptr::null_mut());
+
+            let mut copied: imp::NvDsUserMeta = std::mem::zeroed();
+            copied.user_meta_data = copy as *mut c_void;
+            release_payload(
+                &mut copied as *mut imp::NvDsUserMeta as glib::ffi::gpointer,
+                std::ptr::null_mut(),
+            );
+            assert!(copied.user_meta_data.is_null());
+        }
+    }
+}
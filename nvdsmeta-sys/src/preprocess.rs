@@ -0,0 +1,355 @@
+//! Batch meta attached by nvdspreprocess
+//!
+//! `GstNvDsPreProcessBatchMeta` of nvds_preprocess_meta.h is a C++ struct holding
+//! `std::vector` and `std::string`, which are read here by the libstdc++ layout
+//! used by DeepStream on Linux.
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+use crate::{
+    imp, nvlist, NvBbox_Coords, NvDsBatchMeta, NvDsClassifierMeta, NvDsFrameMeta, NvDsUserMeta,
+};
+
+/// `std::vector<T>` of libstdc++
+#[repr(C)]
+#[derive(Debug)]
+struct CxxVector<T> {
+    begin: *const T,
+    end: *const T,
+    end_of_storage: *const T,
+}
+
+impl<T> CxxVector<T> {
+    fn as_slice(&self) -> &[T] {
+        if self.begin.is_null() {
+            &[]
+        } else {
+            unsafe {
+                let len = self.end.offset_from(self.begin) as usize;
+                std::slice::from_raw_parts(self.begin, len)
+            }
+        }
+    }
+}
+
+/// `std::string` of libstdc++ with the C++11 ABI
+#[repr(C)]
+#[derive(Debug)]
+struct CxxString {
+    ptr: *const c_char,
+    len: usize,
+    local_buf: [u8; 16],
+}
+
+impl CxxString {
+    fn as_bytes(&self) -> &[u8] {
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+}
+
+// sizes of libstdc++ on 64-bit Linux, a mismatch means the layout is not the one read here
+const _: () =
+    assert!(std::mem::size_of::<CxxString>() == 32 && std::mem::size_of::<CxxVector<u8>>() == 24);
+
+/// Tensor prepared by nvdspreprocess for nvinfer with `input-tensor-meta=1`
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsPreProcessTensorMeta {
+    raw_tensor_buffer: *mut c_void,
+    tensor_shape: CxxVector<c_int>,
+    data_type: imp::NvDsDataType,
+    tensor_name: CxxString,
+    gpu_id: c_uint,
+    private_data: *mut c_void,
+    meta_id: c_uint,
+    maintain_aspect_ratio: c_int,
+    buffer_size: u64,
+}
+
+impl NvDsPreProcessTensorMeta {
+    /// Device memory of the tensor on `gpu_id`, not readable from the host
+    #[inline]
+    pub fn raw_tensor_buffer(&self) -> *const c_void {
+        self.raw_tensor_buffer
+    }
+    #[inline]
+    pub fn tensor_shape(&self) -> &[i32] {
+        self.tensor_shape.as_slice()
+    }
+    /// One of `NvDsDataType_*`
+    #[inline]
+    pub fn data_type(&self) -> imp::NvDsDataType {
+        self.data_type
+    }
+    #[inline]
+    pub fn tensor_name(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(self.tensor_name.as_bytes())
+    }
+    #[inline]
+    pub fn gpu_id(&self) -> u32 {
+        self.gpu_id
+    }
+    #[inline]
+    pub fn meta_id(&self) -> u32 {
+        self.meta_id
+    }
+    #[inline]
+    pub fn maintain_aspect_ratio(&self) -> bool {
+        self.maintain_aspect_ratio != 0
+    }
+    #[inline]
+    pub fn buffer_size(&self) -> u64 {
+        self.buffer_size
+    }
+}
+
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct NvDsRoiMeta(imp::NvDsRoiMeta);
+
+impl NvDsRoiMeta {
+    /// ROI in the frame coordinates
+    #[inline]
+    pub fn roi(&self) -> NvBbox_Coords {
+        let r = &self.0.roi;
+        NvBbox_Coords {
+            left: r.left,
+            top: r.top,
+            width: r.width,
+            height: r.height,
+        }
+    }
+    /// Frame which the ROI belongs to
+    #[inline]
+    pub fn frame_meta(&self) -> Option<&NvDsFrameMeta> {
+        if self.0.frame_meta.is_null() {
+            None
+        } else {
+            unsafe { Some(&*(self.0.frame_meta as *const NvDsFrameMeta)) }
+        }
+    }
+    #[inline]
+    pub fn scale_ratio_x(&self) -> f64 {
+        self.0.scale_ratio_x
+    }
+    #[inline]
+    pub fn scale_ratio_y(&self) -> f64 {
+        self.0.scale_ratio_y
+    }
+    #[inline]
+    pub fn offset_left(&self) -> f64 {
+        self.0.offset_left
+    }
+    #[inline]
+    pub fn offset_top(&self) -> f64 {
+        self.0.offset_top
+    }
+    /// results of classifiers operated on the ROI
+    pub fn classifier_meta_list(&self) -> nvlist::GListIter<'_, NvDsClassifierMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(
+                self.0.classifier_meta_list as *const gst::glib::ffi::GList,
+            )
+        }
+    }
+    pub fn user_meta_list(&self) -> nvlist::GListIter<'_, NvDsUserMeta> {
+        unsafe {
+            nvlist::GListIter::from_glib_none(
+                self.0.roi_user_meta_list as *const gst::glib::ffi::GList,
+            )
+        }
+    }
+
+    /// Map a point of the scaled ROI (network input) to the frame
+    #[inline]
+    pub fn to_frame(&self, x: f32, y: f32) -> (f32, f32) {
+        let r = &self.0.roi;
+        (
+            r.left + ((x as f64 - self.0.offset_left) / self.0.scale_ratio_x) as f32,
+            r.top + ((y as f64 - self.0.offset_top) / self.0.scale_ratio_y) as f32,
+        )
+    }
+
+    /// Map a box of the scaled ROI (network input) to the frame
+    pub fn bbox_to_frame(&self, b: &NvBbox_Coords) -> NvBbox_Coords {
+        let (left, top) = self.to_frame(b.left, b.top);
+        NvBbox_Coords {
+            left,
+            top,
+            width: (b.width as f64 / self.0.scale_ratio_x) as f32,
+            height: (b.height as f64 / self.0.scale_ratio_y) as f32,
+        }
+    }
+}
+
+/// `NVDS_PREPROCESS_BATCH_META` attached to the batch by nvdspreprocess
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsPreProcessBatchMeta {
+    target_unique_ids: CxxVector<u64>,
+    tensor_meta: *mut NvDsPreProcessTensorMeta,
+    roi_vector: CxxVector<NvDsRoiMeta>,
+    private_data: *mut c_void,
+}
+
+impl NvDsPreProcessBatchMeta {
+    /// gie-unique-id of nvinfer which consumes the tensor
+    #[inline]
+    pub fn target_unique_ids(&self) -> &[u64] {
+        self.target_unique_ids.as_slice()
+    }
+    #[inline]
+    pub fn tensor_meta(&self) -> Option<&NvDsPreProcessTensorMeta> {
+        if self.tensor_meta.is_null() {
+            None
+        } else {
+            unsafe { Some(&*self.tensor_meta) }
+        }
+    }
+    /// ROIs in the order of the batch of the tensor
+    #[inline]
+    pub fn roi_vector(&self) -> &[NvDsRoiMeta] {
+        self.roi_vector.as_slice()
+    }
+}
+
+impl NvDsUserMeta {
+    pub fn preprocess_batch_meta(&self) -> Option<&NvDsPreProcessBatchMeta> {
+        if self.meta_type() == imp::NvDsMetaType_NVDS_PREPROCESS_BATCH_META
+            && !self.0.user_meta_data.is_null()
+        {
+            unsafe { Some(&*(self.0.user_meta_data as *const NvDsPreProcessBatchMeta)) }
+        } else {
+            None
+        }
+    }
+}
+
+impl NvDsBatchMeta {
+    /// Preprocess meta of each nvdspreprocess in the pipeline
+    pub fn preprocess_batch_meta(&self) -> impl Iterator<Item = &NvDsPreProcessBatchMeta> {
+        self.user_meta_list()
+            .filter_map(NvDsUserMeta::preprocess_batch_meta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector<T>(v: &[T]) -> CxxVector<T> {
+        let range = v.as_ptr_range();
+        CxxVector {
+            begin: range.start,
+            end: range.end,
+            end_of_storage: range.end,
+        }
+    }
+
+    /// ROI at (100, 50) scaled by `scale` into the network input padded by `offset`
+    fn roi(scale: (f64, f64), offset: (f64, f64)) -> NvDsRoiMeta {
+        let mut m: imp::NvDsRoiMeta = unsafe { std::mem::zeroed() };
+        m.roi.left = 100.0;
+        m.roi.top = 50.0;
+        m.roi.width = 400.0;
+        m.roi.height = 300.0;
+        m.scale_ratio_x = scale.0;
+        m.scale_ratio_y = scale.1;
+        m.offset_left = offset.0;
+        m.offset_top = offset.1;
+        NvDsRoiMeta(m)
+    }
+
+    fn coords(b: &NvBbox_Coords) -> [f32; 4] {
+        [b.left, b.top, b.width, b.height]
+    }
+
+    #[test]
+    fn unscaled_roi_is_shifted() {
+        let r = roi((1.0, 1.0), (0.0, 0.0));
+        assert_eq!(coords(&r.roi()), [100.0, 50.0, 400.0, 300.0]);
+        assert_eq!(r.to_frame(0.0, 0.0), (100.0, 50.0));
+        assert_eq!(r.to_frame(40.0, 30.0), (140.0, 80.0));
+        let b = NvBbox_Coords {
+            left: 10.0,
+            top: 20.0,
+            width: 30.0,
+            height: 40.0,
+        };
+        assert_eq!(coords(&r.bbox_to_frame(&b)), [110.0, 70.0, 30.0, 40.0]);
+    }
+
+    #[test]
+    fn scaled_and_padded_roi() {
+        // 400 x 300 scaled to 200 x 75 with 10 and 5 pixels of padding
+        let r = roi((0.5, 0.25), (10.0, 5.0));
+        assert_eq!(r.to_frame(10.0, 5.0), (100.0, 50.0));
+        // (110 - 10) / 0.5 = 200, (80 - 5) / 0.25 = 300
+        assert_eq!(r.to_frame(110.0, 80.0), (300.0, 350.0));
+        // points in the padding fall outside of the ROI
+        assert_eq!(r.to_frame(0.0, 0.0), (80.0, 30.0));
+        let b = NvBbox_Coords {
+            left: 20.0,
+            top: 30.0,
+            width: 50.0,
+            height: 10.0,
+        };
+        assert_eq!(coords(&r.bbox_to_frame(&b)), [120.0, 150.0, 100.0, 40.0]);
+    }
+
+    #[test]
+    fn batch_meta_of_cxx_containers() {
+        let ids = [3u64, 5];
+        let rois = [roi((1.0, 1.0), (0.0, 0.0)), roi((0.5, 0.5), (0.0, 0.0))];
+        let shape = [2, 3, 224, 224];
+        let name = b"input_1:0";
+        let mut tensor = NvDsPreProcessTensorMeta {
+            raw_tensor_buffer: std::ptr::null_mut(),
+            tensor_shape: vector(&shape),
+            data_type: 0,
+            tensor_name: CxxString {
+                ptr: name.as_ptr() as *const c_char,
+                len: name.len(),
+                local_buf: [0; 16],
+            },
+            gpu_id: 0,
+            private_data: std::ptr::null_mut(),
+            meta_id: 1,
+            maintain_aspect_ratio: 1,
+            buffer_size: 2 * 3 * 224 * 224 * 4,
+        };
+        let batch = NvDsPreProcessBatchMeta {
+            target_unique_ids: vector(&ids),
+            tensor_meta: &mut tensor,
+            roi_vector: vector(&rois),
+            private_data: std::ptr::null_mut(),
+        };
+        assert_eq!(batch.target_unique_ids(), [3, 5]);
+        assert_eq!(batch.roi_vector().len(), 2);
+        assert_eq!(batch.roi_vector()[1].scale_ratio_x(), 0.5);
+        let t = batch.tensor_meta().unwrap();
+        assert_eq!(t.tensor_shape(), [2, 3, 224, 224]);
+        assert_eq!(t.tensor_name(), "input_1:0");
+        assert!(t.maintain_aspect_ratio());
+
+        // empty containers are null
+        let empty = NvDsPreProcessBatchMeta {
+            target_unique_ids: CxxVector {
+                begin: std::ptr::null(),
+                end: std::ptr::null(),
+                end_of_storage: std::ptr::null(),
+            },
+            tensor_meta: std::ptr::null_mut(),
+            roi_vector: vector(&[]),
+            private_data: std::ptr::null_mut(),
+        };
+        assert!(empty.target_unique_ids().is_empty());
+        assert!(empty.roi_vector().is_empty());
+        assert!(empty.tensor_meta().is_none());
+    }
+}
@@ -0,0 +1,365 @@
+//! Segmentation output meta attached by nvinfer with `network-type=2`
+//!
+//! `NvDsInferSegmentationMeta` of gstnvdsinfer.h, which is not included in the bindings.
+use std::os::raw::{c_int, c_uint, c_void};
+
+/// Class map value of pixels below `segmentation-threshold`
+pub const BACKGROUND_CLASS: i32 = -1;
+
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsInferSegmentationMeta {
+    classes: c_uint,
+    width: c_uint,
+    height: c_uint,
+    class_map: *mut c_int,
+    class_probabilities_map: *mut f32,
+    priv_data: *mut c_void,
+}
+
+impl NvDsInferSegmentationMeta {
+    #[inline]
+    pub fn classes(&self) -> u32 {
+        self.classes
+    }
+    #[inline]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    #[inline]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Class id of every pixel in row-major order, [`BACKGROUND_CLASS`] for no class
+    pub fn class_map(&self) -> &[i32] {
+        if self.class_map.is_null() {
+            &[]
+        } else {
+            unsafe {
+                std::slice::from_raw_parts(
+                    self.class_map,
+                    self.width as usize * self.height as usize,
+                )
+            }
+        }
+    }
+
+    /// Rows of [`NvDsInferSegmentationMeta::class_map`]
+    pub fn class_map_rows(&self) -> std::slice::ChunksExact<'_, i32> {
+        self.class_map().chunks_exact((self.width as usize).max(1))
+    }
+
+    #[inline]
+    pub fn class_at(&self, x: u32, y: u32) -> Option<i32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.class_map()
+            .get(y as usize * self.width as usize + x as usize)
+            .copied()
+    }
+
+    /// Probabilities of all classes, `classes` planes of `height` x `width`
+    pub fn class_probabilities_map(&self) -> Option<&[f32]> {
+        if self.class_probabilities_map.is_null() {
+            None
+        } else {
+            unsafe {
+                Some(std::slice::from_raw_parts(
+                    self.class_probabilities_map,
+                    self.classes as usize * self.width as usize * self.height as usize,
+                ))
+            }
+        }
+    }
+
+    /// Probability plane of the class
+    pub fn class_probabilities(&self, class_id: u32) -> Option<&[f32]> {
+        if class_id >= self.classes {
+            return None;
+        }
+        let plane = self.width as usize * self.height as usize;
+        let start = class_id as usize * plane;
+        self.class_probabilities_map()
+            .map(|m| &m[start..start + plane])
+    }
+
+    /// Number of pixels by class id, indexed by class id
+    ///
+    /// Background pixels and ids out of `classes` are not counted.
+    pub fn class_areas(&self) -> Vec<u64> {
+        let mut areas = vec![0; self.classes as usize];
+        for &c in self.class_map() {
+            if let Some(a) = usize::try_from(c).ok().and_then(|c| areas.get_mut(c)) {
+                *a += 1;
+            }
+        }
+        areas
+    }
+
+    /// Number of 8-connected regions by class id, indexed by class id
+    ///
+    /// Counted in a single pass over the class map, unlike tracing
+    /// [`NvDsInferSegmentationMeta::contours`] of every class.
+    pub fn class_regions(&self) -> Vec<u32> {
+        class_regions(self.class_map(), self.width, self.height, self.classes)
+    }
+
+    /// Outer contours of 8-connected regions of the class
+    ///
+    /// Each contour is a closed polygon of boundary pixel coordinates `(x, y)` traced
+    /// clockwise from the top-left pixel of the region. Holes are not traced.
+    pub fn contours(&self, class_id: i32) -> Vec<Vec<(u32, u32)>> {
+        contours(self.class_map(), self.width, self.height, class_id)
+    }
+}
+
+/// Neighbors clockwise from west in image coordinates
+const NEIGHBORS: [(i64, i64); 8] = [
+    (-1, 0),
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+];
+
+fn contours(map: &[i32], width: u32, height: u32, class_id: i32) -> Vec<Vec<(u32, u32)>> {
+    let (w, h) = (width as i64, height as i64);
+    if map.len() < (w * h) as usize {
+        return vec![];
+    }
+    // region index + 1 by pixel, 0 for other classes
+    let mut regions = vec![0u32; map.len()];
+    let mut result = vec![];
+    let mut stack = vec![];
+    for start in 0..map.len() {
+        if map[start] != class_id || regions[start] != 0 {
+            continue;
+        }
+        let region = result.len() as u32 + 1;
+        regions[start] = region;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i as i64 % w, i as i64 / w);
+            for (dx, dy) in NEIGHBORS {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                    continue;
+                }
+                let n = (ny * w + nx) as usize;
+                if map[n] == class_id && regions[n] == 0 {
+                    regions[n] = region;
+                    stack.push(n);
+                }
+            }
+        }
+        result.push(trace(&regions, w, h, start, region));
+    }
+    result
+}
+
+/// Flood fill from every unvisited pixel, so each pixel is pushed once
+fn class_regions(map: &[i32], width: u32, height: u32, classes: u32) -> Vec<u32> {
+    let (w, h) = (width as i64, height as i64);
+    let mut counts = vec![0; classes as usize];
+    if map.len() < (w * h) as usize {
+        return counts;
+    }
+    let mut visited = vec![false; map.len()];
+    let mut stack = vec![];
+    for start in 0..map.len() {
+        let class_id = map[start];
+        if visited[start] {
+            continue;
+        }
+        // background and ids out of `classes` are not counted
+        match usize::try_from(class_id)
+            .ok()
+            .and_then(|c| counts.get_mut(c))
+        {
+            Some(count) => *count += 1,
+            None => continue,
+        }
+        visited[start] = true;
+        stack.push(start);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i as i64 % w, i as i64 / w);
+            for (dx, dy) in NEIGHBORS {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                    continue;
+                }
+                let n = (ny * w + nx) as usize;
+                if map[n] == class_id && !visited[n] {
+                    visited[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+    }
+    counts
+}
+
+/// Moore-neighbor tracing which stops when the first move is repeated
+fn trace(regions: &[u32], w: i64, h: i64, start: usize, region: u32) -> Vec<(u32, u32)> {
+    let inside = |x: i64, y: i64| {
+        x >= 0 && y >= 0 && x < w && y < h && regions[(y * w + x) as usize] == region
+    };
+    let s = (start as i64 % w, start as i64 / w);
+    let mut contour = vec![(s.0 as u32, s.1 as u32)];
+    // the start is the first pixel of the region in raster order, so its west is outside
+    let (mut cur, mut back) = (s, 0usize);
+    let mut second = None;
+    // every boundary pixel is visited from at most 4 directions
+    for _ in 0..regions.len() * 4 + 8 {
+        let next = (1..=8).map(|k| (back + k) % 8).find(|&d| {
+            let (dx, dy) = NEIGHBORS[d];
+            inside(cur.0 + dx, cur.1 + dy)
+        });
+        let d = match next {
+            Some(d) => d,
+            // single pixel region
+            None => break,
+        };
+        let (dx, dy) = NEIGHBORS[d];
+        let p = (cur.0 + dx, cur.1 + dy);
+        // stop when the first move is repeated
+        if cur == s && second == Some(p) {
+            contour.pop();
+            break;
+        }
+        second.get_or_insert(p);
+        // the neighbor examined before p becomes the backtrack of p
+        let (bx, by) = NEIGHBORS[(d + 7) % 8];
+        let b = (cur.0 + bx - p.0, cur.1 + by - p.1);
+        back = NEIGHBORS
+            .iter()
+            .position(|&n| n == b)
+            .expect("backtrack is a neighbor");
+        cur = p;
+        contour.push((p.0 as u32, p.1 as u32));
+    }
+    contour
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Class map from rows of `.` for background and digits for class ids
+    fn map(rows: &[&str]) -> (Vec<i32>, u32, u32) {
+        let map = rows
+            .iter()
+            .flat_map(|r| r.chars())
+            .map(|c| c.to_digit(10).map_or(BACKGROUND_CLASS, |d| d as i32))
+            .collect();
+        (map, rows[0].len() as u32, rows.len() as u32)
+    }
+
+    fn contours_of(rows: &[&str], class_id: i32) -> Vec<Vec<(u32, u32)>> {
+        let (map, w, h) = map(rows);
+        contours(&map, w, h, class_id)
+    }
+
+    #[test]
+    fn single_pixel() {
+        assert_eq!(contours_of(&["...", ".1.", "..."], 1), [[(1, 1)]]);
+    }
+
+    #[test]
+    fn square_is_traced_clockwise() {
+        let rows = ["....", ".11.", ".11.", "...."];
+        assert_eq!(contours_of(&rows, 1), [[(1, 1), (2, 1), (2, 2), (1, 2)]]);
+    }
+
+    #[test]
+    fn holes_are_not_traced() {
+        let rows = ["111", "1.1", "111"];
+        assert_eq!(
+            contours_of(&rows, 1),
+            [[
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (2, 1),
+                (2, 2),
+                (1, 2),
+                (0, 2),
+                (0, 1)
+            ]]
+        );
+    }
+
+    #[test]
+    fn thin_regions_go_out_and_back() {
+        assert_eq!(contours_of(&["111"], 1), [[(0, 0), (1, 0), (2, 0), (1, 0)]]);
+        // diagonal pixels are 8-connected
+        assert_eq!(contours_of(&["1.", ".1"], 1), [[(0, 0), (1, 1)]]);
+    }
+
+    #[test]
+    fn regions_in_raster_order() {
+        let rows = ["1..2", "1..2", "..22"];
+        assert_eq!(contours_of(&rows, 1), [[(0, 0), (0, 1)]]);
+        // (3, 1) is passed again on the way back from the diagonal neighbor (2, 2)
+        assert_eq!(
+            contours_of(&rows, 2),
+            [[(3, 0), (3, 1), (3, 2), (2, 2), (3, 1)]]
+        );
+        let rows = ["1.1", "...", "1.."];
+        assert_eq!(
+            contours_of(&rows, 1),
+            [vec![(0, 0)], vec![(2, 0)], vec![(0, 2)]]
+        );
+        assert!(contours_of(&rows, 3).is_empty());
+        // a map smaller than the size is ignored
+        assert!(contours(&[1, 1], 2, 2, 1).is_empty());
+    }
+
+    #[test]
+    fn regions_of_all_classes() {
+        let (map, w, h) = map(&["1..2", "1..2", "..22", "2.1.", ".21."]);
+        assert_eq!(class_regions(&map, w, h, 4), [0, 2, 2, 0]);
+        for c in 0..4 {
+            assert_eq!(
+                contours(&map, w, h, c).len(),
+                class_regions(&map, w, h, 4)[c as usize] as usize
+            );
+        }
+        // ids out of classes are not counted
+        assert_eq!(class_regions(&map, w, h, 2), [0, 2]);
+        assert_eq!(class_regions(&[1, 1], 2, 2, 2), [0, 0]);
+    }
+
+    #[test]
+    fn meta_accessors() {
+        let (mut class_map, width, height) = map(&["0.1", "117"]);
+        let mut probabilities = (0..12).map(|v| v as f32).collect::<Vec<_>>();
+        let meta = NvDsInferSegmentationMeta {
+            classes: 2,
+            width,
+            height,
+            class_map: class_map.as_mut_ptr(),
+            class_probabilities_map: probabilities.as_mut_ptr(),
+            priv_data: std::ptr::null_mut(),
+        };
+        assert_eq!(meta.class_at(2, 0), Some(1));
+        assert_eq!(meta.class_at(1, 0), Some(BACKGROUND_CLASS));
+        assert_eq!(meta.class_at(3, 0), None);
+        assert_eq!(meta.class_map_rows().nth(1), Some(&[1, 1, 7][..]));
+        // background and the out of range class 7 are not counted
+        assert_eq!(meta.class_areas(), [1, 3]);
+        assert_eq!(
+            meta.class_probabilities(1),
+            Some(&[6.0, 7.0, 8.0, 9.0, 10.0, 11.0][..])
+        );
+        assert_eq!(meta.class_probabilities(2), None);
+        assert_eq!(meta.contours(1), [[(2, 0), (1, 1), (0, 1), (1, 1)]]);
+        assert_eq!(meta.class_regions(), [1, 1]);
+    }
+}
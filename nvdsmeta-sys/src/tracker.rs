@@ -0,0 +1,160 @@
+//! Past-frame meta attached by nvtracker with `enable-past-frame=1`
+//!
+//! Low-level trackers such as NvDCF keep shadow tracks of objects which are not
+//! reported in some frames, and report them later in `NVDS_TRACKER_PAST_FRAME_META`.
+//! Structs of nvds_tracker_meta.h of DeepStream 6.0 - 6.2, which are not included in
+//! the bindings.
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+
+use crate::{imp, NvBbox_Coords, NvDsBatchMeta, NvDsUserMeta};
+
+/// Slice of a C array, empty if null
+unsafe fn slice<'a, T>(ptr: *const T, len: u32) -> &'a [T] {
+    if ptr.is_null() {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len as usize)
+    }
+}
+
+/// Position of an object in a past frame
+#[repr(C)]
+#[derive(Debug)]
+pub struct NvDsPastFrameObj {
+    frame_num: u32,
+    t_bbox: imp::NvOSD_RectParams,
+    confidence: f32,
+    age: f32,
+}
+
+impl NvDsPastFrameObj {
+    #[inline]
+    pub fn frame_num(&self) -> u32 {
+        self.frame_num
+    }
+    #[inline]
+    pub fn bbox(&self) -> NvBbox_Coords {
+        NvBbox_Coords {
+            left: self.t_bbox.left,
+            top: self.t_bbox.top,
+            width: self.t_bbox.width,
+            height: self.t_bbox.height,
+        }
+    }
+    #[inline]
+    pub fn confidence(&self) -> f32 {
+        self.confidence
+    }
+    /// Frames since the object is tracked
+    #[inline]
+    pub fn age(&self) -> f32 {
+        self.age
+    }
+}
+
+/// Past positions of an object
+#[repr(C)]
+#[derive(Debug)]
+pub struct NvDsPastFrameObjList {
+    list: *mut NvDsPastFrameObj,
+    num_obj: u32,
+    unique_id: u64,
+    class_id: u16,
+    obj_label: [c_char; imp::MAX_LABEL_SIZE as usize],
+}
+
+impl NvDsPastFrameObjList {
+    #[inline]
+    pub fn objects(&self) -> &[NvDsPastFrameObj] {
+        unsafe { slice(self.list, self.num_obj) }
+    }
+    /// `object_id` of the object meta
+    #[inline]
+    pub fn unique_id(&self) -> u64 {
+        self.unique_id
+    }
+    #[inline]
+    pub fn class_id(&self) -> u16 {
+        self.class_id
+    }
+    #[inline]
+    pub fn label(&self) -> &CStr {
+        let label = &self.obj_label;
+        if label.contains(&0) {
+            unsafe { CStr::from_ptr(label.as_ptr()) }
+        } else {
+            Default::default()
+        }
+    }
+}
+
+/// Past-frame objects of a stream
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsPastFrameObjStream {
+    list: *mut NvDsPastFrameObjList,
+    stream_id: u32,
+    surface_stream_id: u64,
+    num_allocated: u32,
+    num_filled: u32,
+}
+
+impl NvDsPastFrameObjStream {
+    #[inline]
+    pub fn object_lists(&self) -> &[NvDsPastFrameObjList] {
+        unsafe { slice(self.list, self.num_filled) }
+    }
+    /// `source_id` of the frame meta
+    #[inline]
+    pub fn stream_id(&self) -> u32 {
+        self.stream_id
+    }
+    #[inline]
+    pub fn surface_stream_id(&self) -> u64 {
+        self.surface_stream_id
+    }
+}
+
+/// `NVDS_TRACKER_PAST_FRAME_META` attached to the batch
+#[repr(C)]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct NvDsPastFrameObjBatch {
+    list: *mut NvDsPastFrameObjStream,
+    num_allocated: u32,
+    num_filled: u32,
+    priv_data: *mut c_void,
+}
+
+impl NvDsPastFrameObjBatch {
+    #[inline]
+    pub fn streams(&self) -> &[NvDsPastFrameObjStream] {
+        unsafe { slice(self.list, self.num_filled) }
+    }
+    /// Past-frame objects of the source
+    pub fn stream(&self, source_id: u32) -> Option<&NvDsPastFrameObjStream> {
+        self.streams().iter().find(|s| s.stream_id() == source_id)
+    }
+}
+
+impl NvDsUserMeta {
+    pub fn past_frame_meta(&self) -> Option<&NvDsPastFrameObjBatch> {
+        if self.meta_type() == imp::NvDsMetaType_NVDS_TRACKER_PAST_FRAME_META
+            && !self.0.user_meta_data.is_null()
+        {
+            unsafe { Some(&*(self.0.user_meta_data as *const NvDsPastFrameObjBatch)) }
+        } else {
+            None
+        }
+    }
+}
+
+impl NvDsBatchMeta {
+    /// Past-frame objects reported by nvtracker in this batch
+    pub fn past_frame_meta(&self) -> impl Iterator<Item = &NvDsPastFrameObjBatch> {
+        self.user_meta_list()
+            .filter_map(NvDsUserMeta::past_frame_meta)
+    }
+}
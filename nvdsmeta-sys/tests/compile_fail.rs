@@ -0,0 +1,6 @@
+//! Views of the metadata borrow the buffer, so misuse is rejected by the borrow checker
+#[test]
+fn compile_fail() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/compile_fail/*.rs");
+}
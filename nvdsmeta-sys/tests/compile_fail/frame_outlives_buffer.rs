@@ -0,0 +1,9 @@
+// references to frame metadata cannot outlive the buffer
+fn escape(buffer: gst::Buffer) -> &'static nvdsmeta_sys::NvDsFrameMeta {
+    let meta = nvdsmeta_sys::NvDsMeta::from_buffer(&buffer).unwrap();
+    meta.batch_meta().unwrap().frame_meta_list().next().unwrap()
+}
+
+fn main() {
+    let _ = escape;
+}
@@ -0,0 +1,12 @@
+// mutable access cannot coexist with shared views of the same buffer
+fn alias(buffer: &mut gst::BufferRef) {
+    let meta = nvdsmeta_sys::NvDsMeta::from_buffer(buffer).unwrap();
+    let frames = meta.batch_meta().unwrap().frame_meta_list();
+    let mut meta_mut = nvdsmeta_sys::NvDsMeta::from_buffer_mut(buffer).unwrap();
+    meta_mut.batch_meta_mut().unwrap();
+    drop(frames);
+}
+
+fn main() {
+    let _ = alias;
+}